@@ -0,0 +1,11 @@
+-- Add migration script here
+-- 记录每个订阅者的投递结果
+CREATE TABLE issue_delivery_log(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    status TEXT NOT NULL,
+    error_code INTEGER NULL,
+    error_message TEXT NULL,
+    attempted_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- 确认令牌在 expires_at 之后失效,由后台任务定期清理
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at timestamptz NULL;
+
+-- 已有的令牌从迁移时开始计算有效期
+UPDATE subscription_tokens SET expires_at = created_at + interval '24 hours';
+
+ALTER TABLE subscription_tokens ALTER COLUMN expires_at SET NOT NULL;
+
+CREATE INDEX subscription_tokens_expires_at_idx ON subscription_tokens (expires_at);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- 令牌只保存 SHA3-256 的十六进制摘要,与应用中的 hash_subscription_token 一致
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+ALTER TABLE subscription_tokens RENAME COLUMN subscription_token TO subscription_token_hash;
+
+UPDATE subscription_tokens
+SET subscription_token_hash = encode(digest(subscription_token_hash, 'sha3-256'), 'hex');
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- 服务端保存的登录会话,cookie 里只有会话 id,这里保存它的摘要
+CREATE TABLE sessions(
+    session_id_hash TEXT NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (session_id_hash)
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- 已有的用户都可以发布,迁移为 owner;之后创建用户必须指定角色
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'viewer'));
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
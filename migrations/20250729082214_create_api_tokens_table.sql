@@ -0,0 +1,15 @@
+-- Add migration script here
+-- 给自动化脚本使用的 API 令牌,和会话一样只保存摘要
+CREATE TABLE api_tokens(
+    api_token_id uuid NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz,
+    last_used_at timestamptz,
+    revoked_at timestamptz,
+    PRIMARY KEY (api_token_id),
+    UNIQUE (user_id, name)
+);
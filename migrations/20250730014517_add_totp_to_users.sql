@@ -0,0 +1,15 @@
+-- Add migration script here
+-- 可选的 TOTP 两步验证
+-- 校验时需要原始密钥,所以这里不能只保存摘要
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamptz;
+-- 最后一次使用的时间步,防止同一个验证码被重放
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- 丢失验证器时使用的一次性恢复码,只保存摘要
+CREATE TABLE totp_recovery_codes(
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at timestamptz,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- 登录失败计数,分别按用户名和客户端 IP 记录
+-- 不存在的用户名也会记录,避免通过限流行为判断用户名是否存在
+CREATE TABLE login_failures(
+    kind TEXT NOT NULL CHECK (kind IN ('username', 'ip')),
+    key TEXT NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failed_at timestamptz NOT NULL,
+    locked_until timestamptz,
+    PRIMARY KEY (kind, key)
+);
+
+CREATE INDEX login_failures_last_failed_at_idx ON login_failures (last_failed_at);
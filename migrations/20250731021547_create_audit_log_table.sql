@@ -0,0 +1,26 @@
+-- Add migration script here
+-- 管理操作的审计日志,只允许追加
+-- user_id 不引用 users,删除用户后仍然保留记录;命令行执行的操作没有 user_id
+CREATE TABLE audit_log(
+    audit_log_id BIGINT GENERATED ALWAYS AS IDENTITY,
+    user_id uuid,
+    action TEXT NOT NULL,
+    target TEXT,
+    request_id TEXT,
+    ip TEXT,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (audit_log_id)
+);
+
+CREATE INDEX audit_log_user_id_idx ON audit_log (user_id);
+CREATE INDEX audit_log_action_idx ON audit_log (action);
+
+CREATE FUNCTION reject_audit_log_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION reject_audit_log_changes();
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- 找回密码时把重置链接发到这个地址,没有设置的用户无法自助重置
+ALTER TABLE users ADD COLUMN email TEXT UNIQUE;
+
+-- 重置令牌只保存摘要,使用一次后删除
+CREATE TABLE password_reset_tokens(
+    token_hash TEXT NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (token_hash)
+);
+
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
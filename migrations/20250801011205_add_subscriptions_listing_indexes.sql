@@ -0,0 +1,6 @@
+-- Add migration script here
+-- 后台订阅者列表按这些列做键集分页,id 用来区分相同的值
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
+CREATE INDEX subscriptions_name_id_idx ON subscriptions (name, id);
+CREATE INDEX subscriptions_email_id_idx ON subscriptions (email, id);
+CREATE INDEX subscriptions_status_idx ON subscriptions (status);
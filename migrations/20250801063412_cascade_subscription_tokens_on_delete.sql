@@ -0,0 +1,6 @@
+-- Add migration script here
+-- 删除订阅者时一并删除它的确认令牌
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscription_id_fkey,
+    ADD CONSTRAINT subscription_tokens_subscription_id_fkey
+        FOREIGN KEY (subscription_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- 导入时跳过确认的订阅者,记录同意订阅的来源(例如原来的邮件系统)
+ALTER TABLE subscriptions ADD COLUMN consent_source TEXT;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- 被擦除的订阅者只留下这条记录,不包含任何能识别本人的数据
+CREATE TABLE subscriber_erasures(
+    subscriber_id uuid NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    erased_at timestamptz NOT NULL,
+    requested_by TEXT NOT NULL CHECK (requested_by IN ('subscriber', 'admin')),
+    PRIMARY KEY (subscriber_id)
+);
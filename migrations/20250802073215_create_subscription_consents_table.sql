@@ -0,0 +1,22 @@
+-- Add migration script here
+-- 订阅和确认时的同意证据,只追加不修改
+-- 来源和隐私政策版本在导入的订阅者或历史数据上可能未知
+CREATE TABLE subscription_consents(
+    consent_id BIGINT GENERATED ALWAYS AS IDENTITY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('subscribe', 'confirm', 'import')),
+    source TEXT,
+    privacy_policy_version TEXT,
+    ip TEXT,
+    user_agent TEXT,
+    recorded_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (consent_id)
+);
+
+CREATE INDEX subscription_consents_subscriber_id_idx ON subscription_consents (subscriber_id);
+
+-- 之前直接确认导入的订阅者只在 consent_source 里留了来源
+INSERT INTO subscription_consents (subscriber_id, kind, source, recorded_at)
+SELECT id, 'import', consent_source, subscribed_at
+FROM subscriptions
+WHERE consent_source IS NOT NULL;
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- 编辑可以保存草稿,发布仍然只有 owner 可以
+CREATE TABLE newsletter_drafts(
+    newsletter_draft_id uuid NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    created_by uuid REFERENCES users (user_id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_draft_id)
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- 暂时性的失败留在队列里,等到 execute_after 之后再试
+-- worker 取走任务时也会把 execute_after 往后推,作为租约,其他实例不会重复取走
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+CREATE INDEX issue_delivery_queue_execute_after_idx ON issue_delivery_queue (execute_after);
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- 后台删除订阅者后仍要能证明当初的同意,记录里保留邮箱,订阅者 id 置空
+-- 只有数据擦除才会删除同意记录
+ALTER TABLE subscription_consents ADD COLUMN email TEXT;
+UPDATE subscription_consents c
+SET email = s.email
+FROM subscriptions s
+WHERE s.id = c.subscriber_id;
+ALTER TABLE subscription_consents ALTER COLUMN email SET NOT NULL;
+
+ALTER TABLE subscription_consents ALTER COLUMN subscriber_id DROP NOT NULL;
+ALTER TABLE subscription_consents DROP CONSTRAINT subscription_consents_subscriber_id_fkey;
+ALTER TABLE subscription_consents
+    ADD CONSTRAINT subscription_consents_subscriber_id_fkey
+    FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE SET NULL;
+
+CREATE INDEX subscription_consents_email_idx ON subscription_consents (email);
+
+-- 擦除时删除了多少条同意记录
+ALTER TABLE subscriber_erasures ADD COLUMN consents_erased INTEGER NOT NULL DEFAULT 0;
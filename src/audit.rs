@@ -0,0 +1,168 @@
+use std::future::{Ready, ready};
+
+use actix_web::{FromRequest, HttpMessage, HttpRequest, dev::Payload};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use tracing_actix_web::RequestId;
+use uuid::Uuid;
+
+use crate::{authentication::UserId, client_ip::client_ip};
+
+/// 审计日志里记录的操作
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Login,
+    Logout,
+    ChangePassword,
+    PublishNewsletter,
+    DraftNewsletter,
+    CreateApiToken,
+    RevokeApiToken,
+    EnableTotp,
+    DisableTotp,
+    CreateUser,
+    DeleteUser,
+    ResetPassword,
+    SetRole,
+    SetEmail,
+    ConfirmSubscriber,
+    UnsubscribeSubscriber,
+    DeleteSubscriber,
+    ImportSubscribers,
+    ExportSubscribers,
+    ExportSubscriberData,
+    EraseSubscriber,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "session.login",
+            AuditAction::Logout => "session.logout",
+            AuditAction::ChangePassword => "user.change_password",
+            AuditAction::PublishNewsletter => "newsletter.publish",
+            AuditAction::DraftNewsletter => "newsletter.draft",
+            AuditAction::CreateApiToken => "api_token.create",
+            AuditAction::RevokeApiToken => "api_token.revoke",
+            AuditAction::EnableTotp => "totp.enable",
+            AuditAction::DisableTotp => "totp.disable",
+            AuditAction::CreateUser => "user.create",
+            AuditAction::DeleteUser => "user.delete",
+            AuditAction::ResetPassword => "user.reset_password",
+            AuditAction::SetRole => "user.set_role",
+            AuditAction::SetEmail => "user.set_email",
+            AuditAction::ConfirmSubscriber => "subscriber.confirm",
+            AuditAction::UnsubscribeSubscriber => "subscriber.unsubscribe",
+            AuditAction::DeleteSubscriber => "subscriber.delete",
+            AuditAction::ImportSubscribers => "subscriber.import",
+            AuditAction::ExportSubscribers => "subscriber.export",
+            AuditAction::ExportSubscriberData => "subscriber.export_data",
+            AuditAction::EraseSubscriber => "subscriber.erase",
+        }
+    }
+}
+
+/// 谁在什么请求里执行了操作
+/// 在 `/admin` 下作为提取器使用时会带上 `reject_anonymous_users` 认证的用户
+#[derive(Clone, Debug, Default)]
+pub struct AuditContext {
+    pub user_id: Option<Uuid>,
+    pub request_id: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl AuditContext {
+    /// 命令行执行的操作,没有用户和请求
+    pub fn cli() -> Self {
+        Self::default()
+    }
+
+    pub fn for_request(req: &HttpRequest) -> Self {
+        let extensions = req.extensions();
+        AuditContext {
+            user_id: extensions.get::<UserId>().map(|user_id| **user_id),
+            request_id: extensions.get::<RequestId>().map(|id| id.to_string()),
+            ip: client_ip(req).map(|ip| ip.to_string()),
+        }
+    }
+
+    /// 登录时用户还不在请求的 extensions 里,需要手动指定
+    pub fn with_user(mut self, user_id: Uuid) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    /// 在调用方的事务(或连接池)中追加一条记录
+    #[tracing::instrument(name = "Record an audit log entry", skip(self, executor))]
+    pub async fn record(
+        &self,
+        executor: impl PgExecutor<'_>,
+        action: AuditAction,
+        target: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_log (user_id, action, target, request_id, ip)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            self.user_id,
+            action.as_str(),
+            target,
+            self.request_id,
+            self.ip
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+impl FromRequest for AuditContext {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(AuditContext::for_request(req)))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub audit_log_id: i64,
+    pub user_id: Option<Uuid>,
+    pub action: String,
+    pub target: Option<String>,
+    pub request_id: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 按时间倒序分页查询,`before` 是上一页最后一条记录的 id
+#[tracing::instrument(name = "Query the audit log", skip(pool))]
+pub async fn query_audit_log(
+    pool: &PgPool,
+    before: Option<i64>,
+    limit: i64,
+    user_id: Option<Uuid>,
+    action: Option<&str>,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT audit_log_id, user_id, action, target, request_id, ip, created_at
+        FROM audit_log
+        WHERE ($1::bigint IS NULL OR audit_log_id < $1)
+            AND ($2::uuid IS NULL OR user_id = $2)
+            AND ($3::text IS NULL OR action = $3)
+        ORDER BY audit_log_id DESC
+        LIMIT $4
+        "#,
+        before,
+        user_id,
+        action,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,287 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::Permission;
+
+/// 所有令牌的前缀,方便在日志和代码仓库里识别泄露的令牌
+const API_TOKEN_PREFIX: &str = "z2p_";
+
+/// API 令牌的授权范围,令牌只能使用范围内、且用户角色允许的权限
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "newsletters:publish")]
+    NewslettersPublish,
+    #[serde(rename = "subscribers:read")]
+    SubscribersRead,
+    #[serde(rename = "subscribers:write")]
+    SubscribersWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::NewslettersPublish => "newsletters:publish",
+            Scope::SubscribersRead => "subscribers:read",
+            Scope::SubscribersWrite => "subscribers:write",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "newsletters:publish" => Ok(Scope::NewslettersPublish),
+            "subscribers:read" => Ok(Scope::SubscribersRead),
+            "subscribers:write" => Ok(Scope::SubscribersWrite),
+            other => Err(format!("{other} is not a valid API token scope.")),
+        }
+    }
+
+    pub fn grants(&self, permission: Permission) -> bool {
+        match self {
+            Scope::NewslettersPublish => {
+                matches!(
+                    permission,
+                    Permission::DraftIssue | Permission::PublishIssue
+                )
+            }
+            Scope::SubscribersRead => matches!(permission, Permission::ReadSubscribers),
+            Scope::SubscribersWrite => matches!(permission, Permission::ManageSubscribers),
+        }
+    }
+}
+
+/// 列表里展示的令牌信息,令牌本身只在创建时返回一次
+#[derive(Debug, Serialize)]
+pub struct ApiToken {
+    pub api_token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// 通过校验的令牌属于哪个用户,可以使用哪些范围
+pub struct ApiTokenGrant {
+    pub user_id: Uuid,
+    pub scopes: Vec<Scope>,
+}
+
+fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha3_256::digest(token.as_bytes()))
+}
+
+fn generate_api_token() -> SecretString {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    SecretString::from(format!("{API_TOKEN_PREFIX}{random}"))
+}
+
+fn parse_scopes(scopes: Vec<String>) -> Result<Vec<Scope>, anyhow::Error> {
+    scopes
+        .iter()
+        .map(|s| Scope::parse(s).map_err(anyhow::Error::msg))
+        .collect()
+}
+
+/// 从 `Authorization: Bearer ...` 头中取出令牌
+/// 没有这个头或者使用其他认证方式时返回 `None`
+pub fn bearer_token(headers: &HeaderMap) -> Result<Option<SecretString>, anyhow::Error> {
+    let Some(header_value) = headers.get("Authorization") else {
+        return Ok(None);
+    };
+    let header_value = header_value
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let Some(token) = header_value.strip_prefix("Bearer ") else {
+        return Ok(None);
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        anyhow::bail!("A token must be provided in 'Bearer' auth.");
+    }
+    Ok(Some(SecretString::from(token)))
+}
+
+/// 创建令牌,返回令牌 id 和只展示一次的令牌
+/// 名称在同一个用户下唯一,重名时返回 `None`
+#[tracing::instrument(name = "Create an API token", skip(pool))]
+pub async fn create_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[Scope],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Option<(Uuid, SecretString)>, sqlx::Error> {
+    let api_token_id = Uuid::new_v4();
+    let token = generate_api_token();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (
+            api_token_id, user_id, name, token_hash, scopes, created_at, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now(), $6)
+        ON CONFLICT (user_id, name) DO NOTHING
+        "#,
+        api_token_id,
+        user_id,
+        name,
+        hash_api_token(token.expose_secret()),
+        &scopes,
+        expires_at
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok((n_inserted > 0).then_some((api_token_id, token)))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, anyhow::Error> {
+    sqlx::query!(
+        r#"
+        SELECT api_token_id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at, name
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list API tokens")?
+    .into_iter()
+    .map(|row| {
+        Ok(ApiToken {
+            api_token_id: row.api_token_id,
+            name: row.name,
+            scopes: parse_scopes(row.scopes)?,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+        })
+    })
+    .collect()
+}
+
+/// 吊销令牌,令牌不存在或不属于这个用户时返回 `false`
+#[tracing::instrument(name = "Revoke an API token", skip(pool))]
+pub async fn revoke_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    api_token_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE api_tokens SET revoked_at = COALESCE(revoked_at, now())
+        WHERE api_token_id = $1 AND user_id = $2
+        "#,
+        api_token_id,
+        user_id
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+/// 校验令牌并记录最后使用时间
+/// 令牌不存在、已吊销或已过期时返回 `None`
+#[tracing::instrument(name = "Validate an API token", skip_all)]
+pub async fn validate_api_token(
+    pool: &PgPool,
+    token: &SecretString,
+) -> Result<Option<ApiTokenGrant>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens SET last_used_at = now()
+        WHERE token_hash = $1
+            AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > now())
+        RETURNING user_id, scopes
+        "#,
+        hash_api_token(token.expose_secret())
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the API token")?;
+    row.map(|row| {
+        Ok(ApiTokenGrant {
+            user_id: row.user_id,
+            scopes: parse_scopes(row.scopes)?,
+        })
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+    use secrecy::ExposeSecret;
+
+    use crate::authentication::{Permission, Scope, bearer_token};
+
+    fn headers(authorization: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static(authorization));
+        headers
+    }
+
+    #[test]
+    fn a_bearer_token_is_extracted() {
+        let token = bearer_token(&headers("Bearer z2p_abc")).unwrap().unwrap();
+        assert_eq!(token.expose_secret(), "z2p_abc");
+    }
+
+    #[test]
+    fn other_schemes_are_left_to_other_extractors() {
+        assert!(bearer_token(&HeaderMap::new()).unwrap().is_none());
+        assert!(
+            bearer_token(&headers("Basic dXNlcjpwYXNz"))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn an_empty_bearer_token_is_rejected() {
+        assert!(bearer_token(&headers("Bearer  ")).is_err());
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_string_form() {
+        for scope in [
+            Scope::NewslettersPublish,
+            Scope::SubscribersRead,
+            Scope::SubscribersWrite,
+        ] {
+            assert_eq!(Scope::parse(scope.as_str()), Ok(scope));
+            let json = serde_json::to_string(&scope).unwrap();
+            assert_eq!(json, format!("\"{}\"", scope.as_str()));
+        }
+        assert!(Scope::parse("admin").is_err());
+    }
+
+    #[test]
+    fn scopes_only_grant_their_own_permissions() {
+        assert!(Scope::NewslettersPublish.grants(Permission::PublishIssue));
+        assert!(!Scope::NewslettersPublish.grants(Permission::ReadSubscribers));
+        assert!(Scope::SubscribersRead.grants(Permission::ReadSubscribers));
+        assert!(!Scope::SubscribersRead.grants(Permission::PublishIssue));
+        assert!(!Scope::SubscribersRead.grants(Permission::ReadStats));
+        assert!(!Scope::SubscribersRead.grants(Permission::ManageSubscribers));
+        assert!(Scope::SubscribersWrite.grants(Permission::ManageSubscribers));
+    }
+}
@@ -0,0 +1,127 @@
+use std::{ops::Deref, time::Duration};
+
+use actix_web::{
+    FromRequest, HttpMessage, HttpResponse,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{self, HeaderValue},
+    middleware::Next,
+    web,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{
+        Access, AuthError, SESSION_COOKIE_NAME, basic_authentication, bearer_token,
+        get_session_user, get_user_role, validate_api_token, validate_credentials,
+    },
+    client_ip::client_ip,
+    configuration::{LoginThrottlingSettings, PasswordHashingSettings},
+};
+
+/// 通过认证的用户,由 `reject_anonymous_users` 和 `Access` 一起放入请求的 extensions
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// 只放行已认证的请求
+/// 浏览器使用登录后的会话 cookie, API 客户端使用 Bearer 令牌或者 Basic 认证
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let pool = {
+        let (http_request, payload) = req.parts_mut();
+        web::Data::<PgPool>::from_request(http_request, payload).await
+    }?;
+    let hashing = {
+        let (http_request, payload) = req.parts_mut();
+        web::Data::<PasswordHashingSettings>::from_request(http_request, payload).await
+    }?;
+    let throttling = {
+        let (http_request, payload) = req.parts_mut();
+        web::Data::<LoginThrottlingSettings>::from_request(http_request, payload).await
+    }?;
+
+    let session_user = match req.cookie(SESSION_COOKIE_NAME) {
+        Some(cookie) => get_session_user(&pool, cookie.value())
+            .await
+            .context("Failed to look up the session")
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+        None => None,
+    };
+    let bearer_token = bearer_token(req.headers()).map_err(unauthorized)?;
+    let (user_id, scopes, session) = match (session_user, bearer_token) {
+        (Some(user_id), _) => (user_id, None, true),
+        (None, Some(token)) => {
+            let grant = validate_api_token(&pool, &token)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?
+                .ok_or_else(|| unauthorized(anyhow::anyhow!("Invalid API token")))?;
+            (grant.user_id, Some(grant.scopes), false)
+        }
+        (None, None) => {
+            let credentials = basic_authentication(req.headers()).map_err(unauthorized)?;
+            let client_ip = client_ip(req.request());
+            let user_id =
+                validate_credentials(credentials, client_ip, **hashing, &throttling, &pool)
+                    .await
+                    .map_err(|e| match e {
+                        AuthError::InvalidCredentials(e) => unauthorized(e),
+                        AuthError::SecondFactorRequired => unauthorized(anyhow::anyhow!(e)),
+                        AuthError::TooManyAttempts(retry_after) => too_many_attempts(retry_after),
+                        AuthError::UnexpectedError(e) => {
+                            actix_web::error::ErrorInternalServerError(e)
+                        }
+                    })?;
+            (user_id, None, false)
+        }
+    };
+
+    // 用户可能在会话有效期内被删除
+    let role = get_user_role(&pool, user_id)
+        .await
+        .context("Failed to look up the user's role")
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .ok_or_else(|| unauthorized(anyhow::anyhow!("The user no longer exists")))?;
+
+    req.extensions_mut().insert(UserId(user_id));
+    req.extensions_mut().insert(Access {
+        role,
+        scopes,
+        session,
+    });
+    next.call(req).await
+}
+
+fn too_many_attempts(retry_after: Duration) -> actix_web::Error {
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.as_secs().to_string()))
+        .finish();
+    InternalError::from_response(anyhow::anyhow!("Too many failed login attempts"), response).into()
+}
+
+fn unauthorized(e: anyhow::Error) -> actix_web::Error {
+    let mut response = HttpResponse::Unauthorized().finish();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static(r#"Basic realm="publish""#),
+    );
+    InternalError::from_response(e, response).into()
+}
@@ -0,0 +1,38 @@
+mod api_tokens;
+mod middleware;
+mod password;
+mod password_reset;
+mod roles;
+mod session;
+mod throttling;
+mod totp;
+mod users;
+
+pub use api_tokens::{
+    ApiToken, ApiTokenGrant, Scope, bearer_token, create_api_token, list_api_tokens,
+    revoke_api_token, validate_api_token,
+};
+pub use middleware::{UserId, reject_anonymous_users};
+pub use password::{
+    AuthError, Credentials, basic_authentication, change_password, compute_password_hash,
+    needs_rehash, validate_credentials, verify_current_password, verify_password_hash,
+};
+pub use password_reset::{
+    PasswordResetError, PasswordResetToken, issue_password_reset_token,
+    password_reset_token_is_valid, reset_password_with_token,
+};
+pub use roles::{Access, Permission, PermissionDenied, Role, get_user_role};
+pub use session::{
+    SESSION_COOKIE_NAME, create_session, delete_other_sessions, delete_session, get_session_user,
+};
+pub use throttling::{
+    ThrottleKeys, ThrottleState, check_throttle, clear_failures, delete_stale_failures,
+    progressive_delay, record_failure, run_login_failure_cleanup_until_stopped,
+};
+pub use totp::{
+    TotpEnrolment, TotpError, begin_totp_enrolment, confirm_totp_enrolment, disable_totp,
+    totp_code, unix_now, verify_second_factor,
+};
+pub use users::{
+    User, UserAdminError, create_user, delete_user, list_users, reset_password, set_email, set_role,
+};
@@ -0,0 +1,423 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHasher, Version,
+    password_hash::{PasswordHash, PasswordVerifier, SaltString},
+};
+use base64::Engine;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use std::{net::IpAddr, time::Duration};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{
+        ThrottleKeys, check_throttle, clear_failures, progressive_delay, record_failure, unix_now,
+        verify_second_factor,
+    },
+    configuration::{LoginThrottlingSettings, PasswordHashingSettings},
+    domain::Password,
+    telemetry::spawn_blocking_with_tractiong,
+};
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretString,
+    /// 开启两步验证的用户必须提供验证码或恢复码
+    pub second_factor: Option<SecretString>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("A one-time code is required.")]
+    SecondFactorRequired,
+    #[error("Too many failed login attempts.")]
+    TooManyAttempts(Duration),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// 从 `Authorization: Basic ...` 头中解析用户名和密码,供 API 客户端使用
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes: Vec<u8> = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decode_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credentials tring is not valid utf8.")?;
+
+    let mut credentials = decode_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'basic' auth."))?
+        .to_string();
+
+    let passowrd = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'basic' auth."))?
+        .to_string();
+
+    // Basic 认证没有地方放验证码,开启两步验证的用户需要改用 API 令牌
+    Ok(Credentials {
+        username,
+        password: secrecy::SecretString::new(passowrd.into()),
+        second_factor: None,
+    })
+}
+
+/// 校验登录凭据,连续失败时先逐步延迟,超过阈值后暂时锁定
+/// 不存在的用户名同样计数和延迟,不会暴露用户名是否存在
+#[tracing::instrument(
+    name = "Validate credentials",
+    skip(credentials, hashing, throttling, pool)
+)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    client_ip: Option<IpAddr>,
+    hashing: PasswordHashingSettings,
+    throttling: &LoginThrottlingSettings,
+    pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let keys = ThrottleKeys::new(&credentials.username, client_ip);
+    throttled(
+        &keys,
+        throttling,
+        pool,
+        verify_credentials(credentials, hashing, pool),
+    )
+    .await
+}
+
+/// 按 `keys` 的失败次数延迟或拒绝 `verify`,并根据它的结果更新计数
+async fn throttled<T>(
+    keys: &ThrottleKeys,
+    throttling: &LoginThrottlingSettings,
+    pool: &PgPool,
+    verify: impl Future<Output = Result<T, AuthError>>,
+) -> Result<T, AuthError> {
+    let state = check_throttle(pool, keys, throttling).await?;
+    if let Some(retry_after) = state.retry_after {
+        return Err(AuthError::TooManyAttempts(retry_after));
+    }
+    let delay = progressive_delay(state.username_failures, throttling);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    match verify.await {
+        Ok(t) => {
+            clear_failures(pool, keys).await?;
+            Ok(t)
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            record_failure(pool, keys, throttling).await?;
+            Err(AuthError::InvalidCredentials(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn verify_credentials(
+    credentials: Credentials,
+    hashing: PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let mut user_id = None;
+    let mut totp_secret = None;
+    // 用户不存在时也要计算一次哈希,避免通过响应时间判断用户名是否存在
+    let mut expected_password_hash = dummy_password_hash(hashing);
+
+    if let Some(stored) = get_stored_credentials(&credentials.username, pool).await? {
+        user_id = Some(stored.user_id);
+        expected_password_hash = stored.password_hash;
+        totp_secret = stored.totp_secret;
+    };
+
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
+    spawn_blocking_with_tractiong(|| {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking taks")??;
+    let user_id = user_id
+        .ok_or_else(|| anyhow::anyhow!("Unkown username"))
+        .map_err(AuthError::InvalidCredentials)?;
+
+    if let Some(totp_secret) = totp_secret {
+        let code = credentials
+            .second_factor
+            .ok_or(AuthError::SecondFactorRequired)?;
+        if !verify_second_factor(
+            pool,
+            user_id,
+            &totp_secret,
+            code.expose_secret(),
+            unix_now()?,
+        )
+        .await?
+        {
+            return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                "Invalid one-time code"
+            )));
+        }
+    }
+
+    if needs_rehash(&stored_password_hash, hashing) {
+        // 升级哈希不影响这次登录,放到后台执行
+        tokio::spawn(
+            upgrade_password_hash(
+                user_id,
+                stored_password_hash,
+                password,
+                hashing,
+                pool.clone(),
+            )
+            .in_current_span(),
+        );
+    }
+    Ok(user_id)
+}
+
+/// 和配置参数一致的假哈希,只用来消耗同样的时间,校验一定失败
+fn dummy_password_hash(hashing: PasswordHashingSettings) -> SecretString {
+    SecretString::from(format!(
+        "$argon2id$v=19$m={},t={},p={}$\
+        gZiV/M1gPc22ELAH/Jh1Hw$\
+        CWOrko070JBQ/iyh7uJ0L02aLEfrHWTWLLSAxT0zRno",
+        hashing.memory_size_kib, hashing.iterations, hashing.parallelism
+    ))
+}
+
+/// 存储的哈希不是 Argon2id v0x13,或者参数和当前配置不一致时需要重新计算
+pub fn needs_rehash(password_hash: &SecretString, hashing: PasswordHashingSettings) -> bool {
+    let Ok(password_hash) = PasswordHash::new(password_hash.expose_secret()) else {
+        return true;
+    };
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&password_hash) {
+        Ok(params) => {
+            params.m_cost() != hashing.memory_size_kib
+                || params.t_cost() != hashing.iterations
+                || params.p_cost() != hashing.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(stored_password_hash, password, hashing, pool)
+)]
+async fn upgrade_password_hash(
+    user_id: Uuid,
+    stored_password_hash: SecretString,
+    password: SecretString,
+    hashing: PasswordHashingSettings,
+    pool: PgPool,
+) {
+    let outcome: Result<(), anyhow::Error> = async {
+        let password_hash =
+            spawn_blocking_with_tractiong(move || compute_password_hash(&password, hashing))
+                .await?
+                .context("Failed to hash password")?;
+        // 只替换刚才校验过的哈希,期间密码被修改时放弃升级
+        sqlx::query!(
+            r#"UPDATE users SET password_hash = $1 WHERE user_id = $2 AND password_hash = $3"#,
+            password_hash.expose_secret(),
+            user_id,
+            stored_password_hash.expose_secret()
+        )
+        .execute(&pool)
+        .await
+        .context("Failed to store the upgraded password hash")?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = outcome {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to upgrade a password hash"
+        );
+    }
+}
+
+/// 校验已登录用户的当前密码,修改密码等敏感操作前使用
+/// 与登录共用失败计数,被盗的会话不能用来无限制地猜测密码
+#[tracing::instrument(name = "Verify current password", skip(password, throttling, pool))]
+pub async fn verify_current_password(
+    user_id: Uuid,
+    password: SecretString,
+    client_ip: Option<IpAddr>,
+    throttling: &LoginThrottlingSettings,
+    pool: &PgPool,
+) -> Result<(), AuthError> {
+    let stored = sqlx::query!(
+        r#"SELECT username, password_hash FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the stored password hash.")?;
+
+    let keys = ThrottleKeys::new(&stored.username, client_ip);
+    let verify = async move {
+        spawn_blocking_with_tractiong(move || {
+            verify_password_hash(SecretString::from(stored.password_hash), password)
+        })
+        .await
+        .context("Failed to spawn blocking taks")?
+    };
+    throttled(&keys, throttling, pool, verify).await
+}
+
+#[tracing::instrument(name = "Change password", skip(password, hashing, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Password,
+    hashing: PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash =
+        spawn_blocking_with_tractiong(move || compute_password_hash(password.as_ref(), hashing))
+            .await?
+            .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    Ok(())
+}
+
+/// 用配置中的参数计算新的 Argon2id 哈希
+/// 很耗 CPU,需要放在 `spawn_blocking_with_tractiong` 里执行
+pub fn compute_password_hash(
+    password: &SecretString,
+    hashing: PasswordHashingSettings,
+) -> Result<SecretString, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let params = hashing
+        .params()
+        .context("Invalid Argon2 parameters in the configuration")?;
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
+    Ok(SecretString::from(password_hash))
+}
+
+struct StoredCredentials {
+    user_id: Uuid,
+    password_hash: SecretString,
+    /// 只有确认绑定后才返回
+    totp_secret: Option<SecretString>,
+}
+
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<StoredCredentials>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash,
+            CASE WHEN totp_enabled_at IS NOT NULL THEN totp_secret END as totp_secret
+        FROM users WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to preform a query to validate auth credentials.")?
+    .map(|row| StoredCredentials {
+        user_id: row.user_id,
+        password_hash: SecretString::new(row.password_hash.into()),
+        totp_secret: row.totp_secret.map(SecretString::from),
+    });
+
+    Ok(row)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_passowrd_hash, password_hash)
+)]
+pub fn verify_password_hash(
+    expected_passowrd_hash: SecretString,
+    password_hash: SecretString,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_passowrd_hash.expose_secret())
+        .context("Failed to PasswordHash ")?;
+
+    Argon2::default()
+        .verify_password(
+            password_hash.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid passowrd")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+
+    use crate::{authentication::needs_rehash, configuration::PasswordHashingSettings};
+
+    const SETTINGS: PasswordHashingSettings = PasswordHashingSettings {
+        memory_size_kib: 15000,
+        iterations: 2,
+        parallelism: 1,
+    };
+
+    fn hash_with(prefix: &str) -> SecretString {
+        SecretString::from(format!(
+            "{prefix}$gZiV/M1gPc22ELAH/Jh1Hw$CWOrko070JBQ/iyh7uJ0L02aLEfrHWTWLLSAxT0zRno"
+        ))
+    }
+
+    #[test]
+    fn a_hash_with_the_current_parameters_is_kept() {
+        let hash = hash_with("$argon2id$v=19$m=15000,t=2,p=1");
+        assert!(!needs_rehash(&hash, SETTINGS));
+    }
+
+    #[test]
+    fn a_hash_with_different_parameters_is_upgraded() {
+        for prefix in [
+            "$argon2id$v=19$m=1500,t=2,p=1",
+            "$argon2id$v=19$m=15000,t=1,p=1",
+            "$argon2id$v=19$m=15000,t=2,p=2",
+        ] {
+            assert!(needs_rehash(&hash_with(prefix), SETTINGS), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn a_hash_with_another_algorithm_or_version_is_upgraded() {
+        for prefix in [
+            "$argon2i$v=19$m=15000,t=2,p=1",
+            "$argon2id$v=16$m=15000,t=2,p=1",
+        ] {
+            assert!(needs_rehash(&hash_with(prefix), SETTINGS), "{prefix}");
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, SecretString};
+use sha3::{Digest, Sha3_256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::compute_password_hash, configuration::PasswordHashingSettings,
+    domain::Password, domain::SubscriberEmail, routes::subscriptions::error_chain_fmt,
+    telemetry::spawn_blocking_with_tractiong,
+};
+
+/// 需要发给用户的重置链接信息
+pub struct PasswordResetToken {
+    pub email: SubscriberEmail,
+    pub token: SecretString,
+}
+
+fn generate_reset_token() -> SecretString {
+    let mut rng = thread_rng();
+    let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect();
+    SecretString::from(token)
+}
+
+fn hash_reset_token(token: &str) -> String {
+    format!("{:x}", Sha3_256::digest(token.as_bytes()))
+}
+
+#[derive(thiserror::Error)]
+pub enum PasswordResetError {
+    #[error("This password reset link is invalid or has expired.")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// 为用户生成新的重置令牌,之前发出的链接全部作废
+/// 用户不存在或没有设置邮箱时返回 `None`,调用方不能把这个区别暴露给请求者
+#[tracing::instrument(name = "Issue a password reset token", skip(pool, expiration))]
+pub async fn issue_password_reset_token(
+    pool: &PgPool,
+    username: &str,
+    expiration: Duration,
+) -> Result<Option<PasswordResetToken>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let user = sqlx::query!(
+        r#"SELECT user_id, email FROM users WHERE username = $1 FOR UPDATE"#,
+        username
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the user")?;
+    let Some((user_id, email)) = user.and_then(|user| Some((user.user_id, user.email?))) else {
+        return Ok(None);
+    };
+    let email = SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?;
+
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the previous password reset tokens")?;
+    let token = generate_reset_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, now(), now() + make_interval(secs => $3))
+        "#,
+        hash_reset_token(token.expose_secret()),
+        user_id,
+        expiration.as_secs_f64()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the password reset token")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to issue a password reset token.")?;
+    Ok(Some(PasswordResetToken { email, token }))
+}
+
+/// 检查令牌是否仍然有效,用来决定是否展示设置新密码的表单
+#[tracing::instrument(name = "Check a password reset token", skip_all)]
+pub async fn password_reset_token_is_valid(
+    pool: &PgPool,
+    token: &str,
+) -> Result<bool, anyhow::Error> {
+    let valid = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM password_reset_tokens
+            WHERE token_hash = $1 AND expires_at > now()
+        ) AS "valid!"
+        "#,
+        hash_reset_token(token)
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up the password reset token")?;
+    Ok(valid)
+}
+
+/// 用重置令牌设置新密码,返回对应的用户
+/// 令牌、这个用户的其他重置令牌以及已登录的会话在同一个事务里删除
+#[tracing::instrument(name = "Reset a password with a token", skip_all)]
+pub async fn reset_password_with_token(
+    pool: &PgPool,
+    token: &str,
+    password: Password,
+    hashing: PasswordHashingSettings,
+) -> Result<Uuid, PasswordResetError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    // 先校验令牌再计算哈希,无效的请求不会消耗 CPU
+    let user_id = sqlx::query_scalar!(
+        r#"
+        SELECT user_id FROM password_reset_tokens
+        WHERE token_hash = $1 AND expires_at > now()
+        FOR UPDATE
+        "#,
+        hash_reset_token(token)
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the password reset token")?
+    .ok_or(PasswordResetError::InvalidToken)?;
+
+    let password_hash =
+        spawn_blocking_with_tractiong(move || compute_password_hash(password.as_ref(), hashing))
+            .await
+            .context("Failed to spawn blocking taks")?
+            .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the used password reset tokens")?;
+    sqlx::query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the user's sessions")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")?;
+    Ok(user_id)
+}
@@ -0,0 +1,211 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::Scope;
+
+/// 后台用户的角色,保存在 `users.role`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Owner,
+    Editor,
+    Viewer,
+}
+
+/// 路由需要的权限,由角色决定是否拥有
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// 查看后台首页和统计数据
+    ReadStats,
+    /// 编写草稿,但不能发送
+    DraftIssue,
+    /// 向所有订阅者发送一期 newsletter
+    PublishIssue,
+    /// 查看订阅者列表
+    ReadSubscribers,
+    /// 手动确认、退订或删除订阅者
+    ManageSubscribers,
+    /// 查看审计日志
+    ReadAuditLog,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "owner" => Ok(Role::Owner),
+            "editor" => Ok(Role::Editor),
+            "viewer" => Ok(Role::Viewer),
+            other => Err(format!(
+                "{other} is not a valid role. Use either `owner`, `editor` or `viewer`."
+            )),
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::ReadStats => true,
+            Permission::DraftIssue => matches!(self, Role::Owner | Role::Editor),
+            Permission::PublishIssue => matches!(self, Role::Owner),
+            Permission::ReadSubscribers => matches!(self, Role::Owner | Role::Editor),
+            Permission::ManageSubscribers => matches!(self, Role::Owner | Role::Editor),
+            Permission::ReadAuditLog => matches!(self, Role::Owner),
+        }
+    }
+
+    pub fn authorize(&self, permission: Permission) -> Result<(), PermissionDenied> {
+        if self.has(permission) {
+            Ok(())
+        } else {
+            Err(PermissionDenied::Role(*self, permission))
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 当前请求可以使用的权限,由 `reject_anonymous_users` 放入请求的 extensions
+/// 使用 API 令牌时还要受令牌范围的限制
+#[derive(Clone, Debug)]
+pub struct Access {
+    pub role: Role,
+    pub scopes: Option<Vec<Scope>>,
+    /// 通过登录后的会话 cookie 认证,而不是 API 令牌或 Basic 认证
+    pub session: bool,
+}
+
+impl Access {
+    pub fn authorize(&self, permission: Permission) -> Result<(), PermissionDenied> {
+        self.role.authorize(permission)?;
+        match &self.scopes {
+            Some(scopes) if !scopes.iter().any(|s| s.grants(permission)) => {
+                Err(PermissionDenied::OutOfScope(permission))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 账号安全相关的操作(修改密码、两步验证、API 令牌)只允许登录的浏览器会话执行,
+    /// 泄露的令牌或者 Basic 凭据不能用来扩大自己的权限
+    pub fn require_session(&self) -> Result<(), PermissionDenied> {
+        if self.session {
+            Ok(())
+        } else {
+            Err(PermissionDenied::SessionRequired)
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PermissionDenied {
+    #[error("The {0} role does not have the {1:?} permission.")]
+    Role(Role, Permission),
+    #[error("The API token is not scoped for the {0:?} permission.")]
+    OutOfScope(Permission),
+    #[error("This action requires a logged-in session, API tokens and basic auth cannot be used.")]
+    SessionRequired,
+}
+
+/// 用户不存在(例如会话期间被删除)时返回 `None`
+#[tracing::instrument(name = "Get the role of a user", skip(pool))]
+pub async fn get_user_role(pool: &PgPool, user_id: Uuid) -> Result<Option<Role>, anyhow::Error> {
+    let role = sqlx::query_scalar!(r#"SELECT role FROM users WHERE user_id = $1"#, user_id)
+        .fetch_optional(pool)
+        .await?;
+    role.map(|role| Role::parse(&role).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::{Access, Permission, Role, Scope};
+
+    #[test]
+    fn roles_round_trip_through_their_string_form() {
+        for role in [Role::Owner, Role::Editor, Role::Viewer] {
+            assert_eq!(Role::parse(role.as_str()), Ok(role));
+        }
+        assert!(Role::parse("admin").is_err());
+    }
+
+    #[test]
+    fn only_owners_can_publish() {
+        assert!(Role::Owner.has(Permission::PublishIssue));
+        assert!(Role::Editor.authorize(Permission::PublishIssue).is_err());
+        assert!(Role::Viewer.authorize(Permission::PublishIssue).is_err());
+    }
+
+    #[test]
+    fn editors_can_draft_but_viewers_cannot() {
+        assert!(Role::Owner.has(Permission::DraftIssue));
+        assert!(Role::Editor.has(Permission::DraftIssue));
+        assert!(!Role::Viewer.has(Permission::DraftIssue));
+    }
+
+    #[test]
+    fn only_owners_can_read_the_audit_log() {
+        assert!(Role::Owner.has(Permission::ReadAuditLog));
+        assert!(!Role::Editor.has(Permission::ReadAuditLog));
+        assert!(!Role::Viewer.has(Permission::ReadAuditLog));
+    }
+
+    #[test]
+    fn editors_can_manage_subscribers_but_viewers_cannot() {
+        assert!(Role::Owner.has(Permission::ManageSubscribers));
+        assert!(Role::Editor.has(Permission::ManageSubscribers));
+        assert!(!Role::Viewer.has(Permission::ManageSubscribers));
+    }
+
+    #[test]
+    fn every_role_can_read_stats() {
+        for role in [Role::Owner, Role::Editor, Role::Viewer] {
+            assert!(role.has(Permission::ReadStats));
+        }
+    }
+
+    #[test]
+    fn api_tokens_are_limited_by_both_role_and_scope() {
+        let token = |role| Access {
+            role,
+            scopes: Some(vec![Scope::NewslettersPublish]),
+            session: false,
+        };
+        assert!(
+            token(Role::Owner)
+                .authorize(Permission::PublishIssue)
+                .is_ok()
+        );
+        assert!(token(Role::Owner).authorize(Permission::ReadStats).is_err());
+        assert!(
+            token(Role::Editor)
+                .authorize(Permission::PublishIssue)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn only_sessions_can_manage_the_account() {
+        let access = |scopes, session| Access {
+            role: Role::Owner,
+            scopes,
+            session,
+        };
+        assert!(access(None, true).require_session().is_ok());
+        assert!(access(None, false).require_session().is_err());
+        assert!(
+            access(Some(vec![Scope::NewslettersPublish]), false)
+                .require_session()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use sha3::{Digest, Sha3_256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// 保存会话 id 的 cookie 名称
+pub const SESSION_COOKIE_NAME: &str = "session_id";
+
+/// 会话 id 只出现在 cookie 里,数据库保存它的摘要
+/// 与确认令牌一样,id 本身足够随机,不需要加盐
+fn hash_session_id(session_id: &str) -> String {
+    format!("{:x}", Sha3_256::digest(session_id.as_bytes()))
+}
+
+fn generate_session_id() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// 为用户创建一个新的会话,返回写入 cookie 的会话 id
+/// 每次登录都生成新的 id,防止会话固定攻击
+#[tracing::instrument(name = "Create a session", skip(pool, expiration))]
+pub async fn create_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    expiration: Duration,
+) -> Result<String, sqlx::Error> {
+    let session_id = generate_session_id();
+    let mut transaction = pool.begin().await?;
+    // 顺便清理这个用户已经过期的会话
+    sqlx::query!(
+        r#"DELETE FROM sessions WHERE user_id = $1 AND expires_at < now()"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (session_id_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, now(), now() + make_interval(secs => $3))
+        "#,
+        hash_session_id(&session_id),
+        user_id,
+        expiration.as_secs_f64()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(session_id)
+}
+
+/// 返回会话对应的用户,会话不存在或已过期时返回 `None`
+#[tracing::instrument(name = "Get the user of a session", skip_all)]
+pub async fn get_session_user(
+    pool: &PgPool,
+    session_id: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT user_id FROM sessions WHERE session_id_hash = $1 AND expires_at > now()"#,
+        hash_session_id(session_id)
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Delete a session", skip_all)]
+pub async fn delete_session(pool: &PgPool, session_id: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    let user_id = sqlx::query_scalar!(
+        r#"DELETE FROM sessions WHERE session_id_hash = $1 RETURNING user_id"#,
+        hash_session_id(session_id)
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user_id)
+}
+
+/// 删除用户除当前会话以外的所有会话,修改密码后让其他设备重新登录
+#[tracing::instrument(name = "Delete the other sessions of a user", skip(pool, session_id))]
+pub async fn delete_other_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_id: &str,
+) -> Result<u64, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM sessions WHERE user_id = $1 AND session_id_hash <> $2"#,
+        user_id,
+        hash_session_id(session_id)
+    )
+    .execute(pool)
+    .await?;
+    Ok(deleted.rows_affected())
+}
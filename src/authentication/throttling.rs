@@ -0,0 +1,231 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::configuration::LoginThrottlingSettings;
+
+/// 记录的用户名最长保留的字符数,避免超长的输入写进数据库
+const MAX_USERNAME_KEY_LENGTH: usize = 255;
+
+/// 一次登录尝试对应的计数键
+pub struct ThrottleKeys {
+    username: String,
+    ip: Option<String>,
+}
+
+impl ThrottleKeys {
+    pub fn new(username: &str, client_ip: Option<IpAddr>) -> Self {
+        Self {
+            username: username.chars().take(MAX_USERNAME_KEY_LENGTH).collect(),
+            ip: client_ip.map(|ip| ip.to_string()),
+        }
+    }
+}
+
+/// 校验前的限流状态
+pub struct ThrottleState {
+    /// 用户名在时间窗口内的失败次数,决定等待的时间
+    pub username_failures: u32,
+    /// 被锁定时还需要等待多久
+    pub retry_after: Option<Duration>,
+}
+
+/// 超过阈值后按失败次数翻倍等待,不超过上限
+pub fn progressive_delay(failures: u32, settings: &LoginThrottlingSettings) -> Duration {
+    if failures < settings.delay_after_failures {
+        return Duration::ZERO;
+    }
+    let exponent = (failures - settings.delay_after_failures).min(16);
+    let delay = settings
+        .base_delay_milliseconds
+        .saturating_mul(1 << exponent)
+        .min(settings.max_delay_milliseconds);
+    Duration::from_millis(delay)
+}
+
+#[tracing::instrument(name = "Check login throttling", skip_all)]
+pub async fn check_throttle(
+    pool: &PgPool,
+    keys: &ThrottleKeys,
+    settings: &LoginThrottlingSettings,
+) -> Result<ThrottleState, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            kind,
+            CASE WHEN last_failed_at > now() - make_interval(secs => $3)
+                THEN failures ELSE 0 END as "failures!",
+            CASE WHEN locked_until > now()
+                THEN EXTRACT(EPOCH FROM locked_until - now())::float8 END as retry_after_seconds
+        FROM login_failures
+        WHERE (kind = 'username' AND key = $1) OR (kind = 'ip' AND key = $2)
+        "#,
+        keys.username,
+        keys.ip,
+        settings.failure_window_seconds as f64
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to look up failed login attempts")?;
+
+    let username_failures = rows
+        .iter()
+        .find(|row| row.kind == "username")
+        .map(|row| row.failures as u32)
+        .unwrap_or(0);
+    let retry_after = rows
+        .iter()
+        .filter_map(|row| row.retry_after_seconds)
+        .reduce(f64::max)
+        .map(|seconds| Duration::from_secs(seconds.ceil() as u64));
+    Ok(ThrottleState {
+        username_failures,
+        retry_after,
+    })
+}
+
+/// 记录一次失败,达到阈值时锁定一段时间
+#[tracing::instrument(name = "Record a failed login attempt", skip_all)]
+pub async fn record_failure(
+    pool: &PgPool,
+    keys: &ThrottleKeys,
+    settings: &LoginThrottlingSettings,
+) -> Result<(), anyhow::Error> {
+    let mut entries = vec![(
+        "username",
+        keys.username.as_str(),
+        settings.username_lockout_after_failures,
+    )];
+    if let Some(ip) = &keys.ip {
+        entries.push(("ip", ip.as_str(), settings.ip_lockout_after_failures));
+    }
+    for (kind, key, lockout_after) in entries {
+        sqlx::query!(
+            r#"
+            INSERT INTO login_failures (kind, key, failures, last_failed_at, locked_until)
+            VALUES (
+                $1, $2, 1, now(),
+                CASE WHEN 1 >= $3 THEN now() + make_interval(secs => $4) END
+            )
+            ON CONFLICT (kind, key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_failures.last_failed_at < now() - make_interval(secs => $5) THEN 1
+                    ELSE login_failures.failures + 1
+                END,
+                last_failed_at = now(),
+                locked_until = CASE
+                    WHEN (CASE
+                        WHEN login_failures.last_failed_at < now() - make_interval(secs => $5) THEN 1
+                        ELSE login_failures.failures + 1
+                    END) >= $3 THEN now() + make_interval(secs => $4)
+                    ELSE login_failures.locked_until
+                END
+            "#,
+            kind,
+            key,
+            lockout_after as i32,
+            settings.lockout_seconds as f64,
+            settings.failure_window_seconds as f64
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record a failed login attempt")?;
+    }
+    Ok(())
+}
+
+/// 登录成功后清除用户名的计数
+/// IP 的计数保留,否则攻击者可以用自己的账号登录来重置它
+#[tracing::instrument(name = "Clear failed login attempts", skip_all)]
+pub async fn clear_failures(pool: &PgPool, keys: &ThrottleKeys) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM login_failures WHERE kind = 'username' AND key = $1"#,
+        keys.username
+    )
+    .execute(pool)
+    .await
+    .context("Failed to clear failed login attempts")?;
+    Ok(())
+}
+
+/// 定期删除已经过了时间窗口、也不在锁定中的记录
+pub async fn run_login_failure_cleanup_until_stopped(
+    pool: PgPool,
+    settings: LoginThrottlingSettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        if let Err(e) = delete_stale_failures(&pool, &settings).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete stale login failures"
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+    }
+}
+
+#[tracing::instrument(name = "Delete stale login failures", skip_all)]
+pub async fn delete_stale_failures(
+    pool: &PgPool,
+    settings: &LoginThrottlingSettings,
+) -> Result<u64, sqlx::Error> {
+    let n_deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM login_failures
+        WHERE last_failed_at < now() - make_interval(secs => $1)
+            AND (locked_until IS NULL OR locked_until < now())
+        "#,
+        settings.failure_window_seconds as f64
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted_rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ThrottleKeys, progressive_delay};
+    use crate::configuration::LoginThrottlingSettings;
+
+    fn settings() -> LoginThrottlingSettings {
+        LoginThrottlingSettings {
+            delay_after_failures: 3,
+            base_delay_milliseconds: 250,
+            max_delay_milliseconds: 4000,
+            username_lockout_after_failures: 10,
+            ip_lockout_after_failures: 50,
+            lockout_seconds: 900,
+            failure_window_seconds: 900,
+        }
+    }
+
+    #[test]
+    fn there_is_no_delay_below_the_threshold() {
+        for failures in 0..3 {
+            assert_eq!(progressive_delay(failures, &settings()), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_until_the_cap() {
+        let delays: Vec<_> = (3..9)
+            .map(|failures| progressive_delay(failures, &settings()).as_millis())
+            .collect();
+        assert_eq!(delays, vec![250, 500, 1000, 2000, 4000, 4000]);
+        assert_eq!(
+            progressive_delay(u32::MAX, &settings()),
+            Duration::from_millis(4000)
+        );
+    }
+
+    #[test]
+    fn very_long_usernames_are_truncated() {
+        let keys = ThrottleKeys::new(&"a".repeat(10_000), None);
+        assert_eq!(keys.username.len(), 255);
+    }
+}
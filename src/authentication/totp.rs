@@ -0,0 +1,448 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, SecretString};
+use sha1::Sha1;
+use sha3::{Digest, Sha3_256};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::routes::subscriptions::error_chain_fmt;
+
+/// RFC 6238 的默认参数,主流验证器 App 都只支持这一组
+const TOTP_PERIOD_SECONDS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// 前后各允许一个时间步,容忍手机和服务器之间的时钟偏差
+const TOTP_ALLOWED_SKEW: u64 = 1;
+const TOTP_ISSUER: &str = "zero2prod";
+const RECOVERY_CODE_COUNT: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// 开始绑定时返回给用户的信息,用来添加到验证器 App
+pub struct TotpEnrolment {
+    pub secret: SecretString,
+    pub provisioning_uri: SecretString,
+}
+
+#[derive(thiserror::Error)]
+pub enum TotpError {
+    #[error("Two-factor authentication is already enabled.")]
+    AlreadyEnabled,
+    #[error("Two-factor authentication enrolment has not been started.")]
+    NotEnrolling,
+    #[error("Two-factor authentication is not enabled.")]
+    NotEnabled,
+    #[error("The one-time code is not valid.")]
+    InvalidCode,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TotpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut decoded = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())
+            .with_context(|| format!("'{c}' is not a base32 character"))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(decoded)
+}
+
+/// 当前的 Unix 时间,校验验证码的函数都显式接收时间,方便测试使用固定的时钟
+pub fn unix_now() -> Result<u64, anyhow::Error> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("The system clock is before the Unix epoch")?
+        .as_secs())
+}
+
+/// RFC 4226 的 HOTP
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// 计算某个时间点的验证码
+pub fn totp_code(secret: &SecretString, unix_time: u64) -> Result<String, anyhow::Error> {
+    let key = base32_decode(secret.expose_secret())?;
+    let code = hotp(&key, unix_time / TOTP_PERIOD_SECONDS);
+    Ok(format!("{code:0width$}", width = TOTP_DIGITS as usize))
+}
+
+/// 校验验证码,成功时返回匹配的时间步
+fn verify_totp_code(
+    secret: &SecretString,
+    code: &str,
+    unix_time: u64,
+) -> Result<Option<u64>, anyhow::Error> {
+    let key = base32_decode(secret.expose_secret())?;
+    let Ok(code) = code.parse::<u32>() else {
+        return Ok(None);
+    };
+    let current_step = unix_time / TOTP_PERIOD_SECONDS;
+    let steps = current_step.saturating_sub(TOTP_ALLOWED_SKEW)..=current_step + TOTP_ALLOWED_SKEW;
+    Ok(steps.into_iter().find(|step| hotp(&key, *step) == code))
+}
+
+fn provisioning_uri(secret: &SecretString, username: &str) -> SecretString {
+    let mut uri = reqwest::Url::parse_with_params(
+        "otpauth://totp/",
+        &[
+            ("secret", secret.expose_secret()),
+            ("issuer", TOTP_ISSUER),
+            ("algorithm", "SHA1"),
+            ("digits", &TOTP_DIGITS.to_string()),
+            ("period", &TOTP_PERIOD_SECONDS.to_string()),
+        ],
+    )
+    .expect("The otpauth URI is valid");
+    uri.set_path(&format!("{TOTP_ISSUER}:{username}"));
+    SecretString::from(uri.to_string())
+}
+
+fn generate_totp_secret() -> SecretString {
+    // RFC 4226 推荐 160 位的密钥
+    let mut key = [0u8; 20];
+    thread_rng().fill_bytes(&mut key);
+    SecretString::from(base32_encode(&key))
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = thread_rng();
+    let code: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(10)
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// 恢复码足够随机,和会话 id 一样只需要摘要
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    format!("{:x}", Sha3_256::digest(normalized.as_bytes()))
+}
+
+/// 生成新的密钥,等待用户用第一个验证码确认后才会启用
+/// 重复调用会替换尚未确认的密钥
+#[tracing::instrument(name = "Begin TOTP enrolment", skip(pool))]
+pub async fn begin_totp_enrolment(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<TotpEnrolment, TotpError> {
+    let secret = generate_totp_secret();
+    let username = sqlx::query_scalar!(
+        r#"
+        UPDATE users SET totp_secret = $1, totp_last_step = NULL
+        WHERE user_id = $2 AND totp_enabled_at IS NULL
+        RETURNING username
+        "#,
+        secret.expose_secret(),
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to store the TOTP secret")?
+    .ok_or(TotpError::AlreadyEnabled)?;
+    Ok(TotpEnrolment {
+        provisioning_uri: provisioning_uri(&secret, &username),
+        secret,
+    })
+}
+
+/// 用第一个验证码确认绑定,返回只展示一次的恢复码
+#[tracing::instrument(name = "Confirm TOTP enrolment", skip(pool, code))]
+pub async fn confirm_totp_enrolment(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+    unix_time: u64,
+) -> Result<Vec<String>, TotpError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let row = sqlx::query!(
+        r#"SELECT totp_secret, totp_enabled_at FROM users WHERE user_id = $1 FOR UPDATE"#,
+        user_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to look up the TOTP secret")?;
+    if row.totp_enabled_at.is_some() {
+        return Err(TotpError::AlreadyEnabled);
+    }
+    let secret = SecretString::from(row.totp_secret.ok_or(TotpError::NotEnrolling)?);
+    let step = verify_totp_code(&secret, code, unix_time)?.ok_or(TotpError::InvalidCode)?;
+
+    sqlx::query!(
+        r#"UPDATE users SET totp_enabled_at = now(), totp_last_step = $1 WHERE user_id = $2"#,
+        step as i64,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enable TOTP")?;
+    let recovery_codes = replace_recovery_codes(&mut transaction, user_id)
+        .await
+        .context("Failed to store the recovery codes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable TOTP.")?;
+    Ok(recovery_codes)
+}
+
+async fn replace_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    let codes: Vec<String> = std::iter::repeat_with(generate_recovery_code)
+        .take(RECOVERY_CODE_COUNT)
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::text[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(codes)
+}
+
+/// 关闭两步验证,需要提供一个有效的验证码或恢复码
+#[tracing::instrument(name = "Disable TOTP", skip(pool, code))]
+pub async fn disable_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+    unix_time: u64,
+) -> Result<(), TotpError> {
+    let secret = sqlx::query_scalar!(
+        r#"SELECT totp_secret FROM users WHERE user_id = $1 AND totp_enabled_at IS NOT NULL"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the TOTP secret")?
+    .flatten()
+    .ok_or(TotpError::NotEnabled)?;
+    if !verify_second_factor(pool, user_id, &SecretString::from(secret), code, unix_time).await? {
+        return Err(TotpError::InvalidCode);
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to disable TOTP")?;
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the recovery codes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable TOTP.")?;
+    Ok(())
+}
+
+/// 校验第二因素:当前的验证码或者一个未使用的恢复码
+/// 验证码只能使用一次,恢复码用过即作废
+#[tracing::instrument(name = "Verify second factor", skip(pool, secret, code))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    secret: &SecretString,
+    code: &str,
+    unix_time: u64,
+) -> Result<bool, anyhow::Error> {
+    let code = code.trim();
+    if code.len() == TOTP_DIGITS as usize {
+        let Some(step) = verify_totp_code(secret, code, unix_time)? else {
+            return Ok(false);
+        };
+        let n_updated = sqlx::query!(
+            r#"
+            UPDATE users SET totp_last_step = $1
+            WHERE user_id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)
+            "#,
+            step as i64,
+            user_id
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the used TOTP step")?
+        .rows_affected();
+        return Ok(n_updated > 0);
+    }
+
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE totp_recovery_codes SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to use a recovery code")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::{ExposeSecret, SecretString};
+
+    use super::{
+        base32_decode, base32_encode, hash_recovery_code, hotp, provisioning_uri, totp_code,
+        verify_totp_code,
+    };
+
+    // RFC 6238 附录 B 的 SHA1 测试密钥
+    fn rfc_secret() -> SecretString {
+        SecretString::from(base32_encode(b"12345678901234567890"))
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        for bytes in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(base32_decode(&base32_encode(bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw 6ytb oi======").unwrap(), b"foobar");
+        assert!(base32_decode("not base32!").is_err());
+    }
+
+    #[test]
+    fn hotp_matches_the_rfc_4226_test_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(b"12345678901234567890", counter as u64), code);
+        }
+    }
+
+    #[test]
+    fn totp_matches_the_rfc_6238_test_vectors_with_a_fixed_clock() {
+        let cases = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (unix_time, code) in cases {
+            assert_eq!(totp_code(&rfc_secret(), unix_time).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn codes_from_adjacent_time_steps_are_accepted() {
+        let now = 1234567890;
+        let step = now / 30;
+        for unix_time in [now - 30, now, now + 30] {
+            let code = totp_code(&rfc_secret(), unix_time).unwrap();
+            assert_eq!(
+                verify_totp_code(&rfc_secret(), &code, now).unwrap(),
+                Some(unix_time / 30)
+            );
+        }
+        let stale = totp_code(&rfc_secret(), (step - 2) * 30).unwrap();
+        assert_eq!(verify_totp_code(&rfc_secret(), &stale, now).unwrap(), None);
+        assert_eq!(
+            verify_totp_code(&rfc_secret(), "abcdef", now).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn the_provisioning_uri_follows_the_key_uri_format() {
+        let uri = provisioning_uri(&rfc_secret(), "ursula le guin");
+        let uri = reqwest::Url::parse(uri.expose_secret()).unwrap();
+        assert_eq!(uri.scheme(), "otpauth");
+        assert_eq!(uri.host_str(), Some("totp"));
+        assert_eq!(uri.path(), "/zero2prod:ursula%20le%20guin");
+        let secret = uri.query_pairs().find(|(k, _)| k == "secret").unwrap().1;
+        assert_eq!(secret, rfc_secret().expose_secret());
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized_before_hashing() {
+        assert_eq!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code(" ABCDE-12345 ")
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::Context;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{Role, change_password, compute_password_hash},
+    configuration::PasswordHashingSettings,
+    domain::{Password, SubscriberEmail},
+    routes::subscriptions::error_chain_fmt,
+    telemetry::spawn_blocking_with_tractiong,
+};
+
+pub struct User {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(thiserror::Error)]
+pub enum UserAdminError {
+    #[error("A user named '{0}' already exists.")]
+    DuplicateUsername(String),
+    #[error("Another user already uses the email address '{0}'.")]
+    DuplicateEmail(String),
+    #[error("There is no user named '{0}'.")]
+    UnknownUsername(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UserAdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// `users.email` 的唯一约束冲突
+fn is_duplicate_email(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.constraint())
+        .is_some_and(|constraint| constraint == "users_email_key")
+}
+
+#[tracing::instrument(name = "Create a user", skip(password, hashing, pool))]
+pub async fn create_user(
+    username: &str,
+    role: Role,
+    email: Option<&SubscriberEmail>,
+    password: Password,
+    hashing: PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<Uuid, UserAdminError> {
+    let password_hash =
+        spawn_blocking_with_tractiong(move || compute_password_hash(password.as_ref(), hashing))
+            .await
+            .context("Failed to spawn blocking taks")?
+            .context("Failed to hash password")?;
+    let user_id = Uuid::new_v4();
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, email)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        role.as_str(),
+        email.map(|email| email.as_ref())
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| match email {
+        Some(email) if is_duplicate_email(&e) => {
+            UserAdminError::DuplicateEmail(email.as_ref().to_owned())
+        }
+        _ => UserAdminError::UnexpectedError(
+            anyhow::Error::new(e).context("Failed to insert the new user"),
+        ),
+    })?
+    .rows_affected();
+    if n_inserted == 0 {
+        return Err(UserAdminError::DuplicateUsername(username.to_owned()));
+    }
+    Ok(user_id)
+}
+
+/// 删除用户以及只属于这个用户的数据(会话、保存的幂等响应)
+#[tracing::instrument(name = "Delete a user", skip(pool))]
+pub async fn delete_user(username: &str, pool: &PgPool) -> Result<(), UserAdminError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let user_id = sqlx::query_scalar!(
+        r#"SELECT user_id FROM users WHERE username = $1 FOR UPDATE"#,
+        username
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the user")?
+    .ok_or_else(|| UserAdminError::UnknownUsername(username.to_owned()))?;
+    sqlx::query!(r#"DELETE FROM idempotency WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the user's idempotency keys")?;
+    sqlx::query!(r#"DELETE FROM users WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the user")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "List users", skip(pool))]
+pub async fn list_users(pool: &PgPool) -> Result<Vec<User>, UserAdminError> {
+    sqlx::query!(r#"SELECT user_id, username, role FROM users ORDER BY username"#)
+        .fetch_all(pool)
+        .await
+        .context("Failed to list users")?
+        .into_iter()
+        .map(|row| {
+            Ok(User {
+                user_id: row.user_id,
+                username: row.username,
+                role: Role::parse(&row.role).map_err(anyhow::Error::msg)?,
+            })
+        })
+        .collect()
+}
+
+#[tracing::instrument(name = "Set the role of a user", skip(pool))]
+pub async fn set_role(username: &str, role: Role, pool: &PgPool) -> Result<(), UserAdminError> {
+    let n_updated = sqlx::query!(
+        r#"UPDATE users SET role = $1 WHERE username = $2"#,
+        role.as_str(),
+        username
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the user's role")?
+    .rows_affected();
+    if n_updated == 0 {
+        return Err(UserAdminError::UnknownUsername(username.to_owned()));
+    }
+    Ok(())
+}
+
+/// 设置找回密码时使用的邮箱,`None` 表示清除
+#[tracing::instrument(name = "Set the email of a user", skip(pool))]
+pub async fn set_email(
+    username: &str,
+    email: Option<&SubscriberEmail>,
+    pool: &PgPool,
+) -> Result<(), UserAdminError> {
+    let n_updated = sqlx::query!(
+        r#"UPDATE users SET email = $1 WHERE username = $2"#,
+        email.map(|email| email.as_ref()),
+        username
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| match email {
+        Some(email) if is_duplicate_email(&e) => {
+            UserAdminError::DuplicateEmail(email.as_ref().to_owned())
+        }
+        _ => UserAdminError::UnexpectedError(
+            anyhow::Error::new(e).context("Failed to update the user's email"),
+        ),
+    })?
+    .rows_affected();
+    if n_updated == 0 {
+        return Err(UserAdminError::UnknownUsername(username.to_owned()));
+    }
+    Ok(())
+}
+
+/// 重置密码,并让这个用户所有已登录的会话失效
+#[tracing::instrument(name = "Reset a user's password", skip(password, hashing, pool))]
+pub async fn reset_password(
+    username: &str,
+    password: Password,
+    hashing: PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<(), UserAdminError> {
+    let user_id = sqlx::query_scalar!(r#"SELECT user_id FROM users WHERE username = $1"#, username)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up the user")?
+        .ok_or_else(|| UserAdminError::UnknownUsername(username.to_owned()))?;
+    change_password(user_id, password, hashing, pool).await?;
+    sqlx::query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+        .execute(pool)
+        .await
+        .context("Failed to delete the user's sessions")?;
+    Ok(())
+}
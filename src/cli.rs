@@ -0,0 +1,359 @@
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use secrecy::SecretString;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{
+        Role, create_user, delete_user, list_users, reset_password, set_email, set_role,
+    },
+    configuration::Settings,
+    domain::{Password, SubscriberEmail},
+    startup::{ApplicationBaseUrl, get_connection_pool},
+    subscriber_import::{ImportOptions, SubscriberImport},
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "zero2prod", about = "Newsletter delivery service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// 启动 HTTP 服务和后台 worker(不带子命令时的默认行为)
+    Serve,
+    /// 管理可以登录后台的用户
+    #[command(subcommand)]
+    User(UserCommand),
+    /// 管理订阅者
+    #[command(subcommand)]
+    Subscribers(SubscribersCommand),
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum SubscribersCommand {
+    /// 从 CSV 导入订阅者,需要 `email` 和 `name` 两列,省略文件时从标准输入读取
+    Import {
+        file: Option<PathBuf>,
+        /// 直接确认,不发送确认邮件,必须同时指定 `--consent-source`
+        #[arg(long, requires = "consent_source")]
+        pre_confirmed: bool,
+        /// 同意订阅的来源,例如原来的邮件系统
+        #[arg(long, requires = "pre_confirmed")]
+        consent_source: Option<String>,
+    },
+}
+
+/// 需要密码的子命令从标准输入读取第一行,避免密码出现在 shell 历史和进程列表里
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum UserCommand {
+    /// 创建用户,密码从标准输入读取
+    Create {
+        username: String,
+        /// owner、editor 或 viewer
+        #[arg(long, default_value = "viewer", value_parser = parse_role)]
+        role: Role,
+        /// 找回密码时使用的邮箱
+        #[arg(long, value_parser = parse_email)]
+        email: Option<SubscriberEmail>,
+    },
+    /// 删除用户以及它的会话
+    Delete { username: String },
+    /// 列出所有用户
+    List,
+    /// 重置用户密码,密码从标准输入读取,已登录的会话会失效
+    ResetPassword { username: String },
+    /// 修改用户的角色
+    SetRole {
+        username: String,
+        #[arg(value_parser = parse_role)]
+        role: Role,
+    },
+    /// 设置找回密码时使用的邮箱,省略邮箱表示清除
+    SetEmail {
+        username: String,
+        #[arg(value_parser = parse_email)]
+        email: Option<SubscriberEmail>,
+    },
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    Role::parse(s)
+}
+
+fn parse_email(s: &str) -> Result<SubscriberEmail, String> {
+    SubscriberEmail::parse(s.to_owned())
+}
+
+pub async fn run_user_command(command: UserCommand, config: &Settings) -> anyhow::Result<()> {
+    let pool = get_connection_pool(&config.database);
+    match command {
+        UserCommand::Create {
+            username,
+            role,
+            email,
+        } => {
+            let password = read_password()?;
+            let user_id = create_user(
+                &username,
+                role,
+                email.as_ref(),
+                password,
+                config.password_hashing,
+                &pool,
+            )
+            .await?;
+            audit(&pool, AuditAction::CreateUser, &username).await?;
+            println!("{user_id}");
+        }
+        UserCommand::Delete { username } => {
+            delete_user(&username, &pool).await?;
+            audit(&pool, AuditAction::DeleteUser, &username).await?;
+        }
+        UserCommand::List => {
+            for user in list_users(&pool).await? {
+                println!("{}\t{}\t{}", user.user_id, user.username, user.role);
+            }
+        }
+        UserCommand::ResetPassword { username } => {
+            let password = read_password()?;
+            reset_password(&username, password, config.password_hashing, &pool).await?;
+            audit(&pool, AuditAction::ResetPassword, &username).await?;
+        }
+        UserCommand::SetRole { username, role } => {
+            set_role(&username, role, &pool).await?;
+            audit(&pool, AuditAction::SetRole, &format!("{username}:{role}")).await?;
+        }
+        UserCommand::SetEmail { username, email } => {
+            set_email(&username, email.as_ref(), &pool).await?;
+            audit(&pool, AuditAction::SetEmail, &username).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 命令行的操作没有登录用户,`user_id` 留空
+async fn audit(pool: &PgPool, action: AuditAction, target: &str) -> anyhow::Result<()> {
+    AuditContext::cli()
+        .record(pool, action, Some(target))
+        .await
+        .context("Failed to record the audit log entry")
+}
+
+pub async fn run_subscribers_command(
+    command: SubscribersCommand,
+    config: &Settings,
+) -> anyhow::Result<()> {
+    let pool = get_connection_pool(&config.database);
+    match command {
+        SubscribersCommand::Import {
+            file,
+            pre_confirmed,
+            consent_source,
+        } => {
+            let mut input: Box<dyn Read> = match &file {
+                Some(file) => Box::new(
+                    std::fs::File::open(file)
+                        .with_context(|| format!("Failed to open {}", file.display()))?,
+                ),
+                None => Box::new(std::io::stdin()),
+            };
+            let email_client = config.email_client.client();
+            let base_url = Arc::new(ApplicationBaseUrl(config.application.base_url.clone()));
+            let mut import = SubscriberImport::new(
+                &pool,
+                email_client,
+                base_url,
+                config.application.subscription_token_expiration(),
+                ImportOptions {
+                    pre_confirmed,
+                    consent_source,
+                },
+            )?;
+            let mut chunk = vec![0; 64 * 1024];
+            loop {
+                let n = input.read(&mut chunk).context("Failed to read the CSV")?;
+                if n == 0 {
+                    break;
+                }
+                import.feed(&chunk[..n]).await?;
+            }
+            let (report, emails) = import.finish().await?;
+            emails.wait().await;
+            let summary = format!(
+                "{} accepted, {} duplicates, {} rejected",
+                report.accepted, report.duplicates, report.rejected
+            );
+            audit(&pool, AuditAction::ImportSubscribers, &summary).await?;
+            eprintln!("{summary}");
+            serde_json::to_writer_pretty(std::io::stdout(), &report)?;
+            println!();
+        }
+    }
+    Ok(())
+}
+
+fn read_password() -> anyhow::Result<Password> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        std::io::stderr().flush()?;
+    }
+    let mut line = String::new();
+    stdin
+        .lock()
+        .read_line(&mut line)
+        .context("Failed to read the password from stdin")?;
+    let password = line.trim_end_matches(['\r', '\n']).to_owned();
+    Password::parse(SecretString::from(password)).map_err(anyhow::Error::msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser};
+
+    use crate::{
+        authentication::Role,
+        cli::{Cli, Command, SubscribersCommand, UserCommand},
+        domain::SubscriberEmail,
+    };
+
+    #[test]
+    fn the_cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn no_subcommand_means_serve() {
+        let cli = Cli::try_parse_from(["zero2prod"]).unwrap();
+        assert_eq!(cli.command, None);
+    }
+
+    #[test]
+    fn user_subcommands_are_parsed() {
+        let cases = [
+            (
+                vec!["zero2prod", "user", "create", "ursula"],
+                UserCommand::Create {
+                    username: "ursula".into(),
+                    role: Role::Viewer,
+                    email: None,
+                },
+            ),
+            (
+                vec!["zero2prod", "user", "create", "ursula", "--role", "owner"],
+                UserCommand::Create {
+                    username: "ursula".into(),
+                    role: Role::Owner,
+                    email: None,
+                },
+            ),
+            (
+                vec![
+                    "zero2prod",
+                    "user",
+                    "create",
+                    "ursula",
+                    "--email",
+                    "ursula@example.com",
+                ],
+                UserCommand::Create {
+                    username: "ursula".into(),
+                    role: Role::Viewer,
+                    email: Some(SubscriberEmail::parse("ursula@example.com".into()).unwrap()),
+                },
+            ),
+            (
+                vec!["zero2prod", "user", "set-email", "ursula"],
+                UserCommand::SetEmail {
+                    username: "ursula".into(),
+                    email: None,
+                },
+            ),
+            (
+                vec!["zero2prod", "user", "set-role", "ursula", "editor"],
+                UserCommand::SetRole {
+                    username: "ursula".into(),
+                    role: Role::Editor,
+                },
+            ),
+            (
+                vec!["zero2prod", "user", "delete", "ursula"],
+                UserCommand::Delete {
+                    username: "ursula".into(),
+                },
+            ),
+            (vec!["zero2prod", "user", "list"], UserCommand::List),
+            (
+                vec!["zero2prod", "user", "reset-password", "ursula"],
+                UserCommand::ResetPassword {
+                    username: "ursula".into(),
+                },
+            ),
+        ];
+        for (args, expected) in cases {
+            let cli = Cli::try_parse_from(&args).unwrap();
+            assert_eq!(cli.command, Some(Command::User(expected)), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn create_requires_a_username() {
+        assert!(Cli::try_parse_from(["zero2prod", "user", "create"]).is_err());
+    }
+
+    #[test]
+    fn invalid_emails_are_rejected() {
+        let args = ["zero2prod", "user", "set-email", "ursula", "not-an-email"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn import_reads_a_file_or_stdin() {
+        let cli = Cli::try_parse_from(["zero2prod", "subscribers", "import", "list.csv"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Subscribers(SubscribersCommand::Import {
+                file: Some("list.csv".into()),
+                pre_confirmed: false,
+                consent_source: None,
+            }))
+        );
+        let cli = Cli::try_parse_from([
+            "zero2prod",
+            "subscribers",
+            "import",
+            "--pre-confirmed",
+            "--consent-source",
+            "legacy-crm",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Subscribers(SubscribersCommand::Import {
+                file: None,
+                pre_confirmed: true,
+                consent_source: Some("legacy-crm".into()),
+            }))
+        );
+    }
+
+    #[test]
+    fn pre_confirmed_imports_need_a_consent_source() {
+        let args = ["zero2prod", "subscribers", "import", "--pre-confirmed"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected() {
+        let args = ["zero2prod", "user", "set-role", "ursula", "admin"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+}
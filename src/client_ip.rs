@@ -0,0 +1,90 @@
+use std::net::IpAddr;
+
+use actix_web::{HttpRequest, web};
+
+use crate::startup::TrustedProxies;
+
+/// 发起请求的客户端地址,用于登录限流、审计日志和同意记录
+///
+/// 部署在反向代理后面时,对端地址是代理自己的。每一层代理都会把它看到的对端追加到
+/// `X-Forwarded-For` 末尾,所以从右往左数第 `n` 个才是可信代理看到的客户端,
+/// 更靠左的部分可能是客户端自己伪造的。头缺失或格式不对时退回到对端地址。
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer_ip = req.peer_addr().map(|addr| addr.ip());
+    let trusted_proxies = req
+        .app_data::<web::Data<TrustedProxies>>()
+        .map_or(0, |trusted| trusted.0);
+    if trusted_proxies == 0 {
+        return peer_ip;
+    }
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    forwarded
+        .len()
+        .checked_sub(trusted_proxies)
+        .and_then(|i| forwarded[i].parse().ok())
+        .or(peer_ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use actix_web::{test::TestRequest, web};
+
+    use super::client_ip;
+    use crate::startup::TrustedProxies;
+
+    const PROXY: &str = "10.0.0.1:443";
+
+    fn request(trusted_proxies: usize, forwarded_for: Option<&str>) -> TestRequest {
+        let request = TestRequest::default()
+            .peer_addr(PROXY.parse::<SocketAddr>().unwrap())
+            .app_data(web::Data::new(TrustedProxies(trusted_proxies)));
+        match forwarded_for {
+            Some(value) => request.insert_header(("X-Forwarded-For", value)),
+            None => request,
+        }
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn the_header_is_ignored_without_trusted_proxies() {
+        let req = request(0, Some("203.0.113.9")).to_http_request();
+        assert_eq!(client_ip(&req), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn the_address_added_by_the_trusted_proxy_is_used() {
+        let req = request(1, Some("203.0.113.9")).to_http_request();
+        assert_eq!(client_ip(&req), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn addresses_spoofed_by_the_client_are_skipped() {
+        let req = request(1, Some("198.51.100.1, 203.0.113.9")).to_http_request();
+        assert_eq!(client_ip(&req), ip("203.0.113.9"));
+        let req = request(2, Some("198.51.100.1, 203.0.113.9, 10.0.0.2")).to_http_request();
+        assert_eq!(client_ip(&req), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn a_missing_or_malformed_header_falls_back_to_the_peer() {
+        assert_eq!(
+            client_ip(&request(1, None).to_http_request()),
+            ip("10.0.0.1")
+        );
+        let req = request(2, Some("203.0.113.9")).to_http_request();
+        assert_eq!(client_ip(&req), ip("10.0.0.1"));
+        let req = request(1, Some("not-an-ip")).to_http_request();
+        assert_eq!(client_ip(&req), ip("10.0.0.1"));
+    }
+}
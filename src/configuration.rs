@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use secrecy::{ExposeSecret, SecretString};
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
@@ -5,12 +7,15 @@ use sqlx::ConnectOptions;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailProvider, FileDropClient, PostmarkClient, RetryPolicy, SmtpClient};
 
 #[derive(Deserialize, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: AoolicationSettings,
     pub email_client: EmailClientSetting,
+    pub password_hashing: PasswordHashingSettings,
+    pub login_throttling: LoginThrottlingSettings,
 }
 #[derive(Deserialize, Debug)]
 pub struct DatabaseSettings {
@@ -29,17 +34,151 @@ pub struct AoolicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub idempotency_expiration_seconds: u64,
+    pub subscription_token_expiration_seconds: u64,
+    pub session_expiration_seconds: u64,
+    pub password_reset_expiration_seconds: u64,
+    /// 订阅者查看或擦除自己数据的签名链接的有效期
+    pub subscriber_data_link_expiration_seconds: u64,
+    /// 当前发布的隐私政策版本,记录在每一条同意记录里
+    pub privacy_policy_version: String,
+    /// 前面有几层可信的反向代理,用来从 `X-Forwarded-For` 中取出客户端地址
+    /// 0 表示直接对外,忽略这个头
+    pub trusted_proxies: usize,
+    /// 用于签名退订链接等不落库的令牌,至少 32 字节
+    /// 不写在 base.yaml 里,生产环境通过 `APP_APPLICATION__HMAC_SECRET` 设置
+    pub hmac_secret: SecretString,
+}
+
+impl AoolicationSettings {
+    pub fn idempotency_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idempotency_expiration_seconds)
+    }
+
+    pub fn subscription_token_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.subscription_token_expiration_seconds)
+    }
+
+    pub fn session_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.session_expiration_seconds)
+    }
+
+    pub fn password_reset_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.password_reset_expiration_seconds)
+    }
+
+    pub fn subscriber_data_link_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.subscriber_data_link_expiration_seconds)
+    }
+}
+
+/// 计算新密码哈希时使用的 Argon2id 参数
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct PasswordHashingSettings {
+    pub memory_size_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(
+            self.memory_size_kib,
+            self.iterations,
+            self.parallelism,
+            None,
+        )
+    }
+}
+
+/// 登录失败的限制,按用户名和客户端 IP 分别计数
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoginThrottlingSettings {
+    /// 连续失败超过这个次数后,每次校验前等待的时间逐次翻倍
+    pub delay_after_failures: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub username_lockout_after_failures: u32,
+    /// 多个用户可能共用一个出口 IP,阈值要比用户名的高
+    pub ip_lockout_after_failures: u32,
+    pub lockout_seconds: u64,
+    /// 超过这个时间没有新的失败,计数重新开始
+    pub failure_window_seconds: u64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct EmailClientSetting {
+    pub provider: EmailProviderKind,
     pub sender_email: String,
     pub base_url: String,
     pub authorization_token: SecretString,
     pub timeout_milliseconds: u64,
+    pub max_retries: u32,
+    pub retry_base_delay_milliseconds: u64,
+    pub retry_max_delay_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file_drop_directory: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProviderKind {
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    pub require_tls: bool,
 }
 
 impl EmailClientSetting {
+    /// 根据 `provider` 创建对应的发送后端
+    pub fn client(&self) -> Arc<dyn EmailProvider> {
+        let sender = self.sender().expect("Invalid sender email address");
+        match self.provider {
+            EmailProviderKind::Postmark => Arc::new(PostmarkClient::new(
+                self.base_url.clone(),
+                sender,
+                self.authorization_token.clone(),
+                self.timeout(),
+                self.retry_policy(),
+            )),
+            EmailProviderKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .as_ref()
+                    .expect("Missing `smtp` settings for the SMTP email provider");
+                let credentials = smtp.username.clone().zip(smtp.password.clone());
+                Arc::new(
+                    SmtpClient::new(
+                        &smtp.host,
+                        smtp.port,
+                        credentials,
+                        smtp.require_tls,
+                        sender,
+                        self.timeout(),
+                        self.retry_policy(),
+                    )
+                    .expect("Failed to build the SMTP transport"),
+                )
+            }
+            EmailProviderKind::File => {
+                let directory = self
+                    .file_drop_directory
+                    .as_ref()
+                    .expect("Missing `file_drop_directory` for the file email provider");
+                Arc::new(FileDropClient::new(directory, sender))
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -47,6 +186,14 @@ impl EmailClientSetting {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: std::time::Duration::from_millis(self.retry_base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.retry_max_delay_milliseconds),
+        }
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -62,7 +209,6 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .expect("Failed to parse APP_ENVIRONMENT");
 
     let enviroment_filname = format!("{}.yaml", environment.as_str());
-    println!("{:?}", environment.as_str());
     let settings = config::Config::builder()
         .add_source(config::File::from(
             configuration_directory.join("base.yaml"),
@@ -77,9 +223,18 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    if settings.application.hmac_secret.expose_secret().len() < MIN_HMAC_SECRET_LENGTH {
+        return Err(config::ConfigError::Message(format!(
+            "application.hmac_secret must be at least {MIN_HMAC_SECRET_LENGTH} bytes long"
+        )));
+    }
+    Ok(settings)
 }
 
+/// HMAC-SHA3-256 的密钥不应短于输出长度
+const MIN_HMAC_SECRET_LENGTH: usize = 32;
+
 impl DatabaseSettings {
     pub fn without_db(&self) -> PgConnectOptions {
         let ssl_mode = if self.require_ssl {
@@ -90,7 +245,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -0,0 +1,238 @@
+use actix_web::{HttpRequest, http::header::USER_AGENT};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{client_ip::client_ip, startup::PrivacyPolicyVersion};
+
+/// 订阅表单没有带来源时使用
+pub const DEFAULT_SUBSCRIBE_SOURCE: &str = "subscribe_form";
+
+const MAX_SOURCE_LENGTH: usize = 100;
+/// 客户端可以发送任意长的 User-Agent,只保留前面一部分
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// 表单或其他来源的标识,例如 `homepage_footer` 或原来的邮件系统
+pub fn parse_consent_source(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("The consent source cannot be empty.".into());
+    }
+    if s.chars().count() > MAX_SOURCE_LENGTH {
+        return Err(format!(
+            "The consent source must be at most {MAX_SOURCE_LENGTH} characters long."
+        ));
+    }
+    if s.chars().any(char::is_control) {
+        return Err("The consent source cannot contain control characters.".into());
+    }
+    Ok(s.to_owned())
+}
+
+/// 同意发生的环节
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsentKind {
+    /// 提交订阅表单
+    Subscribe,
+    /// 点击确认邮件里的链接
+    Confirm,
+    /// 直接确认的导入,同意是在其他系统里取得的
+    Import,
+}
+
+impl ConsentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentKind::Subscribe => "subscribe",
+            ConsentKind::Confirm => "confirm",
+            ConsentKind::Import => "import",
+        }
+    }
+}
+
+/// 一次同意的证据,写入 `subscription_consents`
+#[derive(Clone, Debug, Default)]
+pub struct ConsentEvidence {
+    pub source: Option<String>,
+    pub privacy_policy_version: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ConsentEvidence {
+    /// 订阅者本人发起的请求,记录客户端和当前的隐私政策版本
+    pub fn for_request(
+        req: &HttpRequest,
+        source: Option<String>,
+        privacy_policy_version: &PrivacyPolicyVersion,
+    ) -> Self {
+        Self {
+            source,
+            privacy_policy_version: Some(privacy_policy_version.0.clone()),
+            ip: client_ip(req).map(|ip| ip.to_string()),
+            user_agent: req
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+        }
+    }
+
+    /// 导入时没有订阅者的请求,只知道来源
+    pub fn imported(source: String) -> Self {
+        Self {
+            source: Some(source),
+            ..Self::default()
+        }
+    }
+
+    /// 管理员在后台手动确认,来源里记下是哪个管理员
+    /// 没有订阅者本人的请求,不记录客户端信息
+    pub fn confirmed_by_admin(admin_id: Option<Uuid>) -> Self {
+        let source = match admin_id {
+            Some(admin_id) => format!("admin:{admin_id}"),
+            None => "admin".to_owned(),
+        };
+        Self {
+            source: Some(source),
+            ..Self::default()
+        }
+    }
+
+    /// 在调用方的事务中追加一条记录
+    /// 同时保存订阅者当前的邮箱,订阅者被删除后记录仍然可以查到
+    #[tracing::instrument(name = "Record consent", skip(self, executor))]
+    pub async fn record(
+        &self,
+        executor: impl PgExecutor<'_>,
+        subscriber_id: Uuid,
+        kind: ConsentKind,
+    ) -> Result<(), sqlx::Error> {
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO subscription_consents
+                (subscriber_id, email, kind, source, privacy_policy_version, ip, user_agent)
+            SELECT id, email, $2, $3, $4, $5, $6
+            FROM subscriptions
+            WHERE id = $1
+            "#,
+            subscriber_id,
+            kind.as_str(),
+            self.source,
+            self.privacy_policy_version,
+            self.ip,
+            self.user_agent
+        )
+        .execute(executor)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsentRecord {
+    pub consent_id: i64,
+    /// 订阅者被后台删除后为 `null`
+    pub subscriber_id: Option<Uuid>,
+    pub email: String,
+    pub kind: String,
+    pub source: Option<String>,
+    pub privacy_policy_version: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// 一个订阅者的所有同意记录,按时间先后
+/// 包括同一个邮箱之前被删除的订阅留下的记录
+pub async fn subscriber_consents(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    email: &str,
+) -> Result<Vec<ConsentRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsentRecord,
+        r#"
+        SELECT consent_id, subscriber_id, email, kind, source, privacy_policy_version, ip,
+            user_agent, recorded_at
+        FROM subscription_consents
+        WHERE subscriber_id = $1 OR email = $2
+        ORDER BY consent_id
+        "#,
+        subscriber_id,
+        email
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[derive(Debug, Default)]
+pub struct ConsentFilters {
+    pub subscriber_id: Option<Uuid>,
+    pub email: Option<String>,
+    pub kind: Option<ConsentKind>,
+    pub source: Option<String>,
+    pub privacy_policy_version: Option<String>,
+}
+
+/// 按时间倒序分页查询,`before` 是上一页最后一条记录的 id
+#[tracing::instrument(name = "Query consent records", skip(pool))]
+pub async fn query_consents(
+    pool: &PgPool,
+    before: Option<i64>,
+    limit: i64,
+    filters: &ConsentFilters,
+) -> Result<Vec<ConsentRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsentRecord,
+        r#"
+        SELECT consent_id, subscriber_id, email, kind, source, privacy_policy_version, ip,
+            user_agent, recorded_at
+        FROM subscription_consents
+        WHERE ($1::bigint IS NULL OR consent_id < $1)
+            AND ($2::uuid IS NULL OR subscriber_id = $2)
+            AND ($3::text IS NULL OR kind = $3)
+            AND ($4::text IS NULL OR source = $4)
+            AND ($5::text IS NULL OR privacy_policy_version = $5)
+            AND ($7::text IS NULL OR email = $7)
+        ORDER BY consent_id DESC
+        LIMIT $6
+        "#,
+        before,
+        filters.subscriber_id,
+        filters.kind.map(|kind| kind.as_str()),
+        filters.source,
+        filters.privacy_policy_version,
+        limit,
+        filters.email
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok_eq};
+
+    use super::parse_consent_source;
+
+    #[test]
+    fn sources_are_trimmed() {
+        assert_ok_eq!(
+            parse_consent_source("  homepage_footer "),
+            "homepage_footer".to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_sources_are_rejected() {
+        assert_err!(parse_consent_source(" "));
+        assert_err!(parse_consent_source(&"a".repeat(101)));
+        assert_err!(parse_consent_source("line\nbreak"));
+    }
+}
@@ -5,9 +5,17 @@
  * @FilePath: /zero2prod/src/domain/mod.rs
  */
 mod new_subscriber;
+mod password;
+mod subscriber_data_token;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
+mod unsubscribe_token;
 
 pub use new_subscriber::NewSubscriber;
+pub use password::Password;
+pub use subscriber_data_token::SubscriberDataToken;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubScriberName;
+pub use subscription_status::SubscriptionStatus;
+pub use unsubscribe_token::UnsubscribeToken;
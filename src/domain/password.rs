@@ -0,0 +1,81 @@
+use secrecy::{ExposeSecret, SecretString};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// 满足密码策略的新密码
+/// 长度在 12 到 128 个字符之间,不能全是空白
+#[derive(Debug)]
+pub struct Password(SecretString);
+
+impl Password {
+    pub const MIN_LENGTH: usize = 12;
+    pub const MAX_LENGTH: usize = 128;
+
+    pub fn parse(s: SecretString) -> Result<Self, String> {
+        let password = s.expose_secret();
+        let length = password.graphemes(true).count();
+        if password.trim().is_empty() {
+            Err("The password cannot be empty.".into())
+        } else if length < Self::MIN_LENGTH {
+            Err(format!(
+                "The password must be at least {} characters long.",
+                Self::MIN_LENGTH
+            ))
+        } else if length > Self::MAX_LENGTH {
+            Err(format!(
+                "The password must be at most {} characters long.",
+                Self::MAX_LENGTH
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<SecretString> for Password {
+    fn as_ref(&self) -> &SecretString {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use secrecy::SecretString;
+
+    use crate::domain::Password;
+
+    fn secret(s: &str) -> SecretString {
+        SecretString::from(s.to_string())
+    }
+
+    #[test]
+    fn a_12_character_password_is_valid() {
+        assert_ok!(Password::parse(secret(&"a".repeat(12))));
+    }
+
+    #[test]
+    fn a_password_shorter_than_12_characters_is_rejected() {
+        assert_err!(Password::parse(secret(&"a".repeat(11))));
+    }
+
+    #[test]
+    fn a_128_character_password_is_valid() {
+        assert_ok!(Password::parse(secret(&"a".repeat(128))));
+    }
+
+    #[test]
+    fn a_password_longer_than_128_characters_is_rejected() {
+        assert_err!(Password::parse(secret(&"a".repeat(129))));
+    }
+
+    #[test]
+    fn whitespace_only_passwords_are_rejected() {
+        assert_err!(Password::parse(secret(&" ".repeat(20))));
+    }
+
+    #[test]
+    fn length_is_counted_in_graphemes() {
+        // 11 个字素,但 UTF-8 编码后远超 12 字节
+        assert_err!(Password::parse(secret(&"ü".repeat(11))));
+    }
+}
@@ -0,0 +1,155 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha3::Sha3_256;
+use uuid::Uuid;
+
+type HmacSha3 = Hmac<Sha3_256>;
+
+/// 订阅者查看或擦除自己数据的链接中的令牌:
+/// base64url(subscriber_id || expires_at || HMAC(subscriber_id || expires_at))
+/// 和退订令牌一样不落库,但是会过期
+#[derive(Debug)]
+pub struct SubscriberDataToken(String);
+
+impl SubscriberDataToken {
+    pub fn generate(
+        subscriber_id: Uuid,
+        expires_at: DateTime<Utc>,
+        hmac_secret: &SecretString,
+    ) -> Self {
+        let expires_at = expires_at.timestamp().to_be_bytes();
+        let mut payload = subscriber_id.as_bytes().to_vec();
+        payload.extend_from_slice(&expires_at);
+        payload.extend_from_slice(
+            &mac(subscriber_id, expires_at, hmac_secret)
+                .finalize()
+                .into_bytes(),
+        );
+        Self(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload))
+    }
+
+    /// 校验签名和有效期,返回令牌对应的订阅者
+    pub fn verify(
+        token: &str,
+        hmac_secret: &SecretString,
+        now: DateTime<Utc>,
+    ) -> Result<Uuid, String> {
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| "The link is not valid.".to_string())?;
+        if payload.len() <= 24 {
+            return Err("The link is not valid.".into());
+        }
+        let (id, rest) = payload.split_at(16);
+        let (expires_at, tag) = rest.split_at(8);
+        let subscriber_id = Uuid::from_slice(id).map_err(|e| e.to_string())?;
+        let expires_at: [u8; 8] = expires_at.try_into().unwrap();
+        // verify_slice 是常数时间比较
+        mac(subscriber_id, expires_at, hmac_secret)
+            .verify_slice(tag)
+            .map_err(|_| "The link is not valid.".to_string())?;
+        if i64::from_be_bytes(expires_at) <= now.timestamp() {
+            return Err("The link has expired. Please request a new one.".into());
+        }
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, expires_at: [u8; 8], hmac_secret: &SecretString) -> HmacSha3 {
+    let mut mac = HmacSha3::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // 用途前缀和退订令牌不同,两种令牌不能互相替代
+    mac.update(b"subscriber-data:");
+    mac.update(subscriber_id.as_bytes());
+    mac.update(&expires_at);
+    mac
+}
+
+impl AsRef<str> for SubscriberDataToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use chrono::{Duration, Utc};
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::SecretString;
+    use uuid::Uuid;
+
+    use crate::domain::{SubscriberDataToken, UnsubscribeToken};
+
+    fn secret() -> SecretString {
+        SecretString::from(Uuid::new_v4().to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_verified_until_it_expires() {
+        let secret = secret();
+        let subscriber_id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = SubscriberDataToken::generate(subscriber_id, now + Duration::hours(1), &secret);
+        assert_ok_eq!(
+            SubscriberDataToken::verify(token.as_ref(), &secret, now),
+            subscriber_id
+        );
+        assert_err!(SubscriberDataToken::verify(
+            token.as_ref(),
+            &secret,
+            now + Duration::hours(2)
+        ));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = SubscriberDataToken::generate(
+            Uuid::new_v4(),
+            Utc::now() + Duration::hours(1),
+            &secret(),
+        );
+        assert_err!(SubscriberDataToken::verify(
+            token.as_ref(),
+            &secret(),
+            Utc::now()
+        ));
+    }
+
+    #[test]
+    fn extending_the_expiry_breaks_the_signature() {
+        let secret = secret();
+        let now = Utc::now();
+        let token =
+            SubscriberDataToken::generate(Uuid::new_v4(), now - Duration::hours(1), &secret);
+        let mut payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token.as_ref())
+            .unwrap();
+        payload[16..24].copy_from_slice(&(now + Duration::days(365)).timestamp().to_be_bytes());
+        let forged = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload);
+        assert_err!(SubscriberDataToken::verify(&forged, &secret, now));
+    }
+
+    #[test]
+    fn unsubscribe_tokens_are_not_accepted() {
+        let secret = secret();
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret);
+        assert_err!(SubscriberDataToken::verify(
+            token.as_ref(),
+            &secret,
+            Utc::now()
+        ));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(SubscriberDataToken::verify(
+            "not a token",
+            &secret(),
+            Utc::now()
+        ));
+        assert_err!(SubscriberDataToken::verify("", &secret(), Utc::now()));
+    }
+}
@@ -0,0 +1,101 @@
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha3::Sha3_256;
+use uuid::Uuid;
+
+type HmacSha3 = Hmac<Sha3_256>;
+
+/// 退订链接中的令牌: base64url(subscriber_id || HMAC(subscriber_id))
+/// 不需要落库,只要密钥不变,同一个订阅者的令牌始终有效
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, hmac_secret: &SecretString) -> Self {
+        let mut payload = subscriber_id.as_bytes().to_vec();
+        payload.extend_from_slice(&mac(subscriber_id, hmac_secret).finalize().into_bytes());
+        Self(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload))
+    }
+
+    /// 校验签名,返回令牌对应的订阅者
+    pub fn verify(token: &str, hmac_secret: &SecretString) -> Result<Uuid, String> {
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| "The unsubscribe token is not valid base64.".to_string())?;
+        if payload.len() <= 16 {
+            return Err("The unsubscribe token is too short.".into());
+        }
+        let (id, tag) = payload.split_at(16);
+        let subscriber_id = Uuid::from_slice(id).map_err(|e| e.to_string())?;
+        // verify_slice 是常数时间比较
+        mac(subscriber_id, hmac_secret)
+            .verify_slice(tag)
+            .map_err(|_| "The unsubscribe token signature is invalid.".to_string())?;
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, hmac_secret: &SecretString) -> HmacSha3 {
+    let mut mac = HmacSha3::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // 加上用途前缀,避免和其他地方的签名混用
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::SecretString;
+    use uuid::Uuid;
+
+    use crate::domain::UnsubscribeToken;
+
+    fn secret() -> SecretString {
+        SecretString::from(Uuid::new_v4().to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_verified() {
+        let secret = secret();
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret);
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret());
+        assert_err!(UnsubscribeToken::verify(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let secret = secret();
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret);
+        let mut payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token.as_ref())
+            .unwrap();
+        payload[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+        let forged = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload);
+        assert_err!(UnsubscribeToken::verify(&forged, &secret));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(UnsubscribeToken::verify("not a token", &secret()));
+        assert_err!(UnsubscribeToken::verify("", &secret()));
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailMessage, EmailProvider, SendEmailError, build_mime_message},
+};
+
+/// 开发用的后端,不真正发送邮件,而是把每封邮件写成一个 `.eml` 文件
+pub struct FileDropClient {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+impl FileDropClient {
+    pub fn new(directory: impl Into<PathBuf>, sender: SubscriberEmail) -> Self {
+        Self {
+            directory: directory.into(),
+            sender,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for FileDropClient {
+    #[tracing::instrument(name = "Write an email to disk", skip_all, fields(recipient = %message.recipient))]
+    async fn send_message(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError> {
+        let message = build_mime_message(&self.sender, message)?;
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .map_err(SendEmailError::Io)?;
+        // 时间戳开头,按文件名排序就是发送顺序
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            uuid::Uuid::new_v4()
+        );
+        tokio::fs::write(self.directory.join(file_name), message.formatted())
+            .await
+            .map_err(SendEmailError::Io)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use fake::{Fake, faker::internet::en::SafeEmail};
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailMessage, EmailProvider, FileDropClient},
+    };
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let client = FileDropClient::new(&directory, email());
+        let recipient = email();
+
+        let outcome = client
+            .send_email(&recipient, "Newsletter", "<p>html body</p>", "text body")
+            .await;
+        assert_ok!(outcome);
+
+        let files = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains(&format!("To: {}", recipient.as_ref())));
+        assert!(content.contains("Subject: Newsletter"));
+        assert!(content.contains("text body"));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_message_writes_custom_headers() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let client = FileDropClient::new(&directory, email());
+        let headers = [(
+            "List-Unsubscribe-Post".to_string(),
+            "List-Unsubscribe=One-Click".to_string(),
+        )];
+
+        let outcome = client
+            .send_message(&EmailMessage {
+                recipient: &email(),
+                subject: "Newsletter",
+                html_content: "html",
+                text_content: "text",
+                headers: &headers,
+            })
+            .await;
+        assert_ok!(outcome);
+
+        let file = std::fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let content = std::fs::read_to_string(file).unwrap();
+        assert!(content.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+mod file;
+mod postmark;
+mod smtp;
+
+use std::time::Duration;
+
+use lettre::message::{
+    Mailbox, MultiPart,
+    header::{HeaderName, HeaderValue},
+};
+use rand::Rng;
+use reqwest::StatusCode;
+
+use crate::domain::SubscriberEmail;
+
+pub use file::FileDropClient;
+pub use postmark::{PostmarkClient, PostmarkError};
+pub use smtp::SmtpClient;
+
+/// 单次批量发送的最大邮件数,与 Postmark `/email/batch` 的限制一致
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// 邮件发送后端
+/// 路由和后台 worker 只依赖这个 trait,具体使用哪个后端由配置中的 `provider` 决定
+#[async_trait::async_trait]
+pub trait EmailProvider: Send + Sync {
+    async fn send_message(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send_message(&EmailMessage {
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers: &[],
+        })
+        .await
+    }
+
+    /// 返回的结果与 `messages` 一一对应,单个收件人失败不会影响同一批次的其他人
+    /// 默认逐封发送,支持批量接口的后端可以覆盖
+    async fn send_batch(
+        &self,
+        messages: &[EmailMessage<'_>],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let mut outcomes = Vec::with_capacity(messages.len());
+        for message in messages {
+            outcomes.push(self.send_message(message).await);
+        }
+        Ok(outcomes)
+    }
+}
+
+/// 一封待发送的邮件
+pub struct EmailMessage<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    /// 额外的邮件头,比如 `List-Unsubscribe`
+    pub headers: &'a [(String, String)],
+}
+
+/// 可重试错误的退避策略
+/// 第 n 次重试前等待 `min(max_delay, base_delay * 2^n)`,再随机抖动到一半到全部之间
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let half = exponential / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    /// 执行 `f`,遇到可重试的错误时按退避策略重试
+    pub async fn retry<T, F, Fut>(&self, mut f: F) -> Result<T, SendEmailError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, SendEmailError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Ok(t) => return Ok(t),
+                Err(e) if e.is_retryable() && attempt < self.max_retries => {
+                    let delay = self.backoff(attempt);
+                    tracing::warn!(
+                        error.message = %e,
+                        attempt,
+                        ?delay,
+                        "Failed to send an email, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error("Timed out while waiting for the email API")]
+    Timeout(#[source] reqwest::Error),
+    #[error("Failed to reach the email API")]
+    Transport(#[source] reqwest::Error),
+    #[error("The email API is rate limiting us: {0}")]
+    RateLimited(PostmarkError),
+    #[error("The email API failed with {0}: {1}")]
+    ServerError(StatusCode, PostmarkError),
+    #[error("The email API rejected the recipient: {0}")]
+    InvalidRecipient(PostmarkError),
+    #[error("The email API rejected our server token: {0}")]
+    Unauthorized(PostmarkError),
+    #[error("The email API rejected the request with {0}: {1}")]
+    Rejected(StatusCode, PostmarkError),
+    #[error("Unexpected response from the email API: {0}")]
+    UnexpectedResponse(String),
+    #[error("The SMTP server failed to accept the email")]
+    Smtp(#[source] lettre::transport::smtp::Error),
+    #[error("Failed to build the email: {0}")]
+    InvalidMessage(String),
+    #[error("Failed to write the email to disk")]
+    Io(#[source] std::io::Error),
+}
+
+impl SendEmailError {
+    /// 超时、429、5xx 以及 SMTP 的 4xx 是暂时性的,其余的重试也不会成功
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout(_)
+            | Self::Transport(_)
+            | Self::RateLimited(_)
+            | Self::ServerError(..) => true,
+            Self::Smtp(e) => !e.is_permanent() && !e.is_client(),
+            Self::InvalidRecipient(_)
+            | Self::Unauthorized(_)
+            | Self::Rejected(..)
+            | Self::UnexpectedResponse(_)
+            | Self::InvalidMessage(_)
+            | Self::Io(_) => false,
+        }
+    }
+
+    /// 后端返回的错误码, Postmark 的 `ErrorCode` 或 SMTP 的回复码
+    pub fn error_code(&self) -> Option<i64> {
+        match self {
+            Self::RateLimited(e)
+            | Self::ServerError(_, e)
+            | Self::InvalidRecipient(e)
+            | Self::Unauthorized(e)
+            | Self::Rejected(_, e) => Some(e.error_code),
+            Self::Smtp(e) => e.status().and_then(|code| code.to_string().parse().ok()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e)
+        } else {
+            Self::Transport(e)
+        }
+    }
+}
+
+/// SMTP 和文件后端共用的 MIME 邮件,同时包含纯文本和 HTML 两个版本
+fn build_mime_message(
+    sender: &SubscriberEmail,
+    message: &EmailMessage<'_>,
+) -> Result<lettre::Message, SendEmailError> {
+    let parse_mailbox = |email: &SubscriberEmail| {
+        email
+            .as_ref()
+            .parse::<Mailbox>()
+            .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))
+    };
+    let mut builder = lettre::Message::builder()
+        .from(parse_mailbox(sender)?)
+        .to(parse_mailbox(message.recipient)?)
+        .subject(message.subject);
+    for (name, value) in message.headers {
+        let name = HeaderName::new_from_ascii(name.clone())
+            .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))?;
+        builder = builder.raw_header(HeaderValue::new(name, value.clone()));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            message.text_content.to_owned(),
+            message.html_content.to_owned(),
+        ))
+        .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::RetryPolicy;
+
+    #[test]
+    fn backoff_is_bounded_by_the_max_delay() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_secs(1),
+        };
+        for attempt in 0..10 {
+            let exponential = std::time::Duration::from_millis(100 * 2u64.pow(attempt))
+                .min(std::time::Duration::from_secs(1));
+            let delay = policy.backoff(attempt);
+            assert!(delay <= exponential);
+            assert!(delay >= exponential / 2);
+        }
+    }
+}
@@ -0,0 +1,636 @@
+/*
+ * @Date: 2025-07-15 10:36:14
+ * @LastEditors: myclooe 994386508@qq.com
+ * @LastEditTime: 2025-07-20 22:18:37
+ * @FilePath: /zero2prod/src/email_client/postmark.rs
+ */
+
+use core::str;
+
+use actix_web::web::Bytes;
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailMessage, EmailProvider, MAX_BATCH_SIZE, RetryPolicy, SendEmailError},
+};
+
+pub struct PostmarkClient {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization: SecretString,
+    retry_policy: RetryPolicy,
+}
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<PostmarkHeader<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(sender: &'a SubscriberEmail, message: &EmailMessage<'a>) -> Self {
+        Self {
+            from: sender.as_ref(),
+            to: message.recipient.as_ref(),
+            subject: message.subject,
+            html_body: message.html_content,
+            text_body: message.text_content,
+            headers: message
+                .headers
+                .iter()
+                .map(|(name, value)| PostmarkHeader { name, value })
+                .collect(),
+        }
+    }
+}
+
+/// Postmark 在请求失败时返回的 JSON,批量接口中每封邮件的结果也是这个格式
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkError {
+    #[serde(default)]
+    pub error_code: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for PostmarkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (ErrorCode {})", self.message, self.error_code)
+    }
+}
+
+/// 根据状态码和 Postmark 返回的内容区分错误类型
+fn error_from_response(status: StatusCode, body: &[u8]) -> SendEmailError {
+    // 响应体不一定是 Postmark 的格式(比如网关返回的 502),这时保留原始内容
+    let error = serde_json::from_slice(body).unwrap_or_else(|_| PostmarkError {
+        error_code: 0,
+        message: String::from_utf8_lossy(body).into_owned(),
+    });
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => SendEmailError::RateLimited(error),
+        StatusCode::UNPROCESSABLE_ENTITY => SendEmailError::InvalidRecipient(error),
+        StatusCode::UNAUTHORIZED => SendEmailError::Unauthorized(error),
+        s if s.is_server_error() => SendEmailError::ServerError(s, error),
+        s => SendEmailError::Rejected(s, error),
+    }
+}
+
+impl PostmarkClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization: SecretString,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            base_url,
+            sender,
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            authorization,
+            retry_policy,
+        }
+    }
+
+    async fn post<T: Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<Bytes, SendEmailError> {
+        let url = format!("{}/{}", self.base_url, path);
+        let response = self
+            .http_client
+            .post(url)
+            .header(
+                "x-Postmark-Server-Token",
+                self.authorization.expose_secret(),
+            )
+            .json(body)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(error_from_response(status, &body))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for PostmarkClient {
+    #[tracing::instrument(name = "Send an email", skip_all, fields(recipient = %message.recipient))]
+    async fn send_message(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError> {
+        let send_email_request = SendEmailRequest::new(&self.sender, message);
+        self.retry_policy
+            .retry(|| async {
+                self.post("email", &send_email_request).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// 通过 `/email/batch` 一次发送多封邮件,每次请求最多 `MAX_BATCH_SIZE` 封
+    #[tracing::instrument(name = "Send a batch of emails", skip_all, fields(n_messages = messages.len()))]
+    async fn send_batch(
+        &self,
+        messages: &[EmailMessage<'_>],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let mut outcomes = Vec::with_capacity(messages.len());
+        for chunk in messages.chunks(MAX_BATCH_SIZE) {
+            let send_email_requests = chunk
+                .iter()
+                .map(|message| SendEmailRequest::new(&self.sender, message))
+                .collect::<Vec<_>>();
+            let responses = self
+                .retry_policy
+                .retry(|| async {
+                    let body = self.post("email/batch", &send_email_requests).await?;
+                    serde_json::from_slice::<Vec<PostmarkError>>(&body)
+                        .map_err(|e| SendEmailError::UnexpectedResponse(e.to_string()))
+                })
+                .await?;
+            if responses.len() != chunk.len() {
+                return Err(SendEmailError::UnexpectedResponse(format!(
+                    "Expected {} results from the batch endpoint, got {}",
+                    chunk.len(),
+                    responses.len()
+                )));
+            }
+            // ErrorCode 为 0 表示这封邮件已被接受
+            // 单封邮件的错误等同于 `/email` 返回的 422
+            outcomes.extend(responses.into_iter().map(|response| {
+                if response.error_code == 0 {
+                    Ok(())
+                } else {
+                    Err(SendEmailError::InvalidRecipient(response))
+                }
+            }));
+        }
+        Ok(outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use claim::{assert_err, assert_ok};
+    use fake::{
+        Fake,
+        faker::{internet::en::SafeEmail, lorem::en::Sentence},
+    };
+    use secrecy::SecretString;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{any, body_partial_json, header, header_exists, method, path},
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailMessage, EmailProvider, PostmarkClient, RetryPolicy, SendEmailError},
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, serde_json::Error> =
+                serde_json::from_slice(&request.body);
+
+            if let Ok(body) = result {
+                dbg!(&body);
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Sentence(1..10).fake()
+    }
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(uri: String) -> PostmarkClient {
+        PostmarkClient::new(
+            uri,
+            email(),
+            SecretString::new(Sentence(3..10).fake::<String>().into()),
+            std::time::Duration::from_millis(200),
+            retry_policy(),
+        )
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(10),
+        }
+    }
+
+    fn postmark_error(error_code: i64, message: &str) -> serde_json::Value {
+        serde_json::json!({
+            "ErrorCode": error_code,
+            "Message": message
+        })
+    }
+
+    #[tokio::test]
+    async fn send_emial_fires_a_request_to_base_url() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        // 5xx 是可重试的,首次请求加上两次重试
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new("500"))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert!(matches!(
+            assert_err!(outcome),
+            SendEmailError::ServerError(..)
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_tasks_too_long() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new("200").set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert!(matches!(assert_err!(outcome), SendEmailError::Timeout(_)));
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_until_the_server_recovers() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_when_rate_limited() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .set_body_json(postmark_error(429, "Rate limit exceeded")),
+            )
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        let error = assert_err!(outcome);
+        assert!(error.is_retryable());
+        match error {
+            SendEmailError::RateLimited(e) => assert_eq!(e.error_code, 429),
+            e => panic!("Expected a rate limit error, got {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_an_invalid_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(422)
+                    .set_body_json(postmark_error(300, "Invalid 'To' address: 'not-an-email'.")),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        let error = assert_err!(outcome);
+        assert!(!error.is_retryable());
+        match error {
+            SendEmailError::InvalidRecipient(e) => {
+                assert_eq!(e.error_code, 300);
+                assert_eq!(e.message, "Invalid 'To' address: 'not-an-email'.");
+            }
+            e => panic!("Expected an invalid recipient error, got {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_a_bad_server_token() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(401).set_body_json(postmark_error(
+                10,
+                "The Server Token you provided in the X-Postmark-Server-Token request header was invalid.",
+            )))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        let error = assert_err!(outcome);
+        assert!(!error.is_retryable());
+        assert!(matches!(error, SendEmailError::Unauthorized(e) if e.error_code == 10));
+    }
+
+    #[tokio::test]
+    async fn send_email_keeps_a_body_that_is_not_postmark_json() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(400).set_body_string("Bad Request"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        match assert_err!(outcome) {
+            SendEmailError::Rejected(status, e) => {
+                assert_eq!(status.as_u16(), 400);
+                assert_eq!(e.message, "Bad Request");
+            }
+            e => panic!("Expected a rejected request, got {:?}", e),
+        }
+    }
+
+    /// 按请求中的邮件数量返回结果,`failing` 中的收件人返回 Postmark 的 300 错误
+    struct BatchResponder {
+        failing: Vec<String>,
+    }
+
+    impl wiremock::Respond for BatchResponder {
+        fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results = messages
+                .iter()
+                .map(|message| {
+                    let to = message["To"].as_str().unwrap();
+                    if self.failing.iter().any(|f| f == to) {
+                        serde_json::json!({"ErrorCode": 300, "Message": "Invalid 'To' address", "To": to})
+                    } else {
+                        serde_json::json!({"ErrorCode": 0, "Message": "OK", "To": to})
+                    }
+                })
+                .collect::<Vec<_>>();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_returns_a_result_for_each_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = [email(), email(), email()];
+        let (subject, content) = (subject(), content());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(BatchResponder {
+                failing: vec![recipients[1].as_ref().to_owned()],
+            })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let messages = recipients
+            .iter()
+            .map(|recipient| EmailMessage {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: &[],
+            })
+            .collect::<Vec<_>>();
+        let outcomes = assert_ok!(email_client.send_batch(&messages).await);
+
+        assert_eq!(outcomes.len(), 3);
+        assert_ok!(&outcomes[0]);
+        let failure = assert_err!(&outcomes[1]);
+        assert!(matches!(failure, SendEmailError::InvalidRecipient(e) if e.error_code == 300));
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = (0..501).map(|_| email()).collect::<Vec<_>>();
+        let (subject, content) = (subject(), content());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder { failing: vec![] })
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let messages = recipients
+            .iter()
+            .map(|recipient| EmailMessage {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: &[],
+            })
+            .collect::<Vec<_>>();
+        let outcomes = assert_ok!(email_client.send_batch(&messages).await);
+
+        assert_eq!(outcomes.len(), 501);
+        assert!(outcomes.iter().all(|o| o.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_retries_when_the_whole_batch_fails() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder { failing: vec![] })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let messages = [EmailMessage {
+            recipient: &recipient,
+            subject: "subject",
+            html_content: "html",
+            text_content: "text",
+            headers: &[],
+        }];
+        let outcomes = assert_ok!(email_client.send_batch(&messages).await);
+        assert_eq!(outcomes.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_if_the_result_count_does_not_match() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let messages = [EmailMessage {
+            recipient: &recipient,
+            subject: "subject",
+            html_content: "html",
+            text_content: "text",
+            headers: &[],
+        }];
+        let outcome = email_client.send_batch(&messages).await;
+        assert!(matches!(
+            assert_err!(outcome),
+            SendEmailError::UnexpectedResponse(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_message_forwards_custom_headers() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{ "Name": "List-Unsubscribe", "Value": "<https://example.com>" }]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let headers = [(
+            "List-Unsubscribe".to_string(),
+            "<https://example.com>".to_string(),
+        )];
+        let outcome = email_client
+            .send_message(&EmailMessage {
+                recipient: &recipient,
+                subject: "subject",
+                html_content: "html",
+                text_content: "text",
+                headers: &headers,
+            })
+            .await;
+        assert_ok!(outcome);
+    }
+}
@@ -0,0 +1,233 @@
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+    transport::smtp::authentication::Credentials,
+};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailMessage, EmailProvider, RetryPolicy, SendEmailError, build_mime_message},
+};
+
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+    retry_policy: RetryPolicy,
+}
+
+impl SmtpClient {
+    /// `require_tls` 为 false 时使用明文连接,只适用于本地的 SMTP 服务
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, SecretString)>,
+        require_tls: bool,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self, SendEmailError> {
+        let mut builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(SendEmailError::Smtp)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+            retry_policy,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailProvider for SmtpClient {
+    #[tracing::instrument(name = "Send an email over SMTP", skip_all, fields(recipient = %message.recipient))]
+    async fn send_message(&self, message: &EmailMessage<'_>) -> Result<(), SendEmailError> {
+        let message = build_mime_message(&self.sender, message)?;
+        self.retry_policy
+            .retry(|| async {
+                self.transport
+                    .send(message.clone())
+                    .await
+                    .map_err(SendEmailError::Smtp)?;
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use claim::{assert_err, assert_ok};
+    use fake::{Fake, faker::internet::en::SafeEmail};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailMessage, EmailProvider, RetryPolicy, SendEmailError, SmtpClient},
+    };
+
+    /// 一个最简单的 SMTP 服务,记录收到的邮件,并对 `RCPT TO` 返回指定的回复
+    struct SmtpStandIn {
+        port: u16,
+        messages: Arc<Mutex<Vec<String>>>,
+        rcpt_attempts: Arc<Mutex<u32>>,
+    }
+
+    impl SmtpStandIn {
+        async fn start(rcpt_reply: &'static str) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let messages = Arc::new(Mutex::new(vec![]));
+            let rcpt_attempts = Arc::new(Mutex::new(0));
+            let (m, r) = (messages.clone(), rcpt_attempts.clone());
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let (m, r) = (m.clone(), r.clone());
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = stream.into_split();
+                        let mut lines = BufReader::new(reader).lines();
+                        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            let command = line.to_uppercase();
+                            let reply = if command.starts_with("EHLO") {
+                                "250 localhost"
+                            } else if command.starts_with("RCPT") {
+                                *r.lock().unwrap() += 1;
+                                rcpt_reply
+                            } else if command.starts_with("DATA") {
+                                writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                                let mut data = String::new();
+                                while let Ok(Some(line)) = lines.next_line().await {
+                                    if line == "." {
+                                        break;
+                                    }
+                                    data.push_str(&line);
+                                    data.push('\n');
+                                }
+                                m.lock().unwrap().push(data);
+                                "250 Queued"
+                            } else if command.starts_with("QUIT") {
+                                writer.write_all(b"221 Bye\r\n").await.unwrap();
+                                break;
+                            } else {
+                                "250 OK"
+                            };
+                            writer.write_all(reply.as_bytes()).await.unwrap();
+                            writer.write_all(b"\r\n").await.unwrap();
+                        }
+                    });
+                }
+            });
+            Self {
+                port,
+                messages,
+                rcpt_attempts,
+            }
+        }
+
+        fn client(&self) -> SmtpClient {
+            SmtpClient::new(
+                "127.0.0.1",
+                self.port,
+                None,
+                false,
+                email(),
+                std::time::Duration::from_secs(2),
+                RetryPolicy {
+                    max_retries: 2,
+                    base_delay: std::time::Duration::from_millis(1),
+                    max_delay: std::time::Duration::from_millis(10),
+                },
+            )
+            .unwrap()
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message() {
+        let server = SmtpStandIn::start("250 OK").await;
+        let recipient = email();
+
+        let outcome = server
+            .client()
+            .send_email(&recipient, "Newsletter", "<p>html body</p>", "text body")
+            .await;
+        assert_ok!(outcome);
+
+        let messages = server.messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains(&format!("To: {}", recipient.as_ref())));
+        assert!(messages[0].contains("Subject: Newsletter"));
+        assert!(messages[0].contains("text body"));
+        assert!(messages[0].contains("<p>html body</p>"));
+    }
+
+    #[tokio::test]
+    async fn a_permanently_rejected_recipient_is_not_retried() {
+        let server = SmtpStandIn::start("550 No such user").await;
+
+        let outcome = server
+            .client()
+            .send_email(&email(), "Newsletter", "html", "text")
+            .await;
+
+        let error = assert_err!(outcome);
+        assert!(!error.is_retryable());
+        assert_eq!(error.error_code(), Some(550));
+        assert_eq!(*server.rcpt_attempts.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_transient_failure_is_retried() {
+        let server = SmtpStandIn::start("451 Try again later").await;
+
+        let outcome = server
+            .client()
+            .send_email(&email(), "Newsletter", "html", "text")
+            .await;
+
+        assert!(matches!(assert_err!(outcome), SendEmailError::Smtp(e) if e.is_transient()));
+        assert_eq!(*server.rcpt_attempts.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn send_batch_returns_a_result_for_each_recipient() {
+        let server = SmtpStandIn::start("250 OK").await;
+        let recipients = [email(), email()];
+
+        let messages = recipients
+            .iter()
+            .map(|recipient| EmailMessage {
+                recipient,
+                subject: "Newsletter",
+                html_content: "html",
+                text_content: "text",
+                headers: &[],
+            })
+            .collect::<Vec<_>>();
+        let outcomes = assert_ok!(server.client().send_batch(&messages).await);
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|o| o.is_ok()));
+        assert_eq!(server.messages.lock().unwrap().len(), 2);
+    }
+}
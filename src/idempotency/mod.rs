@@ -0,0 +1,5 @@
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{NextAction, run_expiry_worker_until_stopped, save_response, try_processing};
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use actix_web::{HttpResponse, body::to_bytes, http::StatusCode};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::idempotency::IdempotencyKey;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+pub enum NextAction {
+    // 第一次处理这个 key,后续的写操作应该放在这个事务里
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// 尝试为 `(user_id, idempotency_key)` 占位
+/// 同一个 key 的并发请求会阻塞在唯一索引上,直到第一个请求提交事务,然后重放它保存的响应
+/// 超过 `expiration` 的 key 视为不存在,可以重新处理
+#[tracing::instrument(name = "Try processing an idempotent request", skip(pool, expiration))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    expiration: Duration,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (
+            user_id,
+            idempotency_key,
+            created_at
+        )
+        VALUES ($1, $2, now())
+        ON CONFLICT (user_id, idempotency_key) DO UPDATE
+        SET
+            response_status_code = NULL,
+            response_headers = NULL,
+            response_body = NULL,
+            created_at = now()
+        WHERE idempotency.created_at < now() - make_interval(secs => $3)
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        expiration.as_secs_f64()
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(r) = saved_response {
+        let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+        let mut response = HttpResponse::build(status_code);
+        for HeaderPairRecord { name, value } in r.response_headers {
+            response.append_header((name, value));
+        }
+        Ok(Some(response.body(r.response_body)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// 保存响应并提交事务,返回一个内容相同的响应给调用方
+#[tracing::instrument(
+    name = "Save the response of an idempotent request",
+    skip(transaction, http_response)
+)]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    // `MessageBody::Error` 没有实现 std::error::Error,只能手动转换
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
+
+/// 定期清理过期的 key,避免表无限增长
+pub async fn run_expiry_worker_until_stopped(
+    pool: PgPool,
+    expiration: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        if let Err(e) = delete_expired_keys(&pool, expiration).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete expired idempotency keys"
+            );
+        }
+        tokio::time::sleep(Duration::from_secs(60)).await;
+    }
+}
+
+#[tracing::instrument(name = "Delete expired idempotency keys", skip(pool))]
+async fn delete_expired_keys(pool: &PgPool, expiration: Duration) -> Result<u64, sqlx::Error> {
+    let n_deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE created_at < now() - make_interval(secs => $1)
+        "#,
+        expiration.as_secs_f64()
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted_rows)
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    sync::Arc,
+    time::Duration,
+};
+
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailMessage, EmailProvider, MAX_BATCH_SIZE, SendEmailError},
+    routes::subscriptions_unsubscribe::unsubscribe_link,
+    startup::{ApplicationBaseUrl, HmacSecret},
+};
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// 后台投递循环,与 HTTP 服务一起运行
+/// 多个实例可以同时消费同一个队列, `SKIP LOCKED` 保证同一个任务只会被一个实例取走
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: Arc<dyn EmailProvider>,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&pool, email_client.as_ref(), &base_url, &hmac_secret).await {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// 取出一批任务,通过批量接口发送,并记录每个收件人的投递结果
+/// 暂时性的失败留在队列里按退避时间重试,投递成功、跳过或永久失败的任务才删除
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailProvider,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    // 认领之后立即提交,发送期间不持有行锁
+    let tasks = claim_tasks(pool, MAX_BATCH_SIZE).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+
+    // 同一批次里可能有多期 issue
+    let mut issues = HashMap::new();
+    for task in &tasks {
+        if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+            entry.insert(get_issue(pool, task.newsletter_issue_id).await?);
+        }
+    }
+
+    let mut deliverable = Vec::with_capacity(tasks.len());
+    let mut outcomes = Vec::with_capacity(tasks.len());
+    for task in &tasks {
+        // 入队之后才退订的订阅者不再投递
+        let Some(subscriber_id) = task.subscriber_id.filter(|_| task.is_confirmed()) else {
+            outcomes.push((task, DeliveryOutcome::Skipped));
+            continue;
+        };
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => {
+                let issue = &issues[&task.newsletter_issue_id];
+                let link = unsubscribe_link(base_url, hmac_secret, subscriber_id);
+                deliverable.push((task, email, Personalized::new(issue, &link)));
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid"
+                );
+                outcomes.push((task, DeliveryOutcome::failed(None, e)));
+            }
+        }
+    }
+
+    let messages = deliverable
+        .iter()
+        .map(|(task, email, personalized)| EmailMessage {
+            recipient: email,
+            subject: &issues[&task.newsletter_issue_id].title,
+            html_content: &personalized.html_content,
+            text_content: &personalized.text_content,
+            headers: &personalized.headers,
+        })
+        .collect::<Vec<_>>();
+    match email_client.send_batch(&messages).await {
+        Ok(results) => {
+            for ((task, ..), result) in deliverable.iter().zip(results) {
+                let outcome = match result {
+                    Ok(()) => DeliveryOutcome::Delivered,
+                    Err(e) => DeliveryOutcome::from_error(task, &e),
+                };
+                outcomes.push((task, outcome));
+            }
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver a batch of issues"
+            );
+            for (task, ..) in &deliverable {
+                outcomes.push((task, DeliveryOutcome::from_error(task, &e)));
+            }
+        }
+    }
+
+    settle_tasks(pool, &outcomes).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// 认领的任务在这段时间内不会被其他实例取走,worker 中途退出时过期后会被重新认领
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// 一个任务最多尝试的次数,之后按永久失败处理
+const MAX_DELIVERY_ATTEMPTS: i32 = 8;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i32,
+    subscriber_id: Option<Uuid>,
+    subscriber_status: Option<String>,
+}
+
+impl DeliveryTask {
+    fn is_confirmed(&self) -> bool {
+        self.subscriber_status.as_deref() == Some("confirmed")
+    }
+}
+
+/// 每个收件人的正文都带有自己的退订链接
+struct Personalized {
+    html_content: String,
+    text_content: String,
+    headers: [(String, String); 2],
+}
+
+impl Personalized {
+    fn new(issue: &NewsletterIssue, unsubscribe_link: &str) -> Self {
+        Self {
+            html_content: format!(
+                "{}<hr/><p><a href=\"{}\">Unsubscribe</a></p>",
+                issue.html_content, unsubscribe_link
+            ),
+            text_content: format!(
+                "{}\n\n--\nUnsubscribe: {}",
+                issue.text_content, unsubscribe_link
+            ),
+            // RFC 8058 一键退订
+            headers: [
+                (
+                    "List-Unsubscribe".to_string(),
+                    format!("<{}>", unsubscribe_link),
+                ),
+                (
+                    "List-Unsubscribe-Post".to_string(),
+                    "List-Unsubscribe=One-Click".to_string(),
+                ),
+            ],
+        }
+    }
+}
+
+enum DeliveryOutcome {
+    Delivered,
+    Skipped,
+    Failed {
+        error_code: Option<i64>,
+        message: String,
+    },
+    /// 暂时性的失败,留在队列里稍后重试
+    Retry,
+}
+
+impl DeliveryOutcome {
+    fn failed(error_code: Option<i64>, message: impl ToString) -> Self {
+        Self::Failed {
+            error_code,
+            message: message.to_string(),
+        }
+    }
+
+    fn from_error(task: &DeliveryTask, e: &SendEmailError) -> Self {
+        if e.is_retryable() && task.n_retries + 1 < MAX_DELIVERY_ATTEMPTS {
+            tracing::warn!(
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                n_retries = task.n_retries,
+                "Failed to deliver issue to a confirmed subscriber. Retrying later."
+            );
+            Self::Retry
+        } else {
+            tracing::warn!(
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "Failed to deliver issue to a confirmed subscriber. Giving up."
+            );
+            Self::failed(e.error_code(), e)
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        !matches!(self, Self::Retry)
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn claim_tasks(pool: &PgPool, limit: usize) -> Result<Vec<DeliveryTask>, anyhow::Error> {
+    // SKIP LOCKED 避免多个实例抢同一行,推后的 execute_after 在提交之后继续挡住其他实例
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        UPDATE issue_delivery_queue q
+        SET execute_after = now() + make_interval(secs => $2)
+        FROM (
+            SELECT newsletter_issue_id, subscriber_email
+            FROM issue_delivery_queue
+            WHERE execute_after <= now()
+            ORDER BY execute_after
+            FOR UPDATE SKIP LOCKED
+            LIMIT $1
+        ) claimed
+        LEFT JOIN subscriptions s ON s.email = claimed.subscriber_email
+        WHERE q.newsletter_issue_id = claimed.newsletter_issue_id
+            AND q.subscriber_email = claimed.subscriber_email
+        RETURNING
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            s.id as "subscriber_id?",
+            s.status as "subscriber_status?"
+        "#,
+        limit as i64,
+        CLAIM_TIMEOUT.as_secs_f64()
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(tasks)
+}
+
+/// 删除已经有结果的任务并写入投递记录,需要重试的任务推迟到退避之后
+#[tracing::instrument(skip_all)]
+async fn settle_tasks(
+    pool: &PgPool,
+    outcomes: &[(&DeliveryTask, DeliveryOutcome)],
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let (finished, retries): (Vec<_>, Vec<_>) =
+        outcomes.iter().partition(|(_, outcome)| outcome.is_final());
+
+    // 发送期间订阅者可能已经被擦除,任务也随之删除,这些收件人不再写入投递记录
+    let (issue_ids, emails) = task_keys(finished.iter().map(|(task, _)| *task));
+    let deleted: HashSet<(Uuid, String)> = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE (newsletter_issue_id, subscriber_email) IN (
+            SELECT * FROM UNNEST($1::uuid[], $2::text[])
+        )
+        RETURNING newsletter_issue_id, subscriber_email
+        "#,
+        &issue_ids,
+        &emails
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| (r.newsletter_issue_id, r.subscriber_email))
+    .collect();
+    let finished = finished
+        .into_iter()
+        .filter(|(task, _)| {
+            deleted.contains(&(task.newsletter_issue_id, task.subscriber_email.clone()))
+        })
+        .collect::<Vec<_>>();
+    record_delivery_outcomes(&mut transaction, &finished).await?;
+
+    // 第 n 次重试前等待 min(1 分钟 * 2^n, 6 小时)
+    let (issue_ids, emails) = task_keys(retries.iter().map(|(task, _)| *task));
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => LEAST(60 * power(2, n_retries), 21600))
+        WHERE (newsletter_issue_id, subscriber_email) IN (
+            SELECT * FROM UNNEST($1::uuid[], $2::text[])
+        )
+        "#,
+        &issue_ids,
+        &emails
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+fn task_keys<'a>(tasks: impl Iterator<Item = &'a DeliveryTask>) -> (Vec<Uuid>, Vec<String>) {
+    tasks
+        .map(|t| (t.newsletter_issue_id, t.subscriber_email.clone()))
+        .unzip()
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_delivery_outcomes(
+    transaction: &mut Transaction<'_, Postgres>,
+    outcomes: &[&(&DeliveryTask, DeliveryOutcome)],
+) -> Result<(), anyhow::Error> {
+    let mut issue_ids = Vec::with_capacity(outcomes.len());
+    let mut emails = Vec::with_capacity(outcomes.len());
+    let mut statuses = Vec::with_capacity(outcomes.len());
+    let mut error_codes = Vec::with_capacity(outcomes.len());
+    let mut error_messages = Vec::with_capacity(outcomes.len());
+    for (task, outcome) in outcomes {
+        issue_ids.push(task.newsletter_issue_id);
+        emails.push(task.subscriber_email.clone());
+        match outcome {
+            DeliveryOutcome::Delivered => {
+                statuses.push("delivered".to_string());
+                error_codes.push(None);
+                error_messages.push(None);
+            }
+            DeliveryOutcome::Skipped => {
+                statuses.push("skipped".to_string());
+                error_codes.push(None);
+                error_messages.push(None);
+            }
+            DeliveryOutcome::Failed {
+                error_code,
+                message,
+            } => {
+                statuses.push("failed".to_string());
+                error_codes.push(error_code.map(|c| c as i32));
+                error_messages.push(Some(message.clone()));
+            }
+            DeliveryOutcome::Retry => unreachable!("Retried tasks are not logged"),
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_log (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            error_code,
+            error_message,
+            attempted_at
+        )
+        SELECT *, now()
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::int4[], $5::text[])
+        "#,
+        &issue_ids,
+        &emails,
+        &statuses,
+        &error_codes as &[Option<i32>],
+        &error_messages as &[Option<String>],
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
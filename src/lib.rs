@@ -4,12 +4,23 @@
  * @LastEditTime: 2025-07-15 10:36:36
  * @FilePath: /zero2prod/src/lib.rs
  */
+pub mod audit;
+pub mod authentication;
+pub mod cli;
+pub mod client_ip;
 pub mod configuration;
+pub mod consent;
 pub mod routes;
 pub mod startup;
+pub mod subscriber_data;
+pub mod subscriber_import;
 
 pub mod telemetry;
 
 pub mod domain;
 
 pub mod email_client;
+
+pub mod issue_delivery_worker;
+
+pub mod idempotency;
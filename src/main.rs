@@ -4,18 +4,38 @@
  * @LastEditTime: 2025-07-15 23:08:49
  * @FilePath: /zero2prod/src/main.rs
  */
+use clap::Parser;
 use zero2prod::{
+    cli::{Cli, Command, run_subscribers_command, run_user_command},
     configuration::get_configuration,
     startup::Application,
     telemetry::{get_subscriber, init_subscriber},
 };
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod", "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-    let config = get_configuration().expect("Failed to read configuration .");
-    let application = Application::build(&config).await?;
-    application.run_until_stoppend().await?;
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        None | Some(Command::Serve) => {
+            let subscriber = get_subscriber("zero2prod", "info", std::io::stdout);
+            init_subscriber(subscriber);
+            let config = get_configuration().expect("Failed to read configuration .");
+            let application = Application::build(&config).await?;
+            application.run_until_stoppend().await?;
+        }
+        Some(Command::User(command)) => {
+            // 标准输出留给命令的结果,日志写到标准错误
+            let subscriber = get_subscriber("zero2prod", "warn", std::io::stderr);
+            init_subscriber(subscriber);
+            let config = get_configuration().expect("Failed to read configuration .");
+            run_user_command(command, &config).await?;
+        }
+        Some(Command::Subscribers(command)) => {
+            let subscriber = get_subscriber("zero2prod", "warn", std::io::stderr);
+            init_subscriber(subscriber);
+            let config = get_configuration().expect("Failed to read configuration .");
+            run_subscribers_command(command, &config).await?;
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,186 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{
+        Access, PermissionDenied, Scope, UserId, create_api_token, list_api_tokens,
+        revoke_api_token,
+    },
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+};
+
+/// 有效期最长十年
+const MAX_EXPIRES_IN_DAYS: u32 = 3650;
+
+#[derive(Deserialize)]
+pub struct NewApiToken {
+    name: String,
+    scopes: Vec<Scope>,
+    /// 不填表示永不过期
+    expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct CreatedApiToken {
+    api_token_id: Uuid,
+    name: String,
+    /// 只在创建时返回一次
+    #[serde(serialize_with = "expose_token")]
+    token: SecretString,
+    scopes: Vec<Scope>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+fn expose_token<S: serde::Serializer>(token: &SecretString, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(token.expose_secret())
+}
+
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(body, pool, user_id, access, audit),
+    fields(user_id=%*user_id)
+)]
+pub async fn create_api_token_handler(
+    body: web::Json<NewApiToken>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ApiTokenError> {
+    access.require_session()?;
+    let NewApiToken {
+        name,
+        scopes,
+        expires_in_days,
+    } = body.0;
+    let name = name.trim().to_owned();
+    if name.is_empty() || name.graphemes(true).count() > 100 {
+        return Err(ApiTokenError::ValidationError(
+            "The token name must be between 1 and 100 characters long.".into(),
+        ));
+    }
+    if scopes.is_empty() {
+        return Err(ApiTokenError::ValidationError(
+            "At least one scope is required.".into(),
+        ));
+    }
+    let expires_at = match expires_in_days {
+        Some(0) => {
+            return Err(ApiTokenError::ValidationError(
+                "`expires_in_days` must be greater than zero.".into(),
+            ));
+        }
+        Some(days) if days > MAX_EXPIRES_IN_DAYS => {
+            return Err(ApiTokenError::ValidationError(format!(
+                "`expires_in_days` must be at most {MAX_EXPIRES_IN_DAYS}."
+            )));
+        }
+        Some(days) => Some(Utc::now() + chrono::Duration::days(days.into())),
+        None => None,
+    };
+
+    let (api_token_id, token) = create_api_token(&pool, **user_id, &name, &scopes, expires_at)
+        .await
+        .context("Failed to store the API token")?
+        .ok_or_else(|| ApiTokenError::DuplicateName(name.clone()))?;
+    audit
+        .record(
+            pool.get_ref(),
+            AuditAction::CreateApiToken,
+            Some(&api_token_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(HttpResponse::Created().json(CreatedApiToken {
+        api_token_id,
+        name,
+        token,
+        scopes,
+        expires_at,
+    }))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool, user_id, access), fields(user_id=%*user_id))]
+pub async fn list_api_tokens_handler(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+) -> Result<HttpResponse, ApiTokenError> {
+    access.require_session()?;
+    let tokens = list_api_tokens(&pool, **user_id).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip(pool, user_id, access, audit),
+    fields(user_id=%*user_id)
+)]
+pub async fn revoke_api_token_handler(
+    api_token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ApiTokenError> {
+    access.require_session()?;
+    let api_token_id = api_token_id.into_inner();
+    let revoked = revoke_api_token(&pool, **user_id, api_token_id)
+        .await
+        .context("Failed to revoke the API token")?;
+    if !revoked {
+        return Err(ApiTokenError::NotFound);
+    }
+    audit
+        .record(
+            pool.get_ref(),
+            AuditAction::RevokeApiToken,
+            Some(&api_token_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum ApiTokenError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("You already have an API token named '{0}'.")]
+    DuplicateName(String),
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error("There is no such API token.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiTokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::DuplicateName(_) => StatusCode::CONFLICT,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
@@ -0,0 +1,91 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditEntry, query_audit_log},
+    authentication::{Access, Permission, PermissionDenied},
+    routes::{
+        admin::pagination::PageSize,
+        subscriptions::{error_chain_fmt, plaintext_error_response},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct QueryParameters {
+    /// 上一页返回的 `next_before`
+    before: Option<i64>,
+    limit: Option<i64>,
+    user_id: Option<Uuid>,
+    action: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuditLogPage {
+    entries: Vec<AuditEntry>,
+    /// 没有更多记录时为 `null`
+    next_before: Option<i64>,
+}
+
+#[tracing::instrument(name = "List audit log entries", skip(query, pool, access))]
+pub async fn audit_log_handler(
+    query: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+) -> Result<HttpResponse, AuditLogError> {
+    access.authorize(Permission::ReadAuditLog)?;
+    let QueryParameters {
+        before,
+        limit,
+        user_id,
+        action,
+    } = query.0;
+    let page_size = PageSize::parse(limit).map_err(AuditLogError::ValidationError)?;
+
+    let mut entries = query_audit_log(
+        &pool,
+        before,
+        page_size.fetch_limit(),
+        user_id,
+        action.as_deref(),
+    )
+    .await
+    .context("Failed to query the audit log")?;
+    let next_before = page_size.next_cursor(&mut entries, |entry| entry.audit_log_id);
+    Ok(HttpResponse::Ok().json(AuditLogPage {
+        entries,
+        next_before,
+    }))
+}
+
+#[derive(thiserror::Error)]
+pub enum AuditLogError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuditLogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AuditLogError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
@@ -0,0 +1,99 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{Access, Permission, PermissionDenied},
+    consent::{ConsentFilters, ConsentKind, ConsentRecord, query_consents},
+    routes::{
+        admin::pagination::PageSize,
+        subscriptions::{error_chain_fmt, plaintext_error_response},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct QueryParameters {
+    /// 上一页返回的 `next_before`
+    before: Option<i64>,
+    limit: Option<i64>,
+    subscriber_id: Option<Uuid>,
+    /// 订阅者被删除后只能按邮箱查找
+    email: Option<String>,
+    kind: Option<ConsentKind>,
+    source: Option<String>,
+    privacy_policy_version: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ConsentsPage {
+    consents: Vec<ConsentRecord>,
+    /// 没有更多记录时为 `null`
+    next_before: Option<i64>,
+}
+
+#[tracing::instrument(name = "List consent records", skip(query, pool, access))]
+pub async fn consents_handler(
+    query: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+) -> Result<HttpResponse, ConsentsError> {
+    access.authorize(Permission::ReadSubscribers)?;
+    let QueryParameters {
+        before,
+        limit,
+        subscriber_id,
+        email,
+        kind,
+        source,
+        privacy_policy_version,
+    } = query.0;
+    let page_size = PageSize::parse(limit).map_err(ConsentsError::ValidationError)?;
+    let filters = ConsentFilters {
+        subscriber_id,
+        email,
+        kind,
+        source,
+        privacy_policy_version,
+    };
+
+    let mut consents = query_consents(&pool, before, page_size.fetch_limit(), &filters)
+        .await
+        .context("Failed to query consent records")?;
+    let next_before = page_size.next_cursor(&mut consents, |consent| consent.consent_id);
+    Ok(HttpResponse::Ok().json(ConsentsPage {
+        consents,
+        next_before,
+    }))
+}
+
+#[derive(thiserror::Error)]
+pub enum ConsentsError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConsentsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConsentsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
@@ -0,0 +1,49 @@
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{Access, Permission, UserId};
+
+pub async fn admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    access
+        .authorize(Permission::ReadStats)
+        .map_err(actix_web::error::ErrorForbidden)?;
+    let username = get_username(*user_id.into_inner(), &pool)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let username = htmlescape::encode_minimal(&username);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    <p>Welcome {username}!</p>
+    <p>Your role: {role}</p>
+    <p><a href="/admin/password">Change password</a></p>
+    <form name="logoutForm" action="/logout" method="post">
+        <input type="submit" value="Logout">
+    </form>
+</body>
+</html>"#,
+            role = access.role.as_str()
+        )))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT username FROM users WHERE user_id = $1"#, user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
@@ -0,0 +1,28 @@
+mod api_tokens;
+mod audit_log;
+mod consents;
+mod dashboard;
+mod pagination;
+mod password;
+mod subscriber_actions;
+mod subscriber_data;
+mod subscriber_export;
+mod subscriber_import;
+mod subscribers;
+mod totp;
+
+pub use api_tokens::{create_api_token_handler, list_api_tokens_handler, revoke_api_token_handler};
+pub use audit_log::audit_log_handler;
+pub use consents::consents_handler;
+pub use dashboard::admin_dashboard;
+pub use password::{change_password_form, change_password_handler};
+pub use subscriber_actions::{
+    bulk_change_subscribers, confirm_subscriber, delete_subscriber, unsubscribe_subscriber,
+};
+pub use subscriber_data::{admin_erase_subscriber, admin_subscriber_data};
+pub use subscriber_export::export_subscribers;
+pub use subscriber_import::import_subscribers;
+pub use subscribers::list_subscribers;
+pub use totp::{
+    begin_totp_enrolment_handler, confirm_totp_enrolment_handler, disable_totp_handler,
+};
@@ -0,0 +1,59 @@
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// 后台列表接口校验过的每页数量,负责多取一条和生成下一页的游标
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PageSize(i64);
+
+impl PageSize {
+    /// 没有 `limit` 参数时使用默认值
+    pub fn parse(limit: Option<i64>) -> Result<Self, String> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(format!("`limit` must be between 1 and {MAX_PAGE_SIZE}."));
+        }
+        Ok(Self(limit))
+    }
+
+    /// 查询时多取一条,用来判断是否还有下一页
+    pub fn fetch_limit(&self) -> i64 {
+        self.0 + 1
+    }
+
+    /// 去掉多取的那一条,还有下一页时用这一页的最后一条生成游标
+    pub fn next_cursor<T, C>(&self, rows: &mut Vec<T>, cursor: impl FnOnce(&T) -> C) -> Option<C> {
+        if rows.len() as i64 > self.0 {
+            rows.truncate(self.0 as usize);
+            rows.last().map(cursor)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageSize;
+
+    #[test]
+    fn the_limit_defaults_and_is_bounded() {
+        assert_eq!(PageSize::parse(None), Ok(PageSize(50)));
+        assert_eq!(PageSize::parse(Some(200)), Ok(PageSize(200)));
+        assert!(PageSize::parse(Some(0)).is_err());
+        assert!(PageSize::parse(Some(201)).is_err());
+    }
+
+    #[test]
+    fn the_extra_row_is_trimmed_into_a_cursor() {
+        let page_size = PageSize::parse(Some(2)).unwrap();
+        assert_eq!(page_size.fetch_limit(), 3);
+
+        let mut rows = vec![1, 2, 3];
+        assert_eq!(page_size.next_cursor(&mut rows, |row| *row), Some(2));
+        assert_eq!(rows, vec![1, 2]);
+
+        let mut rows = vec![1, 2];
+        assert_eq!(page_size.next_cursor(&mut rows, |row| *row), None);
+        assert_eq!(rows, vec![1, 2]);
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    http::{
+        StatusCode,
+        header::{ContentType, HeaderValue, RETRY_AFTER},
+    },
+    web,
+};
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{
+        Access, AuthError, PermissionDenied, SESSION_COOKIE_NAME, UserId, change_password,
+        delete_other_sessions, verify_current_password,
+    },
+    client_ip::client_ip,
+    configuration::{LoginThrottlingSettings, PasswordHashingSettings},
+    domain::Password,
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    current_password: SecretString,
+    new_password: SecretString,
+    new_password_check: SecretString,
+}
+
+pub async fn change_password_form() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password" name="current_password">
+        </label>
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )
+}
+
+#[tracing::instrument(
+    name = "Change password",
+    skip(form, request, pool, hashing, throttling, user_id, access),
+    fields(user_id=%*user_id)
+)]
+pub async fn change_password_handler(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    throttling: web::Data<LoginThrottlingSettings>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+) -> Result<HttpResponse, ChangePasswordError> {
+    access.require_session()?;
+    let session_id = request
+        .cookie(SESSION_COOKIE_NAME)
+        .context("The session cookie is missing")?;
+    let user_id = *user_id.into_inner();
+    let FormData {
+        current_password,
+        new_password,
+        new_password_check,
+    } = form.0;
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return Err(ChangePasswordError::ValidationError(
+            "You entered two different new passwords - the field values must match.".into(),
+        ));
+    }
+    let new_password =
+        Password::parse(new_password).map_err(ChangePasswordError::ValidationError)?;
+
+    verify_current_password(
+        user_id,
+        current_password,
+        client_ip(&request),
+        &throttling,
+        &pool,
+    )
+    .await
+    .map_err(|e| match e {
+        AuthError::InvalidCredentials(e) => ChangePasswordError::InvalidCurrentPassword(e),
+        AuthError::TooManyAttempts(retry_after) => {
+            ChangePasswordError::TooManyAttempts(retry_after)
+        }
+        // 只校验密码,不会要求第二因素
+        e @ AuthError::SecondFactorRequired => {
+            ChangePasswordError::UnexpectedError(anyhow::anyhow!(e))
+        }
+        AuthError::UnexpectedError(e) => ChangePasswordError::UnexpectedError(e),
+    })?;
+    change_password(user_id, new_password, **hashing, &pool).await?;
+    // 其他设备上的会话可能是泄露的,让它们用新密码重新登录
+    delete_other_sessions(&pool, user_id, session_id.value())
+        .await
+        .context("Failed to delete the user's other sessions")?;
+    AuditContext::for_request(&request)
+        .record(
+            pool.get_ref(),
+            AuditAction::ChangePassword,
+            Some(&user_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>Your password has been changed.</p>"))
+}
+
+#[derive(thiserror::Error)]
+pub enum ChangePasswordError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The current password is incorrect.")]
+    InvalidCurrentPassword(#[source] anyhow::Error),
+    #[error("Too many failed attempts. Try again later.")]
+    TooManyAttempts(Duration),
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ChangePasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ChangePasswordError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidCurrentPassword(_) => StatusCode::UNAUTHORIZED,
+            Self::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // 把原因告诉用户,方便在表单里修正
+    fn error_response(&self) -> HttpResponse {
+        let mut response = plaintext_error_response(self);
+        if let Self::TooManyAttempts(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+        }
+        response
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashSet;
+
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{Access, Permission, PermissionDenied},
+    consent::{ConsentEvidence, ConsentKind},
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+};
+
+/// 一次批量操作最多涉及的订阅者数量
+const MAX_BULK_SIZE: usize = 1000;
+
+/// 后台对订阅者的手动操作
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberAction {
+    /// 确认邮件无法使用时手动确认,只适用于等待确认的订阅者
+    Confirm,
+    Unsubscribe,
+    /// 删除订阅者和它的确认令牌
+    Delete,
+}
+
+impl SubscriberAction {
+    fn audit_action(&self) -> AuditAction {
+        match self {
+            SubscriberAction::Confirm => AuditAction::ConfirmSubscriber,
+            SubscriberAction::Unsubscribe => AuditAction::UnsubscribeSubscriber,
+            SubscriberAction::Delete => AuditAction::DeleteSubscriber,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BulkRequest {
+    action: SubscriberAction,
+    #[serde(default)]
+    ids: Vec<Uuid>,
+    #[serde(default)]
+    emails: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct BulkResponse {
+    affected: Vec<Uuid>,
+    /// 没有找到的 id 和邮箱,原样返回
+    not_found: Vec<String>,
+    /// 找到了但当前状态不允许这个操作,例如确认已经退订的订阅者
+    skipped: Vec<Uuid>,
+}
+
+pub async fn confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, SubscriberActionError> {
+    change_subscriber(
+        SubscriberAction::Confirm,
+        *subscriber_id,
+        &pool,
+        &access,
+        &audit,
+    )
+    .await
+}
+
+pub async fn unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, SubscriberActionError> {
+    change_subscriber(
+        SubscriberAction::Unsubscribe,
+        *subscriber_id,
+        &pool,
+        &access,
+        &audit,
+    )
+    .await
+}
+
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, SubscriberActionError> {
+    change_subscriber(
+        SubscriberAction::Delete,
+        *subscriber_id,
+        &pool,
+        &access,
+        &audit,
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Change a subscriber", skip(pool, access, audit))]
+async fn change_subscriber(
+    action: SubscriberAction,
+    subscriber_id: Uuid,
+    pool: &PgPool,
+    access: &Access,
+    audit: &AuditContext,
+) -> Result<HttpResponse, SubscriberActionError> {
+    access.authorize(Permission::ManageSubscribers)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let affected = apply_action(&mut transaction, action, &[subscriber_id])
+        .await
+        .context("Failed to change the subscriber")?;
+    if affected.is_empty() {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM subscriptions WHERE id = $1) as "exists!""#,
+            subscriber_id
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to look up the subscriber")?;
+        return Err(if exists {
+            SubscriberActionError::NotPending
+        } else {
+            SubscriberActionError::NotFound
+        });
+    }
+    record_actions(&mut transaction, audit, action, &affected).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a subscriber.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// 按 id 或邮箱批量操作,所有修改在同一个事务里完成
+#[tracing::instrument(name = "Change subscribers in bulk", skip(body, pool, access, audit))]
+pub async fn bulk_change_subscribers(
+    body: web::Json<BulkRequest>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, SubscriberActionError> {
+    access.authorize(Permission::ManageSubscribers)?;
+    let BulkRequest {
+        action,
+        ids,
+        emails,
+    } = body.0;
+    let n_requested = ids.len() + emails.len();
+    if n_requested == 0 || n_requested > MAX_BULK_SIZE {
+        return Err(SubscriberActionError::ValidationError(format!(
+            "Provide between 1 and {MAX_BULK_SIZE} ids or emails."
+        )));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let found = sqlx::query!(
+        r#"
+        SELECT id, email FROM subscriptions
+        WHERE id = ANY($1) OR email = ANY($2)
+        FOR UPDATE
+        "#,
+        &ids,
+        &emails
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the subscribers")?;
+    let found_ids: HashSet<Uuid> = found.iter().map(|row| row.id).collect();
+    let found_emails: HashSet<&str> = found.iter().map(|row| row.email.as_str()).collect();
+    let not_found = ids
+        .iter()
+        .filter(|id| !found_ids.contains(id))
+        .map(Uuid::to_string)
+        .chain(
+            emails
+                .iter()
+                .filter(|email| !found_emails.contains(email.as_str()))
+                .cloned(),
+        )
+        .collect();
+
+    let found_ids: Vec<Uuid> = found_ids.into_iter().collect();
+    let affected = apply_action(&mut transaction, action, &found_ids)
+        .await
+        .context("Failed to change the subscribers")?;
+    let skipped = found_ids
+        .into_iter()
+        .filter(|id| !affected.contains(id))
+        .collect();
+    record_actions(&mut transaction, &audit, action, &affected).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change subscribers.")?;
+    Ok(HttpResponse::Ok().json(BulkResponse {
+        affected,
+        not_found,
+        skipped,
+    }))
+}
+
+/// 返回实际修改的订阅者
+/// 确认和退订后,尚未使用的确认令牌也不再需要;删除时由外键级联删除
+/// 已经确认或退订的订阅者不会被重新确认,退订必须由本人重新订阅才能恢复
+async fn apply_action(
+    transaction: &mut Transaction<'_, Postgres>,
+    action: SubscriberAction,
+    subscriber_ids: &[Uuid],
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let affected = match action {
+        SubscriberAction::Confirm | SubscriberAction::Unsubscribe => {
+            let affected = if action == SubscriberAction::Confirm {
+                sqlx::query_scalar!(
+                    r#"
+                    UPDATE subscriptions SET status = 'confirmed'
+                    WHERE id = ANY($1) AND status = 'pending_confirmation'
+                    RETURNING id
+                    "#,
+                    subscriber_ids
+                )
+                .fetch_all(&mut **transaction)
+                .await?
+            } else {
+                sqlx::query_scalar!(
+                    r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = ANY($1) RETURNING id"#,
+                    subscriber_ids
+                )
+                .fetch_all(&mut **transaction)
+                .await?
+            };
+            sqlx::query!(
+                r#"DELETE FROM subscription_tokens WHERE subscription_id = ANY($1)"#,
+                &affected
+            )
+            .execute(&mut **transaction)
+            .await?;
+            affected
+        }
+        SubscriberAction::Delete => {
+            sqlx::query_scalar!(
+                r#"DELETE FROM subscriptions WHERE id = ANY($1) RETURNING id"#,
+                subscriber_ids
+            )
+            .fetch_all(&mut **transaction)
+            .await?
+        }
+    };
+    Ok(affected)
+}
+
+/// 手动确认没有订阅者本人的同意,同时记下是哪个管理员确认的
+async fn record_actions(
+    transaction: &mut Transaction<'_, Postgres>,
+    audit: &AuditContext,
+    action: SubscriberAction,
+    subscriber_ids: &[Uuid],
+) -> Result<(), anyhow::Error> {
+    let consent = ConsentEvidence::confirmed_by_admin(audit.user_id);
+    for subscriber_id in subscriber_ids {
+        if action == SubscriberAction::Confirm {
+            consent
+                .record(&mut **transaction, *subscriber_id, ConsentKind::Confirm)
+                .await
+                .context("Failed to record the consent evidence")?;
+        }
+        audit
+            .record(
+                &mut **transaction,
+                action.audit_action(),
+                Some(&subscriber_id.to_string()),
+            )
+            .await
+            .context("Failed to record the audit log entry")?;
+    }
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberActionError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no such subscriber.")]
+    NotFound,
+    #[error("Only subscribers waiting for confirmation can be confirmed.")]
+    NotPending,
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberActionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::NotPending => StatusCode::CONFLICT,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
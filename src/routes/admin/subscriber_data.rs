@@ -0,0 +1,102 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{Access, Permission, PermissionDenied},
+    routes::{
+        subscriber_data::subscriber_data_response,
+        subscriptions::{error_chain_fmt, plaintext_error_response},
+    },
+    subscriber_data::{ErasureRequester, erase_subscriber, get_subscriber_data},
+};
+
+/// 代替订阅者导出数据,例如订阅者通过其他渠道提出了请求
+#[tracing::instrument(
+    name = "Export subscriber data for an admin",
+    skip(pool, access, audit)
+)]
+pub async fn admin_subscriber_data(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, SubscriberErasureError> {
+    access.authorize(Permission::ReadSubscribers)?;
+    let subscriber_id = subscriber_id.into_inner();
+    let data = get_subscriber_data(&pool, subscriber_id)
+        .await?
+        .ok_or(SubscriberErasureError::NotFound)?;
+    audit
+        .record(
+            pool.get_ref(),
+            AuditAction::ExportSubscriberData,
+            Some(&subscriber_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(subscriber_data_response(&data))
+}
+
+#[tracing::instrument(name = "Erase a subscriber for an admin", skip(pool, access, audit))]
+pub async fn admin_erase_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, SubscriberErasureError> {
+    access.authorize(Permission::ManageSubscribers)?;
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    if !erase_subscriber(&mut transaction, subscriber_id, ErasureRequester::Admin).await? {
+        return Err(SubscriberErasureError::NotFound);
+    }
+    audit
+        .record(
+            &mut *transaction,
+            AuditAction::EraseSubscriber,
+            Some(&subscriber_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberErasureError {
+    #[error("There is no such subscriber.")]
+    NotFound,
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberErasureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberErasureError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
@@ -0,0 +1,262 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::{
+        StatusCode,
+        header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    },
+    web::{self, Bytes},
+};
+use anyhow::Context;
+use chrono::SecondsFormat;
+use futures_util::{StreamExt, stream};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{Access, Permission, PermissionDenied},
+    domain::SubscriptionStatus,
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+};
+
+use super::subscribers::{Subscriber, SubscriberRow};
+
+/// 攒够这么多字节再交给响应体,避免每一行都是一个分块
+const CHUNK_SIZE: usize = 16 * 1024;
+/// 客户端读得慢时最多缓存这么多分块,数据库游标跟着暂停
+const CHANNEL_CAPACITY: usize = 4;
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    fn content_type(&self) -> ContentType {
+        match self {
+            ExportFormat::Csv => ContentType("text/csv; charset=utf-8".parse().unwrap()),
+            ExportFormat::Ndjson => ContentType("application/x-ndjson".parse().unwrap()),
+        }
+    }
+
+    fn header(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "id,email,name,status,subscribed_at\r\n",
+            ExportFormat::Ndjson => "",
+        }
+    }
+
+    fn write(&self, subscriber: &Subscriber, buffer: &mut String) -> Result<(), anyhow::Error> {
+        match self {
+            ExportFormat::Csv => {
+                let subscribed_at = subscriber
+                    .subscribed_at
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                let fields = [
+                    subscriber.id.to_string(),
+                    subscriber.email.clone(),
+                    subscriber.name.clone(),
+                    subscriber.status.to_string(),
+                    subscribed_at,
+                ];
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        buffer.push(',');
+                    }
+                    push_csv_field(buffer, field);
+                }
+                buffer.push_str("\r\n");
+            }
+            ExportFormat::Ndjson => {
+                let json = serde_json::to_string(subscriber)
+                    .context("Failed to serialize a subscriber")?;
+                buffer.push_str(&json);
+                buffer.push('\n');
+            }
+        }
+        Ok(())
+    }
+}
+
+/// RFC 4180: 含有分隔符、引号或换行的字段用引号包起来,引号写两次
+/// 名字来自公开的订阅表单,以公式字符开头的字段加上 `'`,电子表格打开时不会被当成公式执行
+fn push_csv_field(buffer: &mut String, field: &str) {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field.to_owned()
+    };
+    if field.contains([',', '"', '\r', '\n']) {
+        buffer.push('"');
+        buffer.push_str(&field.replace('"', "\"\""));
+        buffer.push('"');
+    } else {
+        buffer.push_str(&field);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+    /// 省略时导出所有状态
+    status: Option<SubscriptionStatus>,
+}
+
+/// 用 `fetch` 逐行读取并写进响应体,内存占用和表的大小无关
+#[tracing::instrument(name = "Export subscribers", skip(query, pool, access, audit))]
+pub async fn export_subscribers(
+    query: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ExportSubscribersError> {
+    access.authorize(Permission::ReadSubscribers)?;
+    let ExportParameters { format, status } = query.into_inner();
+    audit
+        .record(
+            pool.get_ref(),
+            AuditAction::ExportSubscribers,
+            Some(&format!(
+                "{}:{}",
+                format.as_str(),
+                status.map_or("all", |status| status.as_str())
+            )),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+
+    // 响应体必须是 'static 的,查询放到独立的任务里,通过有界通道把分块交给响应体
+    // 客户端断开后发送失败,任务随之结束并释放连接
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let pool = pool.get_ref().clone();
+    tokio::spawn(
+        async move {
+            if let Err(e) = stream_subscribers(&pool, format, status, &sender).await {
+                tracing::error!(error.cause_chain = ?e, "Failed to export subscribers");
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        .in_current_span(),
+    );
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "subscribers.{}",
+                format.as_str()
+            ))],
+        })
+        .streaming(body))
+}
+
+async fn stream_subscribers(
+    pool: &PgPool,
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+    sender: &mpsc::Sender<Result<Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut rows = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY subscribed_at, id
+        "#,
+        status.map(|status| status.as_str())
+    )
+    .fetch(pool);
+
+    let mut buffer = String::from(format.header());
+    while let Some(row) = rows.next().await {
+        let subscriber = Subscriber::try_from(row.context("Failed to fetch a subscriber")?)?;
+        format.write(&subscriber, &mut buffer)?;
+        if buffer.len() >= CHUNK_SIZE && !send(sender, &mut buffer).await {
+            return Ok(());
+        }
+    }
+    if !buffer.is_empty() {
+        send(sender, &mut buffer).await;
+    }
+    Ok(())
+}
+
+/// 返回 `false` 表示客户端已经断开
+async fn send(sender: &mpsc::Sender<Result<Bytes, anyhow::Error>>, buffer: &mut String) -> bool {
+    let chunk = Bytes::from(std::mem::take(buffer));
+    sender.send(Ok(chunk)).await.is_ok()
+}
+
+#[derive(thiserror::Error)]
+pub enum ExportSubscribersError {
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ExportSubscribersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ExportSubscribersError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_csv_field;
+
+    #[test]
+    fn fields_with_special_characters_are_quoted() {
+        let cases = [
+            ("ursula", "ursula"),
+            ("Le Guin, Ursula", "\"Le Guin, Ursula\""),
+            ("Ursula \"K\" Le Guin", "\"Ursula \"\"K\"\" Le Guin\""),
+            ("two\nlines", "\"two\nlines\""),
+            (
+                "=HYPERLINK(\"http://evil\")",
+                "\"'=HYPERLINK(\"\"http://evil\"\")\"",
+            ),
+            ("+cmd|' /C calc'!A0", "'+cmd|' /C calc'!A0"),
+            ("-1+1", "'-1+1"),
+            ("@SUM(A1:A2)", "'@SUM(A1:A2)"),
+            ("\tursula", "'\tursula"),
+            ("\rursula", "\"'\rursula\""),
+        ];
+        for (field, expected) in cases {
+            let mut buffer = String::new();
+            push_csv_field(&mut buffer, field);
+            assert_eq!(buffer, expected);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use actix_web::{HttpRequest, HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{Access, Permission, PermissionDenied},
+    email_client::EmailProvider,
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+    startup::{ApplicationBaseUrl, SubscriptionTokenExpiration},
+    subscriber_import::{ImportError, ImportOptions, SubscriberImport},
+};
+
+#[derive(Deserialize)]
+pub struct ImportParameters {
+    #[serde(default)]
+    pre_confirmed: bool,
+    consent_source: Option<String>,
+}
+
+/// 请求体是 CSV,边接收边导入
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(
+        request,
+        payload,
+        pool,
+        email_client,
+        base_url,
+        token_expiration,
+        access
+    )
+)]
+pub async fn import_subscribers(
+    request: HttpRequest,
+    mut payload: web::Payload,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailProvider>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_expiration: web::Data<SubscriptionTokenExpiration>,
+    access: web::ReqData<Access>,
+) -> Result<HttpResponse, ImportSubscribersError> {
+    access.authorize(Permission::ManageSubscribers)?;
+    let parameters = web::Query::<ImportParameters>::from_query(request.query_string())
+        .map_err(|e| ImportSubscribersError::ValidationError(e.to_string()))?
+        .into_inner();
+    let options = ImportOptions {
+        pre_confirmed: parameters.pre_confirmed,
+        consent_source: parameters.consent_source,
+    };
+
+    let mut import = SubscriberImport::new(
+        &pool,
+        email_client.into_inner(),
+        base_url.into_inner(),
+        token_expiration.0,
+        options,
+    )?;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.context("Failed to read the request body")?;
+        import.feed(&chunk).await?;
+    }
+    let (report, emails) = import.finish().await?;
+    emails.detach();
+
+    AuditContext::for_request(&request)
+        .record(
+            pool.get_ref(),
+            AuditAction::ImportSubscribers,
+            Some(&format!(
+                "{} accepted, {} duplicates, {} rejected",
+                report.accepted, report.duplicates, report.rejected
+            )),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportSubscribersError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<ImportError> for ImportSubscribersError {
+    fn from(e: ImportError) -> Self {
+        match e {
+            ImportError::ValidationError(e) => Self::ValidationError(e),
+            ImportError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+impl std::fmt::Debug for ImportSubscribersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ImportSubscribersError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
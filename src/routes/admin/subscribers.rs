@@ -0,0 +1,355 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{Access, Permission, PermissionDenied},
+    domain::SubscriptionStatus,
+    routes::{
+        admin::pagination::PageSize,
+        subscriptions::{error_chain_fmt, plaintext_error_response},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct QueryParameters {
+    status: Option<SubscriptionStatus>,
+    /// 包含这个时间
+    subscribed_after: Option<DateTime<Utc>>,
+    /// 不包含这个时间
+    subscribed_before: Option<DateTime<Utc>>,
+    /// 按邮箱或名字的子串搜索,不区分大小写
+    q: Option<String>,
+    /// `subscribed_at`、`email` 或 `name`,加 `-` 前缀表示倒序,默认 `-subscribed_at`
+    sort: Option<String>,
+    limit: Option<i64>,
+    /// 上一页返回的 `next_cursor`
+    cursor: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SortColumn {
+    SubscribedAt,
+    Email,
+    Name,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Sort {
+    column: SortColumn,
+    descending: bool,
+}
+
+impl Sort {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (descending, column) = match s.strip_prefix('-') {
+            Some(column) => (true, column),
+            None => (false, s),
+        };
+        let column = match column {
+            "subscribed_at" => SortColumn::SubscribedAt,
+            "email" => SortColumn::Email,
+            "name" => SortColumn::Name,
+            _ => {
+                return Err(format!(
+                    "{s} is not a valid sort. Use `subscribed_at`, `email` or `name`, \
+                    optionally prefixed with `-`."
+                ));
+            }
+        };
+        Ok(Self { column, descending })
+    }
+
+    fn as_string(&self) -> String {
+        let prefix = if self.descending { "-" } else { "" };
+        format!("{prefix}{}", self.column_name())
+    }
+
+    fn column_name(&self) -> &'static str {
+        match self.column {
+            SortColumn::SubscribedAt => "subscribed_at",
+            SortColumn::Email => "email",
+            SortColumn::Name => "name",
+        }
+    }
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Self {
+            column: SortColumn::SubscribedAt,
+            descending: true,
+        }
+    }
+}
+
+/// 键集分页的游标: 上一页最后一行的排序值和 id
+/// 带上排序方式,换了排序后旧的游标不能继续使用
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    value: String,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Failed to serialize a cursor");
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json)
+    }
+
+    fn decode(s: &str, sort: Sort) -> Result<Self, String> {
+        let invalid = || "The cursor is not valid.".to_string();
+        let json = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| invalid())?;
+        let cursor: Cursor = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        if cursor.sort != sort.as_string() {
+            return Err("The cursor was created for a different sort order.".into());
+        }
+        if sort.column == SortColumn::SubscribedAt {
+            DateTime::parse_from_rfc3339(&cursor.value).map_err(|_| invalid())?;
+        }
+        Ok(cursor)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub(super) struct SubscriberRow {
+    pub(super) id: Uuid,
+    pub(super) email: String,
+    pub(super) name: String,
+    pub(super) status: String,
+    pub(super) subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub(super) struct Subscriber {
+    pub(super) id: Uuid,
+    pub(super) email: String,
+    pub(super) name: String,
+    pub(super) status: SubscriptionStatus,
+    pub(super) subscribed_at: DateTime<Utc>,
+}
+
+impl TryFrom<SubscriberRow> for Subscriber {
+    type Error = anyhow::Error;
+
+    fn try_from(row: SubscriberRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            email: row.email,
+            name: row.name,
+            status: SubscriptionStatus::parse(&row.status).map_err(anyhow::Error::msg)?,
+            subscribed_at: row.subscribed_at,
+        })
+    }
+}
+
+impl Subscriber {
+    fn cursor(&self, sort: Sort) -> Cursor {
+        let value = match sort.column {
+            SortColumn::SubscribedAt => self
+                .subscribed_at
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            SortColumn::Email => self.email.clone(),
+            SortColumn::Name => self.name.clone(),
+        };
+        Cursor {
+            sort: sort.as_string(),
+            value,
+            id: self.id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SubscribersPage {
+    subscribers: Vec<Subscriber>,
+    /// 没有更多记录时为 `null`
+    next_cursor: Option<String>,
+}
+
+/// `ILIKE` 的通配符需要转义,用户输入只做子串匹配
+fn contains_pattern(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+#[tracing::instrument(name = "List subscribers", skip(query, pool, access))]
+pub async fn list_subscribers(
+    query: web::Query<QueryParameters>,
+    pool: web::Data<PgPool>,
+    access: web::ReqData<Access>,
+) -> Result<HttpResponse, SubscribersError> {
+    access.authorize(Permission::ReadSubscribers)?;
+    let QueryParameters {
+        status,
+        subscribed_after,
+        subscribed_before,
+        q,
+        sort,
+        limit,
+        cursor,
+    } = query.0;
+    let sort = sort
+        .as_deref()
+        .map(Sort::parse)
+        .transpose()
+        .map_err(SubscribersError::ValidationError)?
+        .unwrap_or_default();
+    let page_size = PageSize::parse(limit).map_err(SubscribersError::ValidationError)?;
+    let cursor = cursor
+        .as_deref()
+        .map(|cursor| Cursor::decode(cursor, sort))
+        .transpose()
+        .map_err(SubscribersError::ValidationError)?;
+
+    let mut query = QueryBuilder::<Postgres>::new(
+        "SELECT id, email, name, status, subscribed_at FROM subscriptions WHERE TRUE",
+    );
+    if let Some(status) = status {
+        query.push(" AND status = ").push_bind(status.as_str());
+    }
+    if let Some(subscribed_after) = subscribed_after {
+        query
+            .push(" AND subscribed_at >= ")
+            .push_bind(subscribed_after);
+    }
+    if let Some(subscribed_before) = subscribed_before {
+        query
+            .push(" AND subscribed_at < ")
+            .push_bind(subscribed_before);
+    }
+    if let Some(q) = q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        let pattern = contains_pattern(q);
+        query
+            .push(" AND (email ILIKE ")
+            .push_bind(pattern.clone())
+            .push(" OR name ILIKE ")
+            .push_bind(pattern)
+            .push(")");
+    }
+    let column = sort.column_name();
+    let (direction, comparison) = if sort.descending {
+        ("DESC", "<")
+    } else {
+        ("ASC", ">")
+    };
+    if let Some(cursor) = cursor {
+        query.push(format_args!(" AND ({column}, id) {comparison} ("));
+        match sort.column {
+            SortColumn::SubscribedAt => {
+                let value = DateTime::parse_from_rfc3339(&cursor.value)
+                    .context("Failed to parse a validated cursor")?
+                    .with_timezone(&Utc);
+                query.push_bind(value)
+            }
+            SortColumn::Email | SortColumn::Name => query.push_bind(cursor.value),
+        };
+        query.push(", ").push_bind(cursor.id).push(")");
+    }
+    query
+        .push(format_args!(
+            " ORDER BY {column} {direction}, id {direction} LIMIT "
+        ))
+        .push_bind(page_size.fetch_limit());
+
+    let mut subscribers = query
+        .build_query_as::<SubscriberRow>()
+        .fetch_all(pool.get_ref())
+        .await
+        .context("Failed to query subscribers")?
+        .into_iter()
+        .map(Subscriber::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    let next_cursor = page_size.next_cursor(&mut subscribers, |subscriber| {
+        subscriber.cursor(sort).encode()
+    });
+    Ok(HttpResponse::Ok().json(SubscribersPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribersError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribersError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cursor, Sort, SortColumn, contains_pattern};
+
+    #[test]
+    fn sorts_are_parsed_with_an_optional_descending_prefix() {
+        assert_eq!(
+            Sort::parse("email"),
+            Ok(Sort {
+                column: SortColumn::Email,
+                descending: false
+            })
+        );
+        assert_eq!(
+            Sort::parse("-subscribed_at"),
+            Ok(Sort {
+                column: SortColumn::SubscribedAt,
+                descending: true
+            })
+        );
+        assert!(Sort::parse("id").is_err());
+        assert!(Sort::parse("--name").is_err());
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+
+    #[test]
+    fn a_cursor_cannot_be_reused_with_another_sort() {
+        let sort = Sort::parse("name").unwrap();
+        let cursor = Cursor {
+            sort: sort.as_string(),
+            value: "ursula".into(),
+            id: uuid::Uuid::new_v4(),
+        }
+        .encode();
+
+        assert!(Cursor::decode(&cursor, sort).is_ok());
+        assert!(Cursor::decode(&cursor, Sort::parse("-name").unwrap()).is_err());
+        assert!(Cursor::decode("not-a-cursor", sort).is_err());
+    }
+}
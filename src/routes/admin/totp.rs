@@ -0,0 +1,136 @@
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{
+        Access, PermissionDenied, TotpError, UserId, begin_totp_enrolment, confirm_totp_enrolment,
+        disable_totp, unix_now,
+    },
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+};
+
+#[derive(Deserialize)]
+pub struct CodeData {
+    code: SecretString,
+}
+
+#[derive(Serialize)]
+struct EnrolmentResponse<'a> {
+    secret: &'a str,
+    provisioning_uri: &'a str,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodesResponse {
+    /// 只展示一次,用户需要自己保存
+    recovery_codes: Vec<String>,
+}
+
+/// 生成新的 TOTP 密钥,用验证器 App 扫描 `provisioning_uri` 后再调用确认接口
+#[tracing::instrument(name = "Begin TOTP enrolment", skip(pool, user_id, access), fields(user_id=%*user_id))]
+pub async fn begin_totp_enrolment_handler(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+) -> Result<HttpResponse, TwoFactorError> {
+    access.require_session()?;
+    let enrolment = begin_totp_enrolment(&pool, **user_id).await?;
+    Ok(HttpResponse::Ok().json(EnrolmentResponse {
+        secret: enrolment.secret.expose_secret(),
+        provisioning_uri: enrolment.provisioning_uri.expose_secret(),
+    }))
+}
+
+#[tracing::instrument(
+    name = "Confirm TOTP enrolment",
+    skip(body, pool, user_id, access, audit),
+    fields(user_id=%*user_id)
+)]
+pub async fn confirm_totp_enrolment_handler(
+    body: web::Json<CodeData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, TwoFactorError> {
+    access.require_session()?;
+    let recovery_codes =
+        confirm_totp_enrolment(&pool, **user_id, body.code.expose_secret(), unix_now()?).await?;
+    audit
+        .record(
+            pool.get_ref(),
+            AuditAction::EnableTotp,
+            Some(&user_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[tracing::instrument(
+    name = "Disable TOTP",
+    skip(body, pool, user_id, access, audit),
+    fields(user_id=%*user_id)
+)]
+pub async fn disable_totp_handler(
+    body: web::Json<CodeData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, TwoFactorError> {
+    access.require_session()?;
+    disable_totp(&pool, **user_id, body.code.expose_secret(), unix_now()?).await?;
+    audit
+        .record(
+            pool.get_ref(),
+            AuditAction::DisableTotp,
+            Some(&user_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum TwoFactorError {
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
+    #[error(transparent)]
+    Totp(#[from] TotpError),
+}
+
+impl From<anyhow::Error> for TwoFactorError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Totp(TotpError::UnexpectedError(e))
+    }
+}
+
+impl std::fmt::Debug for TwoFactorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TwoFactorError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Totp(e) => match e {
+                TotpError::AlreadyEnabled | TotpError::NotEnrolling | TotpError::NotEnabled => {
+                    StatusCode::CONFLICT
+                }
+                TotpError::InvalidCode => StatusCode::UNAUTHORIZED,
+                TotpError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
@@ -0,0 +1,188 @@
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    cookie::{Cookie, SameSite},
+    http::{
+        StatusCode,
+        header::{ContentType, HeaderValue, LOCATION, RETRY_AFTER},
+    },
+    web,
+};
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::time::Duration;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{
+        AuthError, Credentials, SESSION_COOKIE_NAME, create_session, delete_session,
+        validate_credentials,
+    },
+    client_ip::client_ip,
+    configuration::{LoginThrottlingSettings, PasswordHashingSettings},
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+    startup::{ApplicationBaseUrl, SessionExpiration},
+};
+
+#[derive(Deserialize)]
+pub struct FormData {
+    username: String,
+    password: SecretString,
+    /// 表单里总会带上这个字段,没开启两步验证时留空
+    #[serde(default)]
+    totp_code: Option<SecretString>,
+}
+
+pub async fn login_form() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <label>One-time code
+            <input type="text" placeholder="Only if two-factor authentication is enabled" name="totp_code" autocomplete="one-time-code">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+    <p><a href="/password_reset">Forgot your password?</a></p>
+</body>
+</html>"#,
+    )
+}
+
+#[tracing::instrument(
+    skip(form, request, pool, base_url, session_expiration, hashing, throttling),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    session_expiration: web::Data<SessionExpiration>,
+    hashing: web::Data<PasswordHashingSettings>,
+    throttling: web::Data<LoginThrottlingSettings>,
+) -> Result<HttpResponse, LoginError> {
+    let FormData {
+        username,
+        password,
+        totp_code,
+    } = form.0;
+    let second_factor = totp_code.filter(|code| !code.expose_secret().trim().is_empty());
+    tracing::Span::current().record("username", tracing::field::display(&username));
+    let credentials = Credentials {
+        username,
+        password,
+        second_factor,
+    };
+    let client_ip = client_ip(&request);
+    let user_id = validate_credentials(credentials, client_ip, **hashing, &throttling, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(e) => LoginError::AuthError(e),
+            AuthError::SecondFactorRequired => LoginError::SecondFactorRequired,
+            AuthError::TooManyAttempts(retry_after) => LoginError::TooManyAttempts(retry_after),
+            AuthError::UnexpectedError(e) => LoginError::UnexpectedError(e),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let session_id = create_session(&pool, user_id, session_expiration.0)
+        .await
+        .context("Failed to create a session")?;
+    // 登录前请求里还没有用户
+    AuditContext::for_request(&request)
+        .with_user(user_id)
+        .record(pool.get_ref(), AuditAction::Login, None)
+        .await
+        .context("Failed to record the audit log entry")?;
+    let cookie = Cookie::build(SESSION_COOKIE_NAME, session_id)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        // 本地开发使用 http,只有部署在 https 上时才加 Secure
+        .secure(base_url.0.starts_with("https://"))
+        .max_age(actix_web::cookie::time::Duration::seconds(
+            session_expiration.0.as_secs() as i64,
+        ))
+        .finish();
+    Ok(HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/admin/dashboard"))
+        .cookie(cookie)
+        .finish())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn logout(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, LoginError> {
+    if let Some(cookie) = request.cookie(SESSION_COOKIE_NAME) {
+        let user_id = delete_session(&pool, cookie.value())
+            .await
+            .context("Failed to delete the session")?;
+        // 会话已经失效时不算一次登出
+        if let Some(user_id) = user_id {
+            audit
+                .with_user(user_id)
+                .record(pool.get_ref(), AuditAction::Logout, None)
+                .await
+                .context("Failed to record the audit log entry")?;
+        }
+    }
+    let mut removal = Cookie::build(SESSION_COOKIE_NAME, "").path("/").finish();
+    removal.make_removal();
+    Ok(HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(removal)
+        .finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("A one-time code is required.")]
+    SecondFactorRequired,
+    #[error("Too many failed login attempts. Try again later.")]
+    TooManyAttempts(Duration),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LoginError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::AuthError(_) | Self::SecondFactorRequired => StatusCode::UNAUTHORIZED,
+            Self::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = plaintext_error_response(self);
+        if let Self::TooManyAttempts(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+        }
+        response
+    }
+}
@@ -4,7 +4,12 @@
  * @LastEditTime: 2025-07-20 20:14:51
  * @FilePath: /zero2prod/src/routes/mod.rs
  */
+pub mod admin;
 pub mod health_check;
+pub mod login;
 pub mod newsletters;
+pub mod password_reset;
+pub mod subscriber_data;
 pub mod subscriptions;
 pub mod subscriptions_confirm;
+pub mod subscriptions_unsubscribe;
@@ -1,25 +1,20 @@
 use actix_web::{
     HttpRequest, HttpResponse, ResponseError,
-    http::{
-        StatusCode,
-        header::{self, HeaderMap, HeaderValue},
-    },
+    http::{StatusCode, header::HeaderMap},
     web,
 };
 
 use anyhow::Context;
-use argon2::{
-    Argon2,
-    password_hash::{PasswordHash, PasswordVerifier},
-};
-use base64::Engine;
-use secrecy::{ExposeSecret, SecretString};
-use serde::Deserialize;
-use sqlx::PgPool;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 use crate::{
-    domain::SubscriberEmail, email_client::EmailClient, routes::subscriptions::error_chain_fmt,
-    telemetry::spawn_blocking_with_tractiong,
+    audit::{AuditAction, AuditContext},
+    authentication::{Access, Permission, PermissionDenied, UserId},
+    idempotency::{IdempotencyKey, NextAction, save_response, try_processing},
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+    startup::IdempotencyExpiration,
 };
 
 #[derive(Debug, Deserialize)]
@@ -34,138 +29,209 @@ pub struct Content {
     html: String,
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
+// 认证由 `reject_anonymous_users` 完成,会话 cookie 和 Basic 认证都可以
 #[tracing::instrument(
     name = "Publish a newsletters issue",
-    skip(body, pool, email_client, request)
-    fields(username=tracing::field::Empty,user_id=tracing::field::Empty)
+    skip(body, pool, idempotency_expiration, request, user_id, access, audit)
+    fields(user_id=%*user_id)
 )]
 pub async fn publish_newsletters(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    idempotency_expiration: web::Data<IdempotencyExpiration>,
     request: HttpRequest,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-
-    let user_id = validate_credentials(credentials, &pool).await?;
+    let user_id = *user_id.into_inner();
+    access.authorize(Permission::PublishIssue)?;
+
+    let idempotency_key =
+        idempotency_key(request.headers()).map_err(PublishError::ValidationError)?;
+    // 发布记录、投递任务和保存的响应在同一个事务中写入,要么全部成功,要么全部失败
+    let mut transaction =
+        match try_processing(&pool, &idempotency_key, user_id, idempotency_expiration.0).await? {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        };
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    audit
+        .record(
+            &mut *transaction,
+            AuditAction::PublishNewsletter,
+            Some(&issue_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
 
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-    let subscribers = get_confirmed_subscriber(&pool).await?;
+    // 真正的投递交给后台 worker
+    let response = HttpResponse::Accepted().finish();
+    let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+    Ok(response)
+}
 
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "Failed to send newsletter issue to {}",
-                            subscriber.email.as_ref()
-                        )
-                    })?;
-            }
-            Err(err) => {
-                tracing::warn!(error=?err,"Skipping a confirmed subscriber \
-                hteir stored contact details are invalid");
-            }
-        }
-    }
-    Ok(HttpResponse::Ok().finish())
+#[derive(Serialize)]
+struct SavedDraft {
+    newsletter_draft_id: Uuid,
 }
-#[derive(Debug)]
-struct Credentials {
-    username: String,
-    password: SecretString,
+
+/// 保存一期草稿,不会投递给订阅者
+#[tracing::instrument(
+    name = "Save a newsletter draft",
+    skip(body, pool, user_id, access, audit)
+    fields(user_id=%*user_id)
+)]
+pub async fn save_newsletter_draft(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    access: web::ReqData<Access>,
+    audit: AuditContext,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = *user_id.into_inner();
+    access.authorize(Permission::DraftIssue)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let newsletter_draft_id = insert_newsletter_draft(
+        &mut transaction,
+        user_id,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+    )
+    .await
+    .context("Failed to store the newsletter draft")?;
+    audit
+        .record(
+            &mut *transaction,
+            AuditAction::DraftNewsletter,
+            Some(&newsletter_draft_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a newsletter draft.")?;
+    Ok(HttpResponse::Created().json(SavedDraft {
+        newsletter_draft_id,
+    }))
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+fn idempotency_key(headers: &HeaderMap) -> Result<IdempotencyKey, String> {
     let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
+        .get("Idempotency-Key")
+        .ok_or_else(|| "The 'Idempotency-Key' header was missing".to_string())?
         .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string.")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes: Vec<u8> = base64::engine::general_purpose::STANDARD
-        .decode(base64encoded_segment)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-    let decode_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credentials tring is not valid utf8.")?;
-
-    let mut credentials = decode_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'basic' auth."))?
-        .to_string();
-
-    let passowrd = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'basic' auth."))?
-        .to_string();
-
-    Ok(Credentials {
-        username,
-        password: secrecy::SecretString::new(passowrd.into()),
-    })
+        .map_err(|_| "The 'Idempotency-Key' header was not a valid UTF8 string.".to_string())?;
+    header_value.to_owned().try_into()
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscriber(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    // struct Row {
-    //     email: String,
-    // }
-
-    let confirmed_subscribers =
-        sqlx::query!(r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#,)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .map(|e| match SubscriberEmail::parse(e.email) {
-                Ok(email) => Ok(ConfirmedSubscriber { email }),
-                Err(e) => Err(anyhow::anyhow!(e)),
-            })
-            .collect();
-
-    // let mut list = vec![];
-
-    // for data in rows {
-    //     let email = SubscriberEmail::parse(data.email).unwrap();
-    //     list.push(ConfirmedSubscriber { email: email });
-    // }
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
 
-    // Ok(list)
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_draft_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_drafts (
+            newsletter_draft_id,
+            title,
+            text_content,
+            html_content,
+            created_by,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        newsletter_draft_id,
+        title,
+        text_content,
+        html_content,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(newsletter_draft_id)
+}
 
-    // let confirmed_subscribers = rows
-    //     .into_iter()
-    //     .map(|e| match SubscriberEmail::parse(e.email) {
-    //         Ok(email) => Ok(ConfirmedSubscriber { email }),
-    //         Err(err) => Err(anyhow::anyhow!(err)),
-    //     })
-    //     .collect();
-    Ok(confirmed_subscribers)
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Forbidden(#[from] PermissionDenied),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
-    #[error("Authentication failed.")]
-    AuthError(#[source] anyhow::Error),
 }
 
 impl std::fmt::Debug for PublishError {
@@ -177,92 +243,13 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::AuthError(_) => StatusCode::UNAUTHORIZED,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        match self {
-            Self::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
-            Self::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
-            }
-        }
+        plaintext_error_response(self)
     }
 }
-
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
-async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-    let mut user_id = None;
-    let mut expected_password_hash = SecretString::from(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ELAH/Jh1Hw$\
-        CWOrko070JBQ/iyh7uJ0L02aLEfrHWTWLLSAxT0zRno",
-    );
-
-    if let Some((store_user_id, store_password_hash)) =
-        get_stored_credentials(&credentials.username, pool)
-            .await
-            .map_err(PublishError::UnexpectedError)?
-    {
-        user_id = Some(store_user_id);
-        expected_password_hash = store_password_hash;
-    };
-
-    spawn_blocking_with_tractiong(|| {
-        verify_password_hash(expected_password_hash, credentials.password)
-    })
-    .await
-    .context("Failed to spawn blocking taks")
-    .map_err(PublishError::UnexpectedError)??;
-    user_id.ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("Unkown username")))
-}
-
-async fn get_stored_credentials(
-    username: &str,
-    pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, SecretString)>, anyhow::Error> {
-    let row = sqlx::query!(
-        r#"
-         SELECT user_id,password_hash from users WHERE username = $1
-        "#,
-        username,
-    )
-    .fetch_optional(pool)
-    .await
-    .context("Failed to preform a query to validate auth credentials.")?
-    .map(|row| (row.user_id, SecretString::new(row.password_hash.into())));
-
-    Ok(row)
-}
-
-#[tracing::instrument(
-    name = "Verify password hash",
-    skip(expected_passowrd_hash, password_hash)
-)]
-fn verify_password_hash(
-    expected_passowrd_hash: SecretString,
-    password_hash: SecretString,
-) -> Result<(), PublishError> {
-    let expected_password_hash = PasswordHash::new(&expected_passowrd_hash.expose_secret())
-        .context("Failed to PasswordHash ")
-        .map_err(PublishError::UnexpectedError)?;
-
-    Argon2::default()
-        .verify_password(
-            password_hash.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
-        .context("Invalid passowrd")
-        .map_err(PublishError::AuthError)
-}
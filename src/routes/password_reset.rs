@@ -0,0 +1,232 @@
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    http::{StatusCode, header::ContentType},
+    web,
+};
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::Instrument;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    authentication::{
+        PasswordResetError, PasswordResetToken, issue_password_reset_token,
+        password_reset_token_is_valid, reset_password_with_token,
+    },
+    configuration::PasswordHashingSettings,
+    domain::Password,
+    email_client::EmailProvider,
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+    startup::{ApplicationBaseUrl, PasswordResetExpiration},
+};
+
+#[derive(Deserialize)]
+pub struct RequestFormData {
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetFormData {
+    token: String,
+    new_password: SecretString,
+    new_password_check: SecretString,
+}
+
+pub async fn password_reset_request_form() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forgot password</title>
+</head>
+<body>
+    <form action="/password_reset" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <button type="submit">Send me a reset link</button>
+    </form>
+    <p><a href="/login">&lt;- Back</a></p>
+</body>
+</html>"#,
+    )
+}
+
+// 无论用户是否存在、有没有邮箱,都返回同样的响应
+// 邮件在后台发送,响应时间也不会暴露用户是否存在
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, pool, email_client, base_url, expiration),
+    fields(username=%form.username)
+)]
+pub async fn request_password_reset(
+    form: web::Form<RequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailProvider>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    expiration: web::Data<PasswordResetExpiration>,
+) -> Result<HttpResponse, ResetPasswordError> {
+    let reset = issue_password_reset_token(&pool, &form.username, expiration.0).await?;
+    if let Some(reset) = reset {
+        let email_client = email_client.into_inner();
+        let base_url = base_url.into_inner();
+        tokio::spawn(
+            async move {
+                if let Err(e) = send_password_reset_email(&*email_client, &base_url, reset).await {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a password reset email"
+                    );
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        "<p>If the account exists and has an email address, \
+        we have sent it a link to reset the password.</p>",
+    ))
+}
+
+async fn send_password_reset_email(
+    email_client: &dyn EmailProvider,
+    base_url: &ApplicationBaseUrl,
+    reset: PasswordResetToken,
+) -> Result<(), anyhow::Error> {
+    let link = format!(
+        "{}/password_reset/confirm?token={}",
+        base_url.0,
+        reset.token.expose_secret()
+    );
+    let html_body = format!(
+        "Someone asked to reset your password.<br/>\
+        Click <a href=\"{link}\">here</a> to choose a new one. \
+        If it was not you, you can ignore this email."
+    );
+    let text_body = format!(
+        "Someone asked to reset your password. Visit {link} to choose a new one. \
+        If it was not you, you can ignore this email."
+    );
+    email_client
+        .send_email(&reset.email, "Reset your password", &html_body, &text_body)
+        .await
+        .context("Failed to send the password reset email")
+}
+
+#[tracing::instrument(name = "Show the password reset form", skip_all)]
+pub async fn password_reset_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ResetPasswordError> {
+    if !password_reset_token_is_valid(&pool, &parameters.token).await? {
+        return Err(PasswordResetError::InvalidToken.into());
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset Password</title>
+</head>
+<body>
+    <form action="/password_reset/confirm" method="post">
+        <input type="hidden" name="token" value="{}">
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+        htmlescape::encode_attribute(&parameters.token)
+    )))
+}
+
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(form, request, pool, hashing),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn reset_password(
+    form: web::Form<ResetFormData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, ResetPasswordError> {
+    let ResetFormData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.0;
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return Err(ResetPasswordError::ValidationError(
+            "You entered two different new passwords - the field values must match.".into(),
+        ));
+    }
+    let new_password =
+        Password::parse(new_password).map_err(ResetPasswordError::ValidationError)?;
+
+    let user_id = reset_password_with_token(&pool, &token, new_password, **hashing).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    AuditContext::for_request(&request)
+        .with_user(user_id)
+        .record(
+            pool.get_ref(),
+            AuditAction::ResetPassword,
+            Some(&user_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>Your password has been reset.</p><p><a href=\"/login\">Log in</a></p>"))
+}
+
+#[derive(thiserror::Error)]
+pub enum ResetPasswordError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Reset(#[from] PasswordResetError),
+}
+
+impl From<anyhow::Error> for ResetPasswordError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Reset(PasswordResetError::UnexpectedError(e))
+    }
+}
+
+impl std::fmt::Debug for ResetPasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ResetPasswordError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) | Self::Reset(PasswordResetError::InvalidToken) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Reset(PasswordResetError::UnexpectedError(_)) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
@@ -0,0 +1,278 @@
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    http::{
+        StatusCode,
+        header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    },
+    web,
+};
+use anyhow::Context;
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditContext},
+    domain::{SubscriberDataToken, SubscriberEmail},
+    email_client::EmailProvider,
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+    startup::{ApplicationBaseUrl, HmacSecret, SubscriberDataLinkExpiration},
+    subscriber_data::{
+        ErasureRequester, SubscriberData, erase_subscriber, find_subscriber_by_email,
+        get_subscriber_data,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct RequestFormData {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+pub async fn subscriber_data_request_form() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    <p>We will email you a link to download or erase the data we hold about you.</p>
+    <form action="/subscriptions/data_request" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <button type="submit">Send me a link</button>
+    </form>
+</body>
+</html>"#,
+    )
+}
+
+// 和找回密码一样,无论邮箱是否订阅过都返回同样的响应,邮件在后台发送
+#[tracing::instrument(
+    name = "Request subscriber data",
+    skip(form, pool, email_client, base_url, hmac_secret, expiration)
+)]
+pub async fn request_subscriber_data(
+    form: web::Form<RequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailProvider>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    expiration: web::Data<SubscriberDataLinkExpiration>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let email =
+        SubscriberEmail::parse(form.0.email).map_err(SubscriberDataError::ValidationError)?;
+    if let Some(subscriber_id) = find_subscriber_by_email(pool.get_ref(), email.as_ref()).await? {
+        let expires_at = Utc::now()
+            + chrono::Duration::from_std(expiration.0).context("The expiration is too long")?;
+        let token = SubscriberDataToken::generate(subscriber_id, expires_at, &hmac_secret.0);
+        let email_client = email_client.into_inner();
+        let base_url = base_url.into_inner();
+        tokio::spawn(
+            async move {
+                if let Err(e) =
+                    send_subscriber_data_email(&*email_client, &base_url, &email, &token).await
+                {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a subscriber data email"
+                    );
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        "<p>If we hold data about this email address, \
+        we have sent it a link to download or erase it.</p>",
+    ))
+}
+
+async fn send_subscriber_data_email(
+    email_client: &dyn EmailProvider,
+    base_url: &ApplicationBaseUrl,
+    email: &SubscriberEmail,
+    token: &SubscriberDataToken,
+) -> Result<(), anyhow::Error> {
+    let download_link = format!("{}/subscriptions/data?token={}", base_url.0, token.as_ref());
+    let erase_link = format!(
+        "{}/subscriptions/erase?token={}",
+        base_url.0,
+        token.as_ref()
+    );
+    let html_body = format!(
+        "Someone asked for the data we hold about this email address.<br/>\
+        <a href=\"{download_link}\">Download your data</a> as JSON, or \
+        <a href=\"{erase_link}\">erase it</a>. \
+        If it was not you, you can ignore this email."
+    );
+    let text_body = format!(
+        "Someone asked for the data we hold about this email address.\n\
+        Download your data as JSON: {download_link}\n\
+        Erase your data: {erase_link}\n\
+        If it was not you, you can ignore this email."
+    );
+    email_client
+        .send_email(email, "Your data", &html_body, &text_body)
+        .await
+        .context("Failed to send the subscriber data email")
+}
+
+/// 以附件形式返回 JSON
+pub fn subscriber_data_response(data: &SubscriberData) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "subscriber-{}.json",
+                data.subscription.id
+            ))],
+        })
+        .json(data)
+}
+
+#[tracing::instrument(
+    name = "Export subscriber data",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn subscriber_data(
+    parameters: web::Query<Parameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let subscriber_id = verify(&parameters.token, &hmac_secret)?;
+    let data = get_subscriber_data(&pool, subscriber_id)
+        .await?
+        .ok_or(SubscriberDataError::NotFound)?;
+    AuditContext::for_request(&request)
+        .record(
+            pool.get_ref(),
+            AuditAction::ExportSubscriberData,
+            Some(&subscriber_id.to_string()),
+        )
+        .await
+        .context("Failed to record the audit log entry")?;
+    Ok(subscriber_data_response(&data))
+}
+
+// 只展示确认页面,邮件网关预取链接时不能触发擦除
+#[tracing::instrument(name = "Show the erasure page", skip_all)]
+pub async fn erase_form(
+    parameters: web::Query<Parameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    verify(&parameters.token, &hmac_secret)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Erase your data</title>
+</head>
+<body>
+    <p>Do you want us to erase all the data we hold about you?
+    You will stop receiving our newsletter. This cannot be undone.</p>
+    <form action="/subscriptions/erase?token={}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(&parameters.token)
+        )))
+}
+
+#[tracing::instrument(
+    name = "Erase subscriber data",
+    skip_all,
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn erase(
+    parameters: web::Query<Parameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let subscriber_id = verify(&parameters.token, &hmac_secret)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    // 重复提交时数据已经不在了,同样告诉订阅者已擦除
+    if erase_subscriber(
+        &mut transaction,
+        subscriber_id,
+        ErasureRequester::Subscriber,
+    )
+    .await?
+    {
+        AuditContext::for_request(&request)
+            .record(
+                &mut *transaction,
+                AuditAction::EraseSubscriber,
+                Some(&subscriber_id.to_string()),
+            )
+            .await
+            .context("Failed to record the audit log entry")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>Your data has been erased.</p>"))
+}
+
+fn verify(token: &str, hmac_secret: &HmacSecret) -> Result<Uuid, SubscriberDataError> {
+    let subscriber_id = SubscriberDataToken::verify(token, &hmac_secret.0, Utc::now())
+        .map_err(SubscriberDataError::InvalidToken)?;
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+    Ok(subscriber_id)
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberDataError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("We do not hold any data about you.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
@@ -6,24 +6,30 @@ use core::str;
  * @FilePath: /zero2prod/src/routes/subscriptions.rs
  */
 use actix_web::{
-    HttpResponse, ResponseError,
-    http::StatusCode,
+    HttpRequest, HttpResponse, ResponseError,
+    http::{StatusCode, header::ContentType},
     web::{self},
 };
 use anyhow::Context;
 use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use sha3::{Digest, Sha3_256};
 use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
+    consent::{ConsentEvidence, ConsentKind, DEFAULT_SUBSCRIBE_SOURCE, parse_consent_source},
     domain::{NewSubscriber, SubScriberName, SubscriberEmail},
-    email_client::EmailClient,
-    startup::ApplicationBaseUrl,
+    email_client::{EmailProvider, SendEmailError},
+    startup::{ApplicationBaseUrl, PrivacyPolicyVersion, SubscriptionTokenExpiration},
 };
 #[derive(Debug, serde::Deserialize, PartialEq)]
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// 提交表单的页面或渠道,省略时为 `subscribe_form`
+    #[serde(default)]
+    pub source: Option<String>,
 }
 
 // 实现 Display
@@ -44,7 +50,7 @@ impl TryFrom<FormData> for NewSubscriber {
 
 #[tracing::instrument(
     name = "Adding a new subscriber", 
-    skip(form, pool,email_client,base_url),
+    skip(form, request, pool, email_client, base_url, token_expiration, privacy_policy_version),
     fields(
         subscriber_email=%form.email,
         subscriber_name=%form.name
@@ -52,10 +58,18 @@ impl TryFrom<FormData> for NewSubscriber {
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     base_url: web::Data<ApplicationBaseUrl>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailProvider>,
+    token_expiration: web::Data<SubscriptionTokenExpiration>,
+    privacy_policy_version: web::Data<PrivacyPolicyVersion>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let source = match form.source.as_deref() {
+        Some(source) => parse_consent_source(source).map_err(SubscribeError::ValidationError)?,
+        None => DEFAULT_SUBSCRIBE_SOURCE.to_owned(),
+    };
+    let consent = ConsentEvidence::for_request(&request, Some(source), &privacy_policy_version);
     let new_subscriber: NewSubscriber =
         form.0.try_into().map_err(SubscribeError::ValidationError)?;
     let mut transaction = pool
@@ -69,23 +83,63 @@ pub async fn subscribe(
     //     )
     // })?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .context("Failed to insert new subscriber in the database")?;
+        .context("Failed to insert new subscriber in the database")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => match reactivate_existing_subscriber(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to look up the existing subscriber")?
+        {
+            Some(subscriber_id) => subscriber_id,
+            // 已确认的邮箱直接返回,响应与新订阅完全相同,避免泄露哪些邮箱已订阅
+            // 新订阅的确认邮件在后台发送,两者的响应时间也没有差别
+            None => return Ok(HttpResponse::Ok().finish()),
+        },
+    };
+    record_subscribe_consent(&mut transaction, subscriber_id, &consent)
+        .await
+        .context("Failed to record the subscriber's consent")?;
+    // 重复订阅时生成新的令牌,旧的确认链接在过期前仍然有效
     let subscriber_token = generate_subscription_token();
 
-    store_token(&mut transaction, subscriber_id, &subscriber_token)
-        .await
-        .context("Failed to store the confirmation token for a new subscriber")?;
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &subscriber_token,
+        token_expiration.0,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to stroe a new subscriber.")?;
 
-    send_confirmation_email(&email_client, new_subscriber, &base_url, &subscriber_token)
-        .await
-        .context("Failed to send a confirmation email")?;
+    // 邮件在后台发送,发送失败或耗时都不会体现在响应上
+    let email_client = email_client.into_inner();
+    let base_url = base_url.into_inner();
+    tokio::spawn(
+        async move {
+            if let Err(e) = send_confirmation_email(
+                &*email_client,
+                new_subscriber,
+                &base_url,
+                &subscriber_token,
+            )
+            .await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email"
+                );
+            }
+        }
+        .in_current_span(),
+    );
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -95,11 +149,11 @@ pub async fn subscribe(
     skip(email_client, new_subscriber, base_url)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
+    email_client: &dyn EmailProvider,
     new_subscriber: NewSubscriber,
     base_url: &ApplicationBaseUrl,
     token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     let link = format!(
         "{}/subscriptions/confirm?subscription_token={token}",
         base_url.0
@@ -118,15 +172,18 @@ pub async fn send_confirmation_email(
         .await
 }
 
+/// 邮箱已存在时不插入,返回 `None`
 pub async fn insert_subscriber(
     transation: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = uuid::Uuid::new_v4();
 
-    sqlx::query!(
+    // ON CONFLICT 同时处理了并发的重复订阅
+    let inserted = sqlx::query!(
         r#"INSERT INTO subscriptions (id,email,name,subscribed_at,status)
         VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email) DO NOTHING
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
@@ -139,12 +196,61 @@ pub async fn insert_subscriber(
     .map_err(|e| {
         tracing::error!("Failed to execute query:{:?}", e);
         e
-    })?;
+    })?
+    .rows_affected();
+
+    Ok((inserted > 0).then_some(subscriber_id))
+}
+
+/// 处理已存在的邮箱,返回需要重新发送确认邮件的订阅者
+/// 待确认的直接重发,已退订的回到待确认状态重新走一遍确认流程,已确认的什么都不做
+#[tracing::instrument(name = "Reactivate an existing subscriber", skip_all)]
+async fn reactivate_existing_subscriber(
+    transation: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let existing = sqlx::query!(
+        r#"SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_one(&mut **transation)
+    .await?;
 
-    Ok(subscriber_id)
+    match existing.status.as_str() {
+        "confirmed" => Ok(None),
+        "unsubscribed" => {
+            sqlx::query!(
+                r#"UPDATE subscriptions SET status = 'pending_confirmation' WHERE id = $1"#,
+                existing.id
+            )
+            .execute(&mut **transation)
+            .await?;
+            Ok(Some(existing.id))
+        }
+        _ => Ok(Some(existing.id)),
+    }
+}
+
+/// 每次提交表单都留一条记录,订阅上的来源更新为最近一次的来源
+#[tracing::instrument(name = "Record subscribe consent", skip(transaction, consent))]
+async fn record_subscribe_consent(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    consent: &ConsentEvidence,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET consent_source = $2 WHERE id = $1"#,
+        subscriber_id,
+        consent.source
+    )
+    .execute(&mut **transaction)
+    .await?;
+    consent
+        .record(&mut **transaction, subscriber_id, ConsentKind::Subscribe)
+        .await
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     // repeat_with 会不停的调用这个笔包生成器
     std::iter::repeat_with(|| {
@@ -159,22 +265,30 @@ fn generate_subscription_token() -> String {
     .collect()
 }
 
+/// 数据库里只保存令牌的摘要,拿到数据库读权限也无法确认订阅
+/// 令牌本身是随机的,不需要加盐或慢哈希
+pub fn hash_subscription_token(subscription_token: &str) -> String {
+    format!("{:x}", Sha3_256::digest(subscription_token.as_bytes()))
+}
+
 #[tracing::instrument(
     name = "Store subscription token in the database.",
-    skip(transation, subscriber_id, subscriber_token)
+    skip(transation, subscriber_id, subscriber_token, expiration)
 )]
-async fn store_token(
+pub async fn store_token(
     transation: &mut Transaction<'_, Postgres>,
     subscriber_id: uuid::Uuid,
     subscriber_token: &str,
+    expiration: std::time::Duration,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
         r#"
-            INSERT INTO subscription_tokens ( subscription_token,subscription_id )
-            VALUES ($1,$2)
+            INSERT INTO subscription_tokens ( subscription_token_hash, subscription_id, created_at, expires_at )
+            VALUES ($1, $2, now(), now() + make_interval(secs => $3))
         "#,
-        subscriber_token,
-        subscriber_id
+        hash_subscription_token(subscriber_token),
+        subscriber_id,
+        expiration.as_secs_f64()
     )
     .execute(&mut **transation)
     .await
@@ -211,7 +325,7 @@ pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
-    write!(f, "{}\n", e)?;
+    writeln!(f, "{}", e)?;
 
     let mut current = e.source();
     while let Some(cause) = current {
@@ -221,6 +335,18 @@ pub fn error_chain_fmt(
     Ok(())
 }
 
+/// 客户端错误以纯文本返回原因,方便调用方修正请求
+/// 服务器内部错误只返回状态码,细节只记录在日志里
+pub fn plaintext_error_response(e: &impl ResponseError) -> HttpResponse {
+    let status = e.status_code();
+    if status.is_server_error() {
+        return HttpResponse::new(status);
+    }
+    HttpResponse::build(status)
+        .content_type(ContentType::plaintext())
+        .body(e.to_string())
+}
+
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     // #[error("Failed to insert new subscriber in the database")]
@@ -291,6 +417,10 @@ impl ResponseError for SubscribeError {
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
 }
 
 impl std::fmt::Debug for SubscribeError {
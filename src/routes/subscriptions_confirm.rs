@@ -4,9 +4,25 @@
  * @LastEditTime: 2025-07-18 15:58:16
  * @FilePath: /zero2prod/src/routes/subscriptions_confirm.rs
  */
-use actix_web::{HttpResponse, web};
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    http::{StatusCode, header::ContentType},
+    web,
+};
+use anyhow::Context;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+    consent::{ConsentEvidence, ConsentKind},
+    domain::{NewSubscriber, SubScriberName, SubscriberEmail},
+    email_client::EmailProvider,
+    routes::subscriptions::{
+        error_chain_fmt, generate_subscription_token, hash_subscription_token,
+        plaintext_error_response, send_confirmation_email, store_token,
+    },
+    startup::{ApplicationBaseUrl, PrivacyPolicyVersion, SubscriptionTokenExpiration},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Parameters {
@@ -14,55 +30,274 @@ pub struct Parameters {
 }
 
 // 确认一个打开的订阅
-#[tracing::instrument(name = "confrim opending a subscribe", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(e) => e,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-    match id {
-        Some(id) => {
-            if confirm_subscriber(&pool, id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
-        None => return HttpResponse::Unauthorized().finish(),
+// 令牌只能使用一次,确认成功后和订阅状态在同一个事务里删除
+#[tracing::instrument(
+    name = "confrim opending a subscribe",
+    skip(parameters, request, pool, privacy_policy_version)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    privacy_policy_version: web::Data<PrivacyPolicyVersion>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let token = get_subscriber_id_from_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to look up the subscription token")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if token.expired {
+        // 过期的令牌留给清理任务删除,这样重复点击仍然能看到重发的入口
+        return Ok(expired_token_page(&parameters.subscription_token));
     }
+
+    let confirmed = confirm_subscriber(&mut transaction, token.subscription_id)
+        .await
+        .context("Failed to mark the subscriber as confirmed")?;
+    // 已退订的订阅者用旧链接不会被确认,也就没有新的同意
+    if let Some(confirmed) = confirmed {
+        ConsentEvidence::for_request(&request, confirmed.consent_source, &privacy_policy_version)
+            .record(
+                &mut *transaction,
+                token.subscription_id,
+                ConsentKind::Confirm,
+            )
+            .await
+            .context("Failed to record the subscriber's consent")?;
+    }
+    delete_tokens(&mut transaction, token.subscription_id)
+        .await
+        .context("Failed to delete the used subscription tokens")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn expired_token_page(subscription_token: &str) -> HttpResponse {
+    HttpResponse::Gone()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation link expired</title>
+</head>
+<body>
+    <p>This confirmation link has expired.</p>
+    <form action="/subscriptions/confirm/resend?subscription_token={}" method="post">
+        <button type="submit">Send me a new confirmation email</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(subscription_token)
+        ))
+}
+
+// 用过期(或未过期)的令牌换一封新的确认邮件
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(parameters, pool, email_client, base_url, token_expiration)
+)]
+pub async fn resend_confirmation(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailProvider>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_expiration: web::Data<SubscriptionTokenExpiration>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    let subscriber =
+        get_pending_subscriber_from_token(&mut transaction, &parameters.subscription_token)
+            .await
+            .context("Failed to look up the subscription token")?
+            .ok_or(ConfirmError::UnknownToken)?;
+    let subscriber_id = subscriber.id;
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(subscriber.email).map_err(anyhow::Error::msg)?,
+        name: SubScriberName::parse(subscriber.name).map_err(anyhow::Error::msg)?,
+    };
+
+    delete_tokens(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to delete the old subscription tokens")?;
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &subscription_token,
+        token_expiration.0,
+    )
+    .await
+    .context("Failed to store the confirmation token for a subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new confirmation token.")?;
+
+    send_confirmation_email(
+        email_client.as_ref(),
+        new_subscriber,
+        &base_url,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send a confirmation email")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>A new confirmation email is on its way.</p>"))
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool, subscriber_id))]
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(transaction, subscriber_id)
+)]
 pub async fn confirm_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: uuid::Uuid,
+) -> Result<Option<ConfirmedSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ConfirmedSubscriber,
+        // 已退订的订阅者不能通过旧的确认链接重新订阅
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
+        RETURNING consent_source
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+/// 确认时沿用订阅时的来源
+pub struct ConfirmedSubscriber {
+    pub consent_source: Option<String>,
+}
+
+#[tracing::instrument(name = "Delete subscription tokens", skip(transaction, subscriber_id))]
+async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: uuid::Uuid,
 ) -> Result<(), sqlx::Error> {
+    // 同一个订阅者可能有多个未使用的令牌(重复订阅),一起作废
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        r#"DELETE FROM subscription_tokens WHERE subscription_id = $1"#,
         subscriber_id
     )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .execute(&mut **transaction)
+    .await?;
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscription_token))]
+pub struct StoredToken {
+    pub subscription_id: uuid::Uuid,
+    pub expired: bool,
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from token",
+    skip(transaction, subscription_token)
+)]
 pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<uuid::Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscription_id FROM subscription_tokens WHERE subscription_token = $1"#,
-        subscription_token
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    // 加锁,避免同一个令牌被并发使用两次
+    sqlx::query_as!(
+        StoredToken,
+        r#"
+        SELECT subscription_id, expires_at < now() as "expired!"
+        FROM subscription_tokens
+        WHERE subscription_token_hash = $1
+        FOR UPDATE
+        "#,
+        hash_subscription_token(subscription_token)
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut **transaction)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execture query:{:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscription_id))
+}
+
+struct PendingSubscriber {
+    id: uuid::Uuid,
+    email: String,
+    name: String,
+}
+
+async fn get_pending_subscriber_from_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<PendingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingSubscriber,
+        r#"
+        SELECT s.id, s.email, s.name
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscription_id
+        WHERE t.subscription_token_hash = $1 AND s.status = 'pending_confirmation'
+        FOR UPDATE OF t
+        "#,
+        hash_subscription_token(subscription_token)
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+/// 清理过期的确认令牌
+pub async fn run_token_cleanup_until_stopped(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        if let Err(e) = delete_expired_tokens(&pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete expired subscription tokens"
+            );
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(60 * 60)).await;
+    }
+}
+
+#[tracing::instrument(name = "Delete expired subscription tokens", skip(pool))]
+pub async fn delete_expired_tokens(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let n_deleted_rows =
+        sqlx::query!(r#"DELETE FROM subscription_tokens WHERE expires_at < now()"#)
+            .execute(pool)
+            .await?
+            .rows_affected();
+    Ok(n_deleted_rows)
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("The subscription token is not valid")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
 }
@@ -0,0 +1,123 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::{StatusCode, header::ContentType},
+    web,
+};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::UnsubscribeToken,
+    routes::subscriptions::{error_chain_fmt, plaintext_error_response},
+    startup::{ApplicationBaseUrl, HmacSecret},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+/// 每个订阅者固定的退订链接,放在 newsletter 的 `List-Unsubscribe` 头和页脚里
+pub fn unsubscribe_link(
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    subscriber_id: Uuid,
+) -> String {
+    let token = UnsubscribeToken::generate(subscriber_id, &hmac_secret.0);
+    format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url.0,
+        token.as_ref()
+    )
+}
+
+// 只展示确认页面,不修改状态
+// 邮件网关和安全扫描会预取链接,真正的退订必须通过 POST
+#[tracing::instrument(name = "Show the unsubscribe page", skip_all)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<Parameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?token={}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            parameters.token
+        )))
+}
+
+// 表单提交和 RFC 8058 的一键退订都会走到这里
+// 一键退订的请求体固定是 `List-Unsubscribe=One-Click`,不需要解析
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip_all, fields(subscriber_id = tracing::field::Empty))]
+pub async fn unsubscribe(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+    mark_subscriber_as_unsubscribed(&pool, subscriber_id)
+        .await
+        .context("Failed to mark the subscriber as unsubscribed")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>You have been unsubscribed.</p>"))
+}
+
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        plaintext_error_response(self)
+    }
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
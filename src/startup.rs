@@ -4,43 +4,81 @@
  * @LastEditTime: 2025-07-20 20:17:20
  * @FilePath: /zero2prod/src/startup.rs
  */
-use std::net::TcpListener;
+use std::{net::TcpListener, sync::Arc, time::Duration};
 
-use actix_web::{App, HttpServer, dev::Server, web};
+use actix_web::{App, HttpServer, dev::Server, middleware::from_fn, web};
+use secrecy::SecretString;
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
+    authentication::{reject_anonymous_users, run_login_failure_cleanup_until_stopped},
+    configuration::{
+        AoolicationSettings, DatabaseSettings, LoginThrottlingSettings, PasswordHashingSettings,
+        Settings,
+    },
+    email_client::EmailProvider,
+    idempotency::run_expiry_worker_until_stopped,
+    issue_delivery_worker::run_worker_until_stopped,
     routes::{
-        health_check::health_check, newsletters::publish_newsletters, subscriptions::subscribe,
-        subscriptions_confirm::confirm,
+        admin::{
+            admin_dashboard, admin_erase_subscriber, admin_subscriber_data, audit_log_handler,
+            begin_totp_enrolment_handler, bulk_change_subscribers, change_password_form,
+            change_password_handler, confirm_subscriber, confirm_totp_enrolment_handler,
+            consents_handler, create_api_token_handler, delete_subscriber, disable_totp_handler,
+            export_subscribers, import_subscribers, list_api_tokens_handler, list_subscribers,
+            revoke_api_token_handler, unsubscribe_subscriber,
+        },
+        health_check::health_check,
+        login::{login, login_form, logout},
+        newsletters::{publish_newsletters, save_newsletter_draft},
+        password_reset::{
+            password_reset_form, password_reset_request_form, request_password_reset,
+            reset_password,
+        },
+        subscriber_data::{
+            erase, erase_form, request_subscriber_data, subscriber_data,
+            subscriber_data_request_form,
+        },
+        subscriptions::subscribe,
+        subscriptions_confirm::{confirm, resend_confirmation, run_token_cleanup_until_stopped},
+        subscriptions_unsubscribe::{unsubscribe, unsubscribe_form},
     },
 };
 
 pub struct Application {
     port: u16,
     server: Server,
+    connection_pool: PgPool,
+    email_client: Arc<dyn EmailProvider>,
+    idempotency_expiration: Duration,
+    base_url: String,
+    hmac_secret: SecretString,
+    login_throttling: LoginThrottlingSettings,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct HmacSecret(pub SecretString);
+
+pub struct IdempotencyExpiration(pub Duration);
+
+pub struct SubscriptionTokenExpiration(pub Duration);
+
+pub struct SessionExpiration(pub Duration);
+
+pub struct PasswordResetExpiration(pub Duration);
+
+pub struct SubscriberDataLinkExpiration(pub Duration);
+
+pub struct PrivacyPolicyVersion(pub String);
+
+pub struct TrustedProxies(pub usize);
+
 impl Application {
     pub async fn build(config: &Settings) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&config.database);
-
-        let sender = config
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-        let timeout = config.email_client.timeout();
-        let email_client = EmailClient::new(
-            config.email_client.base_url.clone(),
-            sender,
-            config.email_client.authorization_token.clone(),
-            timeout,
-        );
+        let email_client = config.email_client.client();
 
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address)?;
@@ -48,19 +86,68 @@ impl Application {
         let port = listener.local_addr().unwrap().port();
         let server = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
             email_client,
-            config.application.base_url.clone(),
+            &config.application,
+            config.password_hashing,
+            config.login_throttling.clone(),
         )?;
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            connection_pool,
+            // 后台投递 worker 使用独立的 client
+            email_client: config.email_client.client(),
+            idempotency_expiration: config.application.idempotency_expiration(),
+            base_url: config.application.base_url.clone(),
+            hmac_secret: config.application.hmac_secret.clone(),
+            login_throttling: config.login_throttling.clone(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// HTTP 服务和后台 worker 一起运行,任意一个退出都会结束整个应用
     pub async fn run_until_stoppend(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let worker = run_worker_until_stopped(
+            self.connection_pool.clone(),
+            self.email_client,
+            ApplicationBaseUrl(self.base_url),
+            HmacSecret(self.hmac_secret),
+        );
+        let expiry_worker = run_expiry_worker_until_stopped(
+            self.connection_pool.clone(),
+            self.idempotency_expiration,
+        );
+        let login_failure_cleanup = run_login_failure_cleanup_until_stopped(
+            self.connection_pool.clone(),
+            self.login_throttling,
+        );
+        let token_cleanup = run_token_cleanup_until_stopped(self.connection_pool);
+        tokio::select! {
+            outcome = self.server => {
+                tracing::info!("API has exited");
+                outcome
+            }
+            outcome = worker => {
+                tracing::error!("Background worker has exited");
+                outcome.map_err(std::io::Error::other)
+            }
+            outcome = expiry_worker => {
+                tracing::error!("Idempotency expiry worker has exited");
+                outcome.map_err(std::io::Error::other)
+            }
+            outcome = token_cleanup => {
+                tracing::error!("Subscription token cleanup worker has exited");
+                outcome.map_err(std::io::Error::other)
+            }
+            outcome = login_failure_cleanup => {
+                tracing::error!("Login failure cleanup worker has exited");
+                outcome.map_err(std::io::Error::other)
+            }
+        }
     }
 }
 
@@ -73,22 +160,136 @@ pub fn get_connection_pool(database_config: &DatabaseSettings) -> PgPool {
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
+    email_client: Arc<dyn EmailProvider>,
+    config: &AoolicationSettings,
+    password_hashing: PasswordHashingSettings,
+    login_throttling: LoginThrottlingSettings,
 ) -> Result<Server, std::io::Error> {
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client: web::Data<dyn EmailProvider> = web::Data::from(email_client);
+    let base_url = web::Data::new(ApplicationBaseUrl(config.base_url.clone()));
+    let idempotency_expiration =
+        web::Data::new(IdempotencyExpiration(config.idempotency_expiration()));
+    let hmac_secret = web::Data::new(HmacSecret(config.hmac_secret.clone()));
+    let subscription_token_expiration = web::Data::new(SubscriptionTokenExpiration(
+        config.subscription_token_expiration(),
+    ));
+    let session_expiration = web::Data::new(SessionExpiration(config.session_expiration()));
+    let password_reset_expiration =
+        web::Data::new(PasswordResetExpiration(config.password_reset_expiration()));
+    let subscriber_data_link_expiration = web::Data::new(SubscriberDataLinkExpiration(
+        config.subscriber_data_link_expiration(),
+    ));
+    let privacy_policy_version =
+        web::Data::new(PrivacyPolicyVersion(config.privacy_policy_version.clone()));
+    let trusted_proxies = web::Data::new(TrustedProxies(config.trusted_proxies));
+    let password_hashing = web::Data::new(password_hashing);
+    let login_throttling = web::Data::new(login_throttling);
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .route("/logout", web::post().to(logout))
+            .route(
+                "/password_reset",
+                web::get().to(password_reset_request_form),
+            )
+            .route("/password_reset", web::post().to(request_password_reset))
+            .route(
+                "/password_reset/confirm",
+                web::get().to(password_reset_form),
+            )
+            .route("/password_reset/confirm", web::post().to(reset_password))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
-            .route("/newsletters", web::post().to(publish_newsletters))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/data_request",
+                web::get().to(subscriber_data_request_form),
+            )
+            .route(
+                "/subscriptions/data_request",
+                web::post().to(request_subscriber_data),
+            )
+            .route("/subscriptions/data", web::get().to(subscriber_data))
+            .route("/subscriptions/erase", web::get().to(erase_form))
+            .route("/subscriptions/erase", web::post().to(erase))
+            // 保留给已有的 API 客户端
+            .service(
+                web::resource("/newsletters")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route(web::post().to(publish_newsletters)),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password_handler))
+                    .route("/newsletters", web::post().to(publish_newsletters))
+                    .route("/newsletters/drafts", web::post().to(save_newsletter_draft))
+                    .route("/api_tokens", web::get().to(list_api_tokens_handler))
+                    .route("/api_tokens", web::post().to(create_api_token_handler))
+                    .route(
+                        "/api_tokens/{api_token_id}",
+                        web::delete().to(revoke_api_token_handler),
+                    )
+                    .route("/totp", web::post().to(begin_totp_enrolment_handler))
+                    .route(
+                        "/totp/confirm",
+                        web::post().to(confirm_totp_enrolment_handler),
+                    )
+                    .route("/totp/disable", web::post().to(disable_totp_handler))
+                    .route("/audit_log", web::get().to(audit_log_handler))
+                    .route("/consents", web::get().to(consents_handler))
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    .route("/subscribers/bulk", web::post().to(bulk_change_subscribers))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route("/subscribers/import", web::post().to(import_subscribers))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::delete().to(delete_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        web::post().to(unsubscribe_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/data",
+                        web::get().to(admin_subscriber_data),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/erase",
+                        web::post().to(admin_erase_subscriber),
+                    ),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(idempotency_expiration.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(subscription_token_expiration.clone())
+            .app_data(session_expiration.clone())
+            .app_data(password_reset_expiration.clone())
+            .app_data(subscriber_data_link_expiration.clone())
+            .app_data(privacy_policy_version.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(password_hashing.clone())
+            .app_data(login_throttling.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,242 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::consent::{ConsentRecord, subscriber_consents};
+
+/// 数据主体访问请求的答复: 我们保存的和这个订阅者有关的所有数据
+#[derive(Serialize)]
+pub struct SubscriberData {
+    pub subscription: SubscriptionRecord,
+    /// 只导出时间,令牌本身只保存了哈希
+    pub subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+    /// 已经入队但还没有投递的 issue
+    pub pending_deliveries: Vec<PendingDeliveryRecord>,
+    pub consent_records: Vec<ConsentRecord>,
+    pub exported_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub consent_source: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionTokenRecord {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub error_code: Option<i32>,
+    pub error_message: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PendingDeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+}
+
+/// 订阅者不存在时返回 `None`
+#[tracing::instrument(name = "Collect subscriber data", skip(pool))]
+pub async fn get_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberData>, anyhow::Error> {
+    // 在同一个快照里读取,各部分之间保持一致
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a postgres connection from the pool")?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to start a read-only transaction")?;
+    let Some(subscription) = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, status, subscribed_at, consent_source
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscription")?
+    else {
+        return Ok(None);
+    };
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"
+        SELECT created_at, expires_at
+        FROM subscription_tokens
+        WHERE subscription_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscription tokens")?;
+    // 投递记录按邮箱保存
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT l.newsletter_issue_id, i.title, l.status, l.error_code, l.error_message, l.attempted_at
+        FROM issue_delivery_log l
+        JOIN newsletter_issues i ON i.newsletter_issue_id = l.newsletter_issue_id
+        WHERE l.subscriber_email = $1
+        ORDER BY l.attempted_at
+        "#,
+        subscription.email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the delivery history")?;
+    let pending_deliveries = sqlx::query_as!(
+        PendingDeliveryRecord,
+        r#"
+        SELECT q.newsletter_issue_id, i.title
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.subscriber_email = $1
+        ORDER BY i.published_at
+        "#,
+        subscription.email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the pending deliveries")?;
+    let consent_records =
+        subscriber_consents(&mut *transaction, subscriber_id, &subscription.email)
+            .await
+            .context("Failed to fetch the consent records")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the read-only transaction")?;
+
+    Ok(Some(SubscriberData {
+        subscription,
+        subscription_tokens,
+        deliveries,
+        pending_deliveries,
+        consent_records,
+        exported_at: Utc::now(),
+    }))
+}
+
+#[tracing::instrument(name = "Find a subscriber by email", skip(executor, email))]
+pub async fn find_subscriber_by_email(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_optional(executor)
+        .await
+        .context("Failed to look up the subscriber")
+}
+
+/// 谁发起了擦除,记录在墓碑里
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErasureRequester {
+    Subscriber,
+    Admin,
+}
+
+impl ErasureRequester {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErasureRequester::Subscriber => "subscriber",
+            ErasureRequester::Admin => "admin",
+        }
+    }
+}
+
+/// 删除订阅、确认令牌和同意记录,丢弃还没投递的任务,投递记录里的邮箱换成匿名标识,
+/// 最后留下只有 id 和时间的墓碑
+/// 订阅者不存在时返回 `false`
+#[tracing::instrument(name = "Erase a subscriber", skip(transaction))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    requested_by: ErasureRequester,
+) -> Result<bool, anyhow::Error> {
+    let Some(subscription) = sqlx::query!(
+        "SELECT email, subscribed_at FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to lock the subscription")?
+    else {
+        return Ok(false);
+    };
+
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1",
+        subscription.email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete pending deliveries")?;
+    // 保留投递状态用于统计,错误信息里可能带有邮箱,一并清除
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_log
+        SET subscriber_email = 'erased:' || $2::uuid, error_message = NULL
+        WHERE subscriber_email = $1
+        "#,
+        subscription.email,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to anonymise the delivery history")?;
+    // 后台删除订阅者时同意记录会保留下来,擦除时连同之前的记录一起删除
+    let consents_erased = sqlx::query!(
+        "DELETE FROM subscription_consents WHERE subscriber_id = $1 OR email = $2",
+        subscriber_id,
+        subscription.email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the consent records")?
+    .rows_affected();
+    // 确认令牌通过外键级联删除
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to delete the subscription")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_erasures
+            (subscriber_id, subscribed_at, erased_at, requested_by, consents_erased)
+        VALUES ($1, $2, now(), $3, $4)
+        "#,
+        subscriber_id,
+        subscription.subscribed_at,
+        requested_by.as_str(),
+        consents_erased as i32
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the erasure")?;
+    Ok(true)
+}
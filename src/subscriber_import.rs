@@ -0,0 +1,520 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use csv_core::ReadRecordResult;
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    consent::{ConsentEvidence, ConsentKind, parse_consent_source},
+    domain::{NewSubscriber, SubscriptionStatus},
+    email_client::EmailProvider,
+    routes::subscriptions::{
+        FormData, error_chain_fmt, generate_subscription_token, send_confirmation_email,
+        store_token,
+    },
+    startup::ApplicationBaseUrl,
+};
+
+/// 导入选项
+/// 直接确认的订阅者不会收到确认邮件,必须说明同意订阅的来源
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    pub pre_confirmed: bool,
+    pub consent_source: Option<String>,
+}
+
+impl ImportOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        let consent_source = self
+            .consent_source
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty());
+        match (self.pre_confirmed, consent_source) {
+            (true, None) => Err("Pre-confirmed imports must record a consent source.".into()),
+            (false, Some(_)) => {
+                Err("A consent source can only be recorded for pre-confirmed imports.".into())
+            }
+            (true, Some(source)) => parse_consent_source(source).map(|_| ()),
+            (false, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum RowOutcome {
+    Accepted,
+    /// 邮箱已经存在,包括同一个文件中重复出现的邮箱
+    Duplicate,
+    Rejected {
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowReport {
+    /// 表头是第 1 行
+    pub row: u64,
+    pub email: String,
+    #[serde(flatten)]
+    pub outcome: RowOutcome,
+}
+
+/// 报告里最多列出的行数
+const MAX_REPORTED_ROWS: usize = 1000;
+
+/// 同时在发送的确认邮件数
+const MAX_CONCURRENT_EMAILS: usize = 4;
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub accepted: u64,
+    pub duplicates: u64,
+    pub rejected: u64,
+    /// 只列出重复和被拒绝的行,最多 `MAX_REPORTED_ROWS` 行
+    pub rows: Vec<RowReport>,
+    /// 超出上限的行没有列出
+    pub truncated: bool,
+}
+
+impl ImportReport {
+    fn push(&mut self, row: RowReport) {
+        match row.outcome {
+            RowOutcome::Accepted => {
+                self.accepted += 1;
+                return;
+            }
+            RowOutcome::Duplicate => self.duplicates += 1,
+            RowOutcome::Rejected { .. } => self.rejected += 1,
+        }
+        if self.rows.len() < MAX_REPORTED_ROWS {
+            self.rows.push(row);
+        } else {
+            self.truncated = true;
+        }
+    }
+}
+
+/// 导入时在后台发送的确认邮件
+pub struct ConfirmationEmails(JoinSet<()>);
+
+impl ConfirmationEmails {
+    /// 等待全部发送完,命令行在退出前调用
+    pub async fn wait(self) {
+        self.0.join_all().await;
+    }
+
+    /// 不等待,邮件在请求结束后继续发送
+    pub fn detach(mut self) {
+        self.0.detach_all();
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// 按块接收 CSV,每解析出一行就立即写入数据库,不需要把整个文件读进内存
+/// 第一行必须是表头,包含 `email` 和 `name` 两列,其他列会被忽略
+/// 确认邮件在每一行提交之后在后台发送,不会拖慢导入
+pub struct SubscriberImport<'a> {
+    pool: &'a PgPool,
+    email_client: Arc<dyn EmailProvider>,
+    base_url: Arc<ApplicationBaseUrl>,
+    token_expiration: Duration,
+    options: ImportOptions,
+    parser: CsvParser,
+    columns: Option<Columns>,
+    row: u64,
+    report: ImportReport,
+    emails: JoinSet<()>,
+    email_permits: Arc<Semaphore>,
+}
+
+// 导入中途出错时,已经提交的行的确认邮件照常发送
+impl Drop for SubscriberImport<'_> {
+    fn drop(&mut self) {
+        self.emails.detach_all();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+impl<'a> SubscriberImport<'a> {
+    pub fn new(
+        pool: &'a PgPool,
+        email_client: Arc<dyn EmailProvider>,
+        base_url: Arc<ApplicationBaseUrl>,
+        token_expiration: Duration,
+        options: ImportOptions,
+    ) -> Result<Self, ImportError> {
+        options.validate().map_err(ImportError::ValidationError)?;
+        Ok(Self {
+            pool,
+            email_client,
+            base_url,
+            token_expiration,
+            options,
+            parser: CsvParser::new(),
+            columns: None,
+            row: 0,
+            report: ImportReport::default(),
+            emails: JoinSet::new(),
+            email_permits: Arc::new(Semaphore::new(MAX_CONCURRENT_EMAILS)),
+        })
+    }
+
+    pub async fn feed(&mut self, chunk: &[u8]) -> Result<(), ImportError> {
+        let records = self.parser.parse(chunk);
+        self.import_records(records).await
+    }
+
+    pub async fn finish(mut self) -> Result<(ImportReport, ConfirmationEmails), ImportError> {
+        let records = self.parser.finish();
+        self.import_records(records).await?;
+        if self.columns.is_none() {
+            return Err(ImportError::ValidationError(
+                "The CSV file is empty.".into(),
+            ));
+        }
+        let report = std::mem::take(&mut self.report);
+        let emails = ConfirmationEmails(std::mem::take(&mut self.emails));
+        Ok((report, emails))
+    }
+
+    async fn import_records(&mut self, records: Vec<Vec<Vec<u8>>>) -> Result<(), ImportError> {
+        for record in records {
+            self.row += 1;
+            match self.columns {
+                None => self.columns = Some(parse_header(&record)?),
+                Some(columns) => {
+                    let row = self.import_record(columns, record).await?;
+                    self.report.push(row);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Import a subscriber", skip(self, columns, record), fields(row = self.row))]
+    async fn import_record(
+        &mut self,
+        columns: Columns,
+        record: Vec<Vec<u8>>,
+    ) -> Result<RowReport, anyhow::Error> {
+        let field = |index: usize, column: &str| -> Result<String, String> {
+            let value = record
+                .get(index)
+                .ok_or_else(|| format!("The {column} column is missing."))?;
+            String::from_utf8(value.clone())
+                .map(|value| value.trim().to_owned())
+                .map_err(|_| format!("The {column} column is not valid UTF-8."))
+        };
+        let email = field(columns.email, "email");
+        let row = self.row;
+        let report = |outcome| RowReport {
+            row,
+            email: email.clone().unwrap_or_default(),
+            outcome,
+        };
+        let new_subscriber = email.clone().and_then(|email| {
+            NewSubscriber::try_from(FormData {
+                email,
+                name: field(columns.name, "name")?,
+                source: None,
+            })
+        });
+        let new_subscriber = match new_subscriber {
+            Ok(new_subscriber) => new_subscriber,
+            Err(error) => return Ok(report(RowOutcome::Rejected { error })),
+        };
+        let outcome = self.insert(new_subscriber).await?;
+        Ok(report(outcome))
+    }
+
+    async fn insert(&mut self, new_subscriber: NewSubscriber) -> Result<RowOutcome, anyhow::Error> {
+        let status = if self.options.pre_confirmed {
+            SubscriptionStatus::Confirmed
+        } else {
+            SubscriptionStatus::PendingConfirmation
+        };
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a postgres connection from the pool")?;
+        let subscriber_id = Uuid::new_v4();
+        let n_inserted = sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, consent_source)
+            VALUES ($1, $2, $3, now(), $4, $5)
+            ON CONFLICT (email) DO NOTHING
+            "#,
+            subscriber_id,
+            new_subscriber.email.as_ref(),
+            new_subscriber.name.as_ref(),
+            status.as_str(),
+            self.options.consent_source.as_deref().map(str::trim)
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to insert an imported subscriber")?
+        .rows_affected();
+        if n_inserted == 0 {
+            return Ok(RowOutcome::Duplicate);
+        }
+
+        let subscription_token = if self.options.pre_confirmed {
+            let source = self.options.consent_source.as_deref().unwrap_or_default();
+            ConsentEvidence::imported(source.trim().to_owned())
+                .record(&mut *transaction, subscriber_id, ConsentKind::Import)
+                .await
+                .context("Failed to record the consent of an imported subscriber")?;
+            None
+        } else {
+            let subscription_token = generate_subscription_token();
+            store_token(
+                &mut transaction,
+                subscriber_id,
+                &subscription_token,
+                self.token_expiration,
+            )
+            .await
+            .context("Failed to store the confirmation token for an imported subscriber")?;
+            Some(subscription_token)
+        };
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to import a subscriber.")?;
+
+        if let Some(subscription_token) = subscription_token {
+            self.send_confirmation_email(new_subscriber, subscription_token);
+        }
+        Ok(RowOutcome::Accepted)
+    }
+
+    /// 发送失败只记录日志,订阅者重新订阅时会收到新的确认邮件
+    fn send_confirmation_email(&mut self, new_subscriber: NewSubscriber, token: String) {
+        let email_client = self.email_client.clone();
+        let base_url = self.base_url.clone();
+        let permits = self.email_permits.clone();
+        self.emails.spawn(
+            async move {
+                let _permit = permits.acquire_owned().await;
+                if let Err(e) =
+                    send_confirmation_email(&*email_client, new_subscriber, &base_url, &token).await
+                {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a confirmation email to an imported subscriber"
+                    );
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+fn parse_header(record: &[Vec<u8>]) -> Result<Columns, ImportError> {
+    let position = |column: &str| {
+        record.iter().position(|field| {
+            let field = String::from_utf8_lossy(field);
+            // Excel 导出的 UTF-8 文件以 BOM 开头
+            field
+                .trim_start_matches('\u{feff}')
+                .trim()
+                .eq_ignore_ascii_case(column)
+        })
+    };
+    match (position("email"), position("name")) {
+        (Some(email), Some(name)) => Ok(Columns { email, name }),
+        _ => Err(ImportError::ValidationError(
+            "The first row must be a header with `email` and `name` columns.".into(),
+        )),
+    }
+}
+
+/// `csv_core` 的增量解析器,输入可以在任意位置被切开
+struct CsvParser {
+    reader: csv_core::Reader,
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+}
+
+impl CsvParser {
+    fn new() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+        }
+    }
+
+    /// 返回这一块输入中完整的记录,不完整的部分留到下一块
+    fn parse(&mut self, input: &[u8]) -> Vec<Vec<Vec<u8>>> {
+        let mut records = Vec::new();
+        // 空的输入对 `csv_core` 表示结束
+        if !input.is_empty() {
+            self.read(input, &mut records);
+        }
+        records
+    }
+
+    fn finish(&mut self) -> Vec<Vec<Vec<u8>>> {
+        let mut records = Vec::new();
+        self.read(&[], &mut records);
+        records
+    }
+
+    fn read(&mut self, mut input: &[u8], records: &mut Vec<Vec<Vec<u8>>>) {
+        let at_end = input.is_empty();
+        loop {
+            let (result, n_in, n_out, n_ends) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[n_in..];
+            self.output_len += n_out;
+            self.ends_len += n_ends;
+            match result {
+                ReadRecordResult::InputEmpty => {
+                    if !at_end {
+                        return;
+                    }
+                }
+                ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    let mut start = 0;
+                    let record = self.ends[..self.ends_len]
+                        .iter()
+                        .map(|&end| {
+                            let field = self.output[start..end].to_vec();
+                            start = end;
+                            field
+                        })
+                        .collect();
+                    records.push(record);
+                    self.output_len = 0;
+                    self.ends_len = 0;
+                    if input.is_empty() && !at_end {
+                        return;
+                    }
+                }
+                ReadRecordResult::End => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvParser, ImportOptions, ImportReport, MAX_REPORTED_ROWS, RowOutcome, RowReport};
+
+    fn parse_in_chunks(input: &str, chunk_size: usize) -> Vec<Vec<String>> {
+        let mut parser = CsvParser::new();
+        let mut records = Vec::new();
+        for chunk in input.as_bytes().chunks(chunk_size) {
+            records.extend(parser.parse(chunk));
+        }
+        records.extend(parser.finish());
+        records
+            .into_iter()
+            .map(|record| {
+                record
+                    .into_iter()
+                    .map(|field| String::from_utf8(field).unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_split_across_chunks_are_reassembled() {
+        let input = "email,name\r\nada@example.com,\"Lovelace, Ada\"\nalan@example.com,\"Alan \"\"The\"\" Turing\"";
+        let expected = vec![
+            vec!["email", "name"],
+            vec!["ada@example.com", "Lovelace, Ada"],
+            vec!["alan@example.com", "Alan \"The\" Turing"],
+        ];
+        for chunk_size in [1, 2, 7, 1024] {
+            assert_eq!(parse_in_chunks(input, chunk_size), expected, "{chunk_size}");
+        }
+    }
+
+    #[test]
+    fn long_fields_grow_the_buffers() {
+        let name = "x".repeat(5000);
+        let fields = (0..40).map(|i| i.to_string()).collect::<Vec<_>>().join(",");
+        let input = format!("{name},{fields}\n");
+
+        let records = parse_in_chunks(&input, 100);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0][0], name);
+        assert_eq!(records[0].len(), 41);
+    }
+
+    #[test]
+    fn pre_confirmed_imports_need_a_consent_source() {
+        let options = |pre_confirmed, consent_source: Option<&str>| ImportOptions {
+            pre_confirmed,
+            consent_source: consent_source.map(str::to_owned),
+        };
+        assert!(options(false, None).validate().is_ok());
+        assert!(options(true, Some("legacy-crm")).validate().is_ok());
+        assert!(options(true, None).validate().is_err());
+        assert!(options(true, Some("  ")).validate().is_err());
+        assert!(options(false, Some("legacy-crm")).validate().is_err());
+    }
+
+    #[test]
+    fn the_report_only_lists_a_bounded_number_of_problem_rows() {
+        let mut report = ImportReport::default();
+        for row in 0..(MAX_REPORTED_ROWS as u64 + 10) {
+            for outcome in [RowOutcome::Accepted, RowOutcome::Duplicate] {
+                report.push(RowReport {
+                    row,
+                    email: String::new(),
+                    outcome,
+                });
+            }
+        }
+        assert_eq!(report.accepted, MAX_REPORTED_ROWS as u64 + 10);
+        assert_eq!(report.duplicates, MAX_REPORTED_ROWS as u64 + 10);
+        assert_eq!(report.rows.len(), MAX_REPORTED_ROWS);
+        assert!(
+            report
+                .rows
+                .iter()
+                .all(|row| row.outcome == RowOutcome::Duplicate)
+        );
+        assert!(report.truncated);
+    }
+}
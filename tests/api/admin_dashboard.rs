@@ -0,0 +1,90 @@
+use wiremock::{
+    Mock,
+    matchers::{method, path},
+};
+
+use crate::helpers::{PostmarkBatchResponder, create_confirmed_subscriber, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn basic_auth_still_grants_access_to_the_admin_scope() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/dashboard", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_expired_session_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    sqlx::query!("UPDATE sessions SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_logged_in_user_can_publish_without_basic_auth() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.login().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", app.address))
+        .header("Idempotency-Key", uuid::Uuid::new_v4().to_string())
+        .json(&serde_json::json!({
+            "title": "newsLetter title",
+            "content": {
+                "text": "NewsLetter body as plain text",
+                "html": "<p>NewsLetter body as html</p>"
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn every_role_can_read_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    for role in ["owner", "editor", "viewer"] {
+        app.set_test_user_role(role).await;
+        app.login().await;
+
+        let response = app.get_admin_dashboard().await;
+
+        assert_eq!(response.status().as_u16(), 200);
+        let html = response.text().await.unwrap();
+        assert!(html.contains(&format!("Your role: {role}")));
+    }
+}
@@ -0,0 +1,242 @@
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, create_unconfirmed_subscriber, spawn_app};
+
+struct Subscriber {
+    id: Uuid,
+    email: String,
+}
+
+/// 通过订阅接口创建,确认令牌也会写入数据库
+async fn pending_subscriber(app: &TestApp) -> Subscriber {
+    let before: Vec<Uuid> = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    create_unconfirmed_subscriber(app).await;
+    let row = sqlx::query!(
+        "SELECT id, email FROM subscriptions WHERE NOT (id = ANY($1))",
+        &before
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    Subscriber {
+        id: row.id,
+        email: row.email,
+    }
+}
+
+async fn status_of(app: &TestApp, subscriber_id: Uuid) -> Option<String> {
+    sqlx::query_scalar!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn token_count(app: &TestApp, subscriber_id: Uuid) -> i64 {
+    sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM subscription_tokens WHERE subscription_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn a_pending_subscriber_can_be_confirmed_manually() {
+    let app = spawn_app().await;
+    let subscriber = pending_subscriber(&app).await;
+    app.login().await;
+
+    let response = app
+        .post_admin_json(
+            &format!("subscribers/{}/confirm", subscriber.id),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(status_of(&app, subscriber.id).await.unwrap(), "confirmed");
+    assert_eq!(token_count(&app, subscriber.id).await, 0);
+    let audit =
+        sqlx::query!("SELECT action, target FROM audit_log WHERE action LIKE 'subscriber.%'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(audit.action, "subscriber.confirm");
+    assert_eq!(audit.target, Some(subscriber.id.to_string()));
+    let consent = sqlx::query!(
+        "SELECT kind, source FROM subscription_consents WHERE subscriber_id = $1 AND kind = 'confirm'",
+        subscriber.id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        consent.source,
+        Some(format!("admin:{}", app.test_user.user_id))
+    );
+}
+
+#[tokio::test]
+async fn only_pending_subscribers_can_be_confirmed_manually() {
+    let app = spawn_app().await;
+    let subscriber = pending_subscriber(&app).await;
+    app.login().await;
+    app.post_admin_json(
+        &format!("subscribers/{}/unsubscribe", subscriber.id),
+        &serde_json::json!({}),
+    )
+    .await;
+
+    let response = app
+        .post_admin_json(
+            &format!("subscribers/{}/confirm", subscriber.id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(
+        status_of(&app, subscriber.id).await.unwrap(),
+        "unsubscribed"
+    );
+
+    let response = app
+        .post_admin_json(
+            "subscribers/bulk",
+            &serde_json::json!({"action": "confirm", "ids": [subscriber.id]}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["affected"], serde_json::json!([]));
+    assert_eq!(body["skipped"], serde_json::json!([subscriber.id]));
+    assert_eq!(
+        status_of(&app, subscriber.id).await.unwrap(),
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_unsubscribed() {
+    let app = spawn_app().await;
+    let subscriber = pending_subscriber(&app).await;
+    app.login().await;
+
+    let response = app
+        .post_admin_json(
+            &format!("subscribers/{}/unsubscribe", subscriber.id),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(
+        status_of(&app, subscriber.id).await.unwrap(),
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_its_confirmation_tokens() {
+    let app = spawn_app().await;
+    let subscriber = pending_subscriber(&app).await;
+    assert_eq!(token_count(&app, subscriber.id).await, 1);
+    app.login().await;
+
+    let response = app
+        .delete_admin(&format!("subscribers/{}", subscriber.id))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(status_of(&app, subscriber.id).await, None);
+    assert_eq!(token_count(&app, subscriber.id).await, 0);
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_reported_with_a_404() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .delete_admin(&format!("subscribers/{}", Uuid::new_v4()))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn viewers_cannot_change_subscribers() {
+    let app = spawn_app().await;
+    let subscriber = pending_subscriber(&app).await;
+    app.login().await;
+    app.set_test_user_role("viewer").await;
+
+    let response = app
+        .delete_admin(&format!("subscribers/{}", subscriber.id))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(status_of(&app, subscriber.id).await.is_some());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_changed_in_bulk_by_id_or_email() {
+    let app = spawn_app().await;
+    let by_id = pending_subscriber(&app).await;
+    let by_email = pending_subscriber(&app).await;
+    let untouched = pending_subscriber(&app).await;
+    app.login().await;
+    let missing_id = Uuid::new_v4();
+
+    let response = app
+        .post_admin_json(
+            "subscribers/bulk",
+            &serde_json::json!({
+                "action": "delete",
+                "ids": [by_id.id, missing_id],
+                "emails": [&by_email.email, "nobody@example.com"],
+            }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let mut affected: Vec<Uuid> = serde_json::from_value(body["affected"].clone()).unwrap();
+    affected.sort();
+    let mut expected = vec![by_id.id, by_email.id];
+    expected.sort();
+    assert_eq!(affected, expected);
+    assert_eq!(
+        body["not_found"],
+        serde_json::json!([missing_id.to_string(), "nobody@example.com"])
+    );
+    assert!(status_of(&app, untouched.id).await.is_some());
+    let audited = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM audit_log WHERE action = 'subscriber.delete'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audited, 2);
+}
+
+#[tokio::test]
+async fn bulk_requests_must_name_at_least_one_subscriber() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for body in [
+        serde_json::json!({"action": "confirm"}),
+        serde_json::json!({"action": "confirm", "ids": [], "emails": []}),
+        serde_json::json!({"action": "archive", "ids": [Uuid::new_v4()]}),
+    ] {
+        let response = app.post_admin_json("subscribers/bulk", &body).await;
+        assert_eq!(response.status().as_u16(), 400, "{body}");
+    }
+}
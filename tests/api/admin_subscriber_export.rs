@@ -0,0 +1,150 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, spawn_app};
+
+async fn insert_subscriber(app: &TestApp, email: &str, name: &str, status: &str, days_ago: i64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        email,
+        name,
+        Utc::now() - Duration::days(days_ago),
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn seed_subscribers(app: &TestApp) {
+    insert_subscriber(app, "ada@example.com", "Lovelace, Ada", "confirmed", 3).await;
+    insert_subscriber(
+        app,
+        "alan@example.com",
+        "Alan \"Bombe\" Turing",
+        "unsubscribed",
+        2,
+    )
+    .await;
+    insert_subscriber(app, "grace@example.com", "Grace Hopper", "confirmed", 1).await;
+}
+
+async fn export(app: &TestApp, query: &str) -> (reqwest::header::HeaderMap, String) {
+    let response = app.get_subscriber_export(query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let headers = response.headers().clone();
+    (headers, response.text().await.unwrap())
+}
+
+#[tokio::test]
+async fn anonymous_users_and_viewers_cannot_export_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_subscriber_export("").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.login().await;
+    app.set_test_user_role("viewer").await;
+    let response = app.get_subscriber_export("").await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn csv_export_lists_every_subscriber_oldest_first() {
+    let app = spawn_app().await;
+    app.login().await;
+    seed_subscribers(&app).await;
+
+    let (headers, body) = export(&app, "").await;
+
+    assert_eq!(headers["content-type"], "text/csv; charset=utf-8");
+    assert!(
+        headers["content-disposition"]
+            .to_str()
+            .unwrap()
+            .contains("subscribers.csv")
+    );
+    let lines: Vec<&str> = body.split("\r\n").collect();
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert!(lines[1].contains(",ada@example.com,\"Lovelace, Ada\",confirmed,"));
+    assert!(lines[2].contains(",alan@example.com,\"Alan \"\"Bombe\"\" Turing\",unsubscribed,"));
+    assert!(lines[3].contains(",grace@example.com,Grace Hopper,confirmed,"));
+    assert_eq!(lines[4], "");
+    assert_eq!(lines.len(), 5);
+}
+
+#[tokio::test]
+async fn ndjson_export_can_be_filtered_by_status() {
+    let app = spawn_app().await;
+    app.login().await;
+    seed_subscribers(&app).await;
+
+    let (headers, body) = export(&app, "format=ndjson&status=confirmed").await;
+
+    assert_eq!(headers["content-type"], "application/x-ndjson");
+    let subscribers: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let emails: Vec<&str> = subscribers
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect();
+    assert_eq!(emails, vec!["ada@example.com", "grace@example.com"]);
+    assert_eq!(subscribers[0]["name"], "Lovelace, Ada");
+    assert_eq!(subscribers[0]["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn large_exports_are_streamed_completely() {
+    let app = spawn_app().await;
+    app.login().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'subscriber' || n || '@example.com', 'Subscriber ' || n,
+            now() - n * interval '1 second', 'confirmed'
+        FROM generate_series(1, 2000) AS n
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (_, body) = export(&app, "format=ndjson").await;
+
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 2000);
+    assert!(lines[0].contains("subscriber2000@example.com"));
+    assert!(lines[1999].contains("subscriber1@example.com"));
+}
+
+#[tokio::test]
+async fn invalid_parameters_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for query in ["format=xml", "status=lapsed"] {
+        let response = app.get_subscriber_export(query).await;
+        assert_eq!(response.status().as_u16(), 400, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn exports_are_recorded_in_the_audit_log() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    export(&app, "format=ndjson&status=unsubscribed").await;
+
+    let target =
+        sqlx::query_scalar!("SELECT target FROM audit_log WHERE action = 'subscriber.export'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(target.as_deref(), Some("ndjson:unsubscribed"));
+}
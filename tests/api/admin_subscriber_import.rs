@@ -0,0 +1,201 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, spawn_app};
+
+const PRE_CONFIRMED: &str = "pre_confirmed=true&consent_source=legacy-crm";
+
+async fn import(app: &TestApp, query: &str, csv: &str) -> serde_json::Value {
+    let response = app.post_subscriber_import(query, csv).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn pre_confirmed_rows_are_imported_without_sending_emails() {
+    let app = spawn_app().await;
+    app.login().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let report = import(
+        &app,
+        PRE_CONFIRMED,
+        "name,email\nAda Lovelace,ada@example.com\n\"Turing, Alan\",alan@example.com\n",
+    )
+    .await;
+
+    assert_eq!(report["accepted"], 2);
+    let rows = sqlx::query!(
+        "SELECT email, name, status, consent_source FROM subscriptions ORDER BY email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].name, "Turing, Alan");
+    for row in rows {
+        assert_eq!(row.status, "confirmed");
+        assert_eq!(row.consent_source.as_deref(), Some("legacy-crm"));
+    }
+}
+
+#[tokio::test]
+async fn the_report_lists_duplicates_and_rejected_rows() {
+    let app = spawn_app().await;
+    app.login().await;
+    import(&app, PRE_CONFIRMED, "email,name\nada@example.com,Ada\n").await;
+
+    let report = import(
+        &app,
+        PRE_CONFIRMED,
+        "email,name\n\
+        ada@example.com,Ada again\n\
+        grace@example.com,Grace\n\
+        grace@example.com,Grace twice\n\
+        not-an-email,Nobody\n\
+        edsger@example.com,\n\
+        barbara@example.com\n",
+    )
+    .await;
+
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(report["duplicates"], 2);
+    assert_eq!(report["rejected"], 3);
+    let rows = report["rows"].as_array().unwrap();
+    let outcomes: Vec<(u64, &str)> = rows
+        .iter()
+        .map(|row| {
+            (
+                row["row"].as_u64().unwrap(),
+                row["outcome"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            (2, "duplicate"),
+            (4, "duplicate"),
+            (5, "rejected"),
+            (6, "rejected"),
+            (7, "rejected"),
+        ]
+    );
+    assert_eq!(rows[2]["email"], "not-an-email");
+    assert!(
+        rows[2]["error"]
+            .as_str()
+            .unwrap()
+            .contains("not a valid subscriber email")
+    );
+    assert!(rows[4]["error"].as_str().unwrap().contains("name column"));
+    assert_eq!(report["truncated"], false);
+}
+
+#[tokio::test]
+async fn rows_that_are_not_pre_confirmed_get_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.login().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let report = import(
+        &app,
+        "",
+        "email,name\nada@example.com,Ada\nalan@example.com,Alan\n",
+    )
+    .await;
+
+    assert_eq!(report["accepted"], 2);
+    let pending = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM subscriptions WHERE status = 'pending_confirmation'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(pending, 2);
+    // 确认邮件在后台发送
+    app.wait_for_email(2).await;
+}
+
+#[tokio::test]
+async fn a_failing_confirmation_email_does_not_fail_the_row() {
+    let app = spawn_app().await;
+    app.login().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let report = import(&app, "", "email,name\nada@example.com,Ada\n").await;
+
+    // 行已经提交,订阅者重新订阅时会收到新的确认邮件
+    assert_eq!(report["accepted"], 1);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "pending_confirmation");
+    app.wait_for_email(1).await;
+}
+
+#[tokio::test]
+async fn invalid_imports_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for (query, csv) in [
+        (PRE_CONFIRMED, "mail,full_name\nada@example.com,Ada\n"),
+        (PRE_CONFIRMED, ""),
+        ("pre_confirmed=true", "email,name\nada@example.com,Ada\n"),
+        (
+            "consent_source=legacy-crm",
+            "email,name\nada@example.com,Ada\n",
+        ),
+    ] {
+        let response = app.post_subscriber_import(query, csv).await;
+        assert_eq!(response.status().as_u16(), 400, "{query} {csv}");
+    }
+}
+
+#[tokio::test]
+async fn viewers_cannot_import_subscribers() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.set_test_user_role("viewer").await;
+
+    let response = app
+        .post_subscriber_import(PRE_CONFIRMED, "email,name\nada@example.com,Ada\n")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn imports_are_recorded_in_the_audit_log() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    import(&app, PRE_CONFIRMED, "email,name\nada@example.com,Ada\n").await;
+
+    let target =
+        sqlx::query_scalar!("SELECT target FROM audit_log WHERE action = 'subscriber.import'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        target.as_deref(),
+        Some("1 accepted, 0 duplicates, 0 rejected")
+    );
+}
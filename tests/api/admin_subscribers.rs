@@ -0,0 +1,255 @@
+use chrono::{Duration, SecondsFormat, Utc};
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, spawn_app};
+
+/// 直接写入订阅者,`days_ago` 决定 `subscribed_at`
+async fn insert_subscriber(app: &TestApp, email: &str, name: &str, status: &str, days_ago: i64) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        email,
+        name,
+        Utc::now() - Duration::days(days_ago),
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn seed_subscribers(app: &TestApp) {
+    insert_subscriber(app, "ada@example.com", "Ada Lovelace", "confirmed", 5).await;
+    insert_subscriber(app, "grace@example.com", "Grace Hopper", "confirmed", 4).await;
+    insert_subscriber(app, "alan@example.com", "Alan Turing", "unsubscribed", 3).await;
+    insert_subscriber(
+        app,
+        "edsger@example.com",
+        "Edsger Dijkstra",
+        "pending_confirmation",
+        2,
+    )
+    .await;
+    insert_subscriber(app, "barbara@example.org", "Barbara Liskov", "confirmed", 1).await;
+}
+
+async fn page(app: &TestApp, query: &str) -> serde_json::Value {
+    let response = app.get_admin_subscribers(query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn anonymous_users_cannot_list_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_subscribers("").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn viewers_cannot_list_subscribers() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.set_test_user_role("viewer").await;
+
+    let response = app.get_admin_subscribers("").await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn pages_cover_every_subscriber_once_newest_first() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+    app.login().await;
+
+    let mut seen = Vec::new();
+    let mut query = "limit=2".to_string();
+    loop {
+        let page = page(&app, &query).await;
+        seen.extend(emails(&page).into_iter().map(str::to_owned));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("limit=2&cursor={cursor}"),
+            None => break,
+        }
+    }
+
+    assert_eq!(
+        seen,
+        vec![
+            "barbara@example.org",
+            "edsger@example.com",
+            "alan@example.com",
+            "grace@example.com",
+            "ada@example.com",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_sorted_by_email() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+    app.login().await;
+
+    let first = page(&app, "sort=email&limit=3").await;
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let second = page(&app, &format!("sort=email&limit=3&cursor={cursor}")).await;
+
+    assert_eq!(
+        emails(&first),
+        vec!["ada@example.com", "alan@example.com", "barbara@example.org"]
+    );
+    assert_eq!(
+        emails(&second),
+        vec!["edsger@example.com", "grace@example.com"]
+    );
+    // 游标和排序方式绑定
+    let response = app
+        .get_admin_subscribers(&format!("sort=-email&cursor={cursor}"))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+    app.login().await;
+
+    let page = page(&app, "status=confirmed&sort=email").await;
+
+    assert_eq!(
+        emails(&page),
+        vec![
+            "ada@example.com",
+            "barbara@example.org",
+            "grace@example.com"
+        ]
+    );
+    assert!(
+        page["subscribers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|s| s["status"] == "confirmed")
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_subscription_date() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+    app.login().await;
+    let at = |days_ago: i64| {
+        // 使用 `Z` 结尾,免得 `+` 在查询字符串里被当成空格
+        (Utc::now() - Duration::days(days_ago) - Duration::hours(12))
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    };
+
+    let page = page(
+        &app,
+        &format!("subscribed_after={}&subscribed_before={}", at(4), at(1)),
+    )
+    .await;
+
+    assert_eq!(
+        emails(&page),
+        vec![
+            "edsger@example.com",
+            "alan@example.com",
+            "grace@example.com"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn search_matches_email_or_name_substrings() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+    app.login().await;
+
+    assert_eq!(
+        emails(&page(&app, "q=HOPPER").await),
+        vec!["grace@example.com"]
+    );
+    assert_eq!(
+        emails(&page(&app, "q=example.org").await),
+        vec!["barbara@example.org"]
+    );
+    // 通配符按字面匹配
+    assert!(emails(&page(&app, "q=%25").await).is_empty());
+}
+
+#[tokio::test]
+async fn invalid_parameters_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for query in [
+        "sort=id",
+        "status=active",
+        "limit=0",
+        "limit=1000",
+        "cursor=garbage",
+        "subscribed_after=yesterday",
+    ] {
+        let response = app.get_admin_subscribers(query).await;
+        assert_eq!(response.status().as_u16(), 400, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn api_tokens_need_the_subscribers_read_scope() {
+    let app = spawn_app().await;
+    seed_subscribers(&app).await;
+    app.login().await;
+    let mut tokens = Vec::new();
+    for scope in ["subscribers:read", "newsletters:publish"] {
+        let created: serde_json::Value = app
+            .post_api_tokens(&serde_json::json!({"name": scope, "scopes": [scope]}))
+            .await
+            .json()
+            .await
+            .unwrap();
+        tokens.push(created["token"].as_str().unwrap().to_owned());
+    }
+
+    let list_with = |token: String| {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers", app.address))
+            .bearer_auth(token)
+            .send()
+    };
+
+    assert_eq!(
+        list_with(tokens[0].clone())
+            .await
+            .unwrap()
+            .status()
+            .as_u16(),
+        200
+    );
+    assert_eq!(
+        list_with(tokens[1].clone())
+            .await
+            .unwrap()
+            .status()
+            .as_u16(),
+        403
+    );
+}
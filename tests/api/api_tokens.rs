@@ -0,0 +1,260 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{any, method, path},
+};
+
+use crate::helpers::{PostmarkBatchResponder, TestApp, create_confirmed_subscriber, spawn_app};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Release notes",
+        "content": {
+            "text": "Release notes as plain text",
+            "html": "<p>Release notes as html</p>"
+        }
+    })
+}
+
+/// 登录后创建一个令牌,返回响应中的 JSON
+async fn create_token(app: &TestApp, body: serde_json::Value) -> serde_json::Value {
+    let response = app.post_api_tokens(&body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    response.json().await.unwrap()
+}
+
+async fn publish_with_bearer(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .bearer_auth(token)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_scoped_token_can_publish_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.login().await;
+    let created = create_token(
+        &app,
+        serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}),
+    )
+    .await;
+    let token = created["token"].as_str().unwrap();
+
+    let response = publish_with_bearer(&app, token).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let last_used_at = sqlx::query_scalar!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn api_tokens_are_stored_hashed_and_never_listed() {
+    let app = spawn_app().await;
+    app.login().await;
+    let created = create_token(
+        &app,
+        serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"], "expires_in_days": 30}),
+    )
+    .await;
+    let token = created["token"].as_str().unwrap();
+    assert!(token.starts_with("z2p_"));
+    assert!(created["expires_at"].is_string());
+
+    let stored = sqlx::query_scalar!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, token);
+
+    let listed: serde_json::Value = app.get_api_tokens().await.json().await.unwrap();
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["name"], "ci");
+    assert_eq!(
+        listed[0]["scopes"],
+        serde_json::json!(["newsletters:publish"])
+    );
+    assert!(listed[0].get("token").is_none());
+}
+
+#[tokio::test]
+async fn a_token_cannot_be_used_outside_its_scopes() {
+    let app = spawn_app().await;
+    app.login().await;
+    let created = create_token(
+        &app,
+        serde_json::json!({"name": "reporting", "scopes": ["subscribers:read"]}),
+    )
+    .await;
+
+    let response = publish_with_bearer(&app, created["token"].as_str().unwrap()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn a_token_cannot_exceed_the_role_of_its_owner() {
+    let app = spawn_app().await;
+    app.login().await;
+    let created = create_token(
+        &app,
+        serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}),
+    )
+    .await;
+    app.set_test_user_role("editor").await;
+
+    let response = publish_with_bearer(&app, created["token"].as_str().unwrap()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn revoked_and_expired_tokens_are_rejected() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.login().await;
+    let revoked = create_token(
+        &app,
+        serde_json::json!({"name": "revoked", "scopes": ["newsletters:publish"]}),
+    )
+    .await;
+    let expired = create_token(
+        &app,
+        serde_json::json!({"name": "expired", "scopes": ["newsletters:publish"]}),
+    )
+    .await;
+    let response = app
+        .delete_api_token(revoked["api_token_id"].as_str().unwrap())
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+    sqlx::query!(
+        "UPDATE api_tokens SET expires_at = now() - interval '1 second' WHERE name = 'expired'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    for token in [&revoked, &expired] {
+        let response = publish_with_bearer(&app, token["token"].as_str().unwrap()).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = publish_with_bearer(&app, "z2p_not-a-real-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn revoking_an_unknown_token_returns_a_404() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.delete_api_token(&Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn api_tokens_cannot_manage_api_tokens() {
+    let app = spawn_app().await;
+    app.login().await;
+    let created = create_token(
+        &app,
+        serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}),
+    )
+    .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/api_tokens", app.address))
+        .bearer_auth(created["token"].as_str().unwrap())
+        .json(&serde_json::json!({"name": "another", "scopes": ["newsletters:publish"]}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn invalid_token_requests_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+    create_token(
+        &app,
+        serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}),
+    )
+    .await;
+    let test_cases = [
+        (
+            serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}),
+            409,
+            "a duplicate name",
+        ),
+        (
+            serde_json::json!({"name": " ", "scopes": ["newsletters:publish"]}),
+            400,
+            "an empty name",
+        ),
+        (
+            serde_json::json!({"name": "other", "scopes": []}),
+            400,
+            "no scopes",
+        ),
+        (
+            serde_json::json!({"name": "other", "scopes": ["everything"]}),
+            400,
+            "an unknown scope",
+        ),
+        (
+            serde_json::json!({"name": "other", "scopes": ["subscribers:read"], "expires_in_days": 0}),
+            400,
+            "a zero expiry",
+        ),
+        (
+            serde_json::json!({"name": "other", "scopes": ["subscribers:read"], "expires_in_days": 3651}),
+            400,
+            "an expiry longer than ten years",
+        ),
+        (
+            serde_json::json!({"name": "other", "scopes": ["subscribers:read"], "expires_in_days": u32::MAX}),
+            400,
+            "an expiry that would overflow the date",
+        ),
+    ];
+
+    for (body, status, description) in test_cases {
+        let response = app.post_api_tokens(&body).await;
+        assert_eq!(
+            response.status().as_u16(),
+            status,
+            "The API did not fail with {status} for {description}."
+        );
+    }
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_api_tokens() {
+    let app = spawn_app().await;
+
+    let response = app.get_api_tokens().await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,187 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, create_confirmed_subscriber, spawn_app};
+
+async fn audit_log_page(app: &TestApp, query: &str) -> serde_json::Value {
+    let response = app.get_audit_log(query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+fn actions(page: &serde_json::Value) -> Vec<&str> {
+    page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn publishing_a_newsletter_is_recorded() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "newsLetter title",
+            "content": {"text": "plain text", "html": "<p>html</p>"}
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let entry = sqlx::query!(
+        "SELECT user_id, action, target, request_id, ip FROM audit_log WHERE action = 'newsletter.publish'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    let issue_id = sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(entry.user_id, Some(app.test_user.user_id));
+    assert_eq!(entry.target, Some(issue_id.to_string()));
+    assert!(entry.request_id.is_some());
+    assert_eq!(entry.ip.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn a_rejected_publish_is_not_recorded() {
+    let app = spawn_app().await;
+    app.set_test_user_role("editor").await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "newsLetter title",
+            "content": {"text": "plain text", "html": "<p>html</p>"}
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let count = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM audit_log"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+async fn session_and_account_changes_are_recorded() {
+    let app = spawn_app().await;
+    app.login().await;
+    let response = app
+        .post_api_tokens(&serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}))
+        .await;
+    let api_token_id = response.json::<serde_json::Value>().await.unwrap()["api_token_id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    app.delete_api_token(&api_token_id).await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+    app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+
+    let page = audit_log_page(&app, "").await;
+    assert_eq!(
+        actions(&page),
+        vec![
+            "user.change_password",
+            "api_token.revoke",
+            "api_token.create",
+            "session.login"
+        ]
+    );
+    assert_eq!(page["entries"][1]["target"], api_token_id.as_str());
+
+    app.post_logout().await;
+    let action =
+        sqlx::query_scalar!("SELECT action FROM audit_log ORDER BY audit_log_id DESC LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(action, "session.logout");
+}
+
+#[tokio::test]
+async fn the_audit_log_is_paginated_newest_first() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.login().await;
+    }
+
+    let first = audit_log_page(&app, "limit=2").await;
+    assert_eq!(first["entries"].as_array().unwrap().len(), 2);
+    let next_before = first["next_before"].as_i64().unwrap();
+    assert_eq!(first["entries"][1]["audit_log_id"], next_before);
+
+    let second = audit_log_page(&app, &format!("limit=2&before={next_before}")).await;
+    assert_eq!(second["entries"].as_array().unwrap().len(), 1);
+    assert!(second["next_before"].is_null());
+    assert!(
+        second["entries"][0]["audit_log_id"].as_i64().unwrap() < next_before,
+        "The second page should only contain older entries"
+    );
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered_by_action() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.post_logout().await;
+    app.login().await;
+
+    let page = audit_log_page(&app, "action=session.logout").await;
+
+    assert_eq!(actions(&page), vec!["session.logout"]);
+}
+
+#[tokio::test]
+async fn an_invalid_limit_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for limit in ["0", "1000"] {
+        let response = app.get_audit_log(&format!("limit={limit}")).await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn only_owners_can_read_the_audit_log() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.set_test_user_role("editor").await;
+
+    let response = app.get_audit_log("").await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn entries_cannot_be_changed_or_deleted() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let update = sqlx::query!("UPDATE audit_log SET action = 'tampered'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
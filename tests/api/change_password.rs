@@ -0,0 +1,231 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+fn new_password_form(current_password: &str, new_password: &str) -> serde_json::Value {
+    serde_json::json!({
+        "current_password": current_password,
+        "new_password": new_password,
+        "new_password_check": new_password,
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_password() {
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": Uuid::new_v4().to_string(),
+            "new_password_check": Uuid::new_v4().to_string(),
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("You entered two different new passwords")
+    );
+}
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    let app = spawn_app().await;
+    app.login().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("The current password is incorrect.")
+    );
+}
+
+#[tokio::test]
+async fn a_new_password_that_violates_the_policy_is_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    for new_password in ["short", &"a".repeat(129), &" ".repeat(16)] {
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": new_password,
+                "new_password_check": new_password,
+            }))
+            .await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject the new password {:?}",
+            new_password
+        );
+    }
+}
+
+#[tokio::test]
+async fn changing_password_works() {
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+    app.login().await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.post_logout().await;
+    let response = app.login().await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let stored = sqlx::query_scalar!("SELECT password_hash FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(stored.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+}
+
+#[tokio::test]
+async fn changing_password_logs_out_the_other_sessions() {
+    let app = spawn_app().await;
+    app.login().await;
+    // 另一台设备上的会话
+    let other_device = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    other_device
+        .post(format!("{}/login", app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    let dashboard = format!("{}/admin/dashboard", app.address);
+    let response = other_device.get(&dashboard).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&new_password_form(&app.test_user.password, &new_password))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = other_device.get(&dashboard).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn api_tokens_and_basic_auth_cannot_change_the_password() {
+    let app = spawn_app().await;
+    app.login().await;
+    let created: serde_json::Value = app
+        .post_api_tokens(&serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let new_password = Uuid::new_v4().to_string();
+    let form = new_password_form(&app.test_user.password, &new_password);
+    let url = format!("{}/admin/password", app.address);
+
+    let response = reqwest::Client::new()
+        .post(&url)
+        .bearer_auth(created["token"].as_str().unwrap())
+        .form(&form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    let response = reqwest::Client::new()
+        .post(&url)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .form(&form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    app.post_logout().await;
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn guessing_the_current_password_is_throttled_like_a_login() {
+    let app = spawn_app().await;
+    app.login().await;
+    let lockout_after = app.login_throttling.username_lockout_after_failures as i32;
+    sqlx::query!(
+        r#"
+        INSERT INTO login_failures (kind, key, failures, last_failed_at)
+        VALUES ('username', $1, $2, now())
+        "#,
+        app.test_user.username,
+        lockout_after - 1
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let new_password = Uuid::new_v4().to_string();
+
+    let wrong_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&new_password_form(&wrong_password, &new_password))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    // 即使猜对了,锁定期间也不会校验
+    let response = app
+        .post_change_password(&new_password_form(&app.test_user.password, &new_password))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
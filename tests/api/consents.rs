@@ -0,0 +1,222 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, spawn_app};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// 带着浏览器的 User-Agent 提交订阅表单
+async fn subscribe(app: &TestApp, source: Option<&str>) -> reqwest::Response {
+    let mut form = vec![("name", "le guin"), ("email", EMAIL)];
+    if let Some(source) = source {
+        form.push(("source", source));
+    }
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("User-Agent", "Mozilla/5.0 (consent test)")
+        .form(&form)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn consents(app: &TestApp, query: &str) -> serde_json::Value {
+    let response = app.get_admin_consents(query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn subscribing_and_confirming_each_record_consent() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+
+    subscribe(&app, Some("homepage_footer"))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email = &app.wait_for_email(1).await;
+    let link = app.get_confirmation_links(email);
+    reqwest::Client::new()
+        .get(link.html)
+        .header("User-Agent", "Mail client")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let id = subscriber_id(&app).await;
+    app.login().await;
+    let page = consents(&app, &format!("subscriber_id={id}")).await;
+    let records = page["consents"].as_array().unwrap();
+    assert_eq!(records.len(), 2);
+    // 新的在前
+    let (confirm, subscribe) = (&records[0], &records[1]);
+    assert_eq!(subscribe["kind"], "subscribe");
+    assert_eq!(subscribe["user_agent"], "Mozilla/5.0 (consent test)");
+    assert_eq!(confirm["kind"], "confirm");
+    assert_eq!(confirm["user_agent"], "Mail client");
+    for record in records {
+        assert_eq!(record["subscriber_id"], id.to_string());
+        assert_eq!(record["source"], "homepage_footer");
+        assert_eq!(record["privacy_policy_version"], "2025-08-01");
+        assert_eq!(record["ip"], "127.0.0.1");
+        assert!(record["recorded_at"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn the_source_defaults_to_the_subscribe_form() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+
+    subscribe(&app, None).await.error_for_status().unwrap();
+
+    app.login().await;
+    let page = consents(&app, "kind=subscribe").await;
+    assert_eq!(page["consents"][0]["source"], "subscribe_form");
+    let source = sqlx::query_scalar!("SELECT consent_source FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(source.as_deref(), Some("subscribe_form"));
+}
+
+#[tokio::test]
+async fn an_invalid_source_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+
+    for source in ["   ", &"a".repeat(101)] {
+        let response = subscribe(&app, Some(source)).await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn pre_confirmed_imports_record_their_consent_source() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .post_subscriber_import(
+            "pre_confirmed=true&consent_source=legacy-crm",
+            &format!("email,name\n{EMAIL},Ursula\n"),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let page = consents(&app, "kind=import").await;
+    let record = &page["consents"][0];
+    assert_eq!(record["source"], "legacy-crm");
+    assert!(record["ip"].is_null());
+    assert!(record["privacy_policy_version"].is_null());
+}
+
+#[tokio::test]
+async fn consents_can_be_filtered_and_paginated() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    app.login().await;
+    for (i, source) in ["footer", "footer", "popup"].iter().enumerate() {
+        let email = format!("subscriber{i}@example.com");
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", app.address))
+            .form(&[("name", "someone"), ("email", &email), ("source", source)])
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let page = consents(&app, "source=footer&limit=1").await;
+    assert_eq!(page["consents"].as_array().unwrap().len(), 1);
+    let next_before = page["next_before"].as_i64().unwrap();
+    let page = consents(&app, &format!("source=footer&limit=1&before={next_before}")).await;
+    assert_eq!(page["consents"].as_array().unwrap().len(), 1);
+    assert!(page["next_before"].is_null());
+
+    let page = consents(&app, "privacy_policy_version=2025-08-01&kind=subscribe").await;
+    assert_eq!(page["consents"].as_array().unwrap().len(), 3);
+    let page = consents(&app, "privacy_policy_version=2024-01-01").await;
+    assert!(page["consents"].as_array().unwrap().is_empty());
+
+    for query in ["limit=0", "kind=opt_in"] {
+        let response = app.get_admin_consents(query).await;
+        assert_eq!(response.status().as_u16(), 400, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn only_users_who_can_read_subscribers_can_query_consents() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_consents("").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.login().await;
+    app.set_test_user_role("viewer").await;
+    let response = app.get_admin_consents("").await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn consents_outlive_an_admin_delete_but_not_an_erasure() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    app.login().await;
+    subscribe(&app, None).await.error_for_status().unwrap();
+    let deleted_id = subscriber_id(&app).await;
+
+    let response = app.delete_admin(&format!("subscribers/{deleted_id}")).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    // 记录还在,只是不再指向订阅者
+    let body = consents(&app, &format!("email={EMAIL}")).await;
+    let records = body["consents"].as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["subscriber_id"], serde_json::Value::Null);
+    assert_eq!(records[0]["kind"], "subscribe");
+
+    // 重新订阅后擦除,之前留下的记录也一起删除
+    subscribe(&app, None).await.error_for_status().unwrap();
+    let erased_id = subscriber_id(&app).await;
+    let response = app
+        .post_admin_json(
+            &format!("subscribers/{erased_id}/erase"),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let body = consents(&app, &format!("email={EMAIL}")).await;
+    assert_eq!(body["consents"], serde_json::json!([]));
+    let consents_erased = sqlx::query_scalar!(
+        "SELECT consents_erased FROM subscriber_erasures WHERE subscriber_id = $1",
+        erased_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(consents_erased, 2);
+}
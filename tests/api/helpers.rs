@@ -10,13 +10,25 @@ use argon2::password_hash::SaltString;
  * @LastEditTime: 2025-07-23 11:46:32
  * @FilePath: /zero2prod/tests/api/helpers.rs
  */
+use fake::{
+    Fake,
+    faker::{internet::en::SafeEmail, name::en::Name},
+};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
 use zero2prod::configuration::DatabaseSettings;
+use zero2prod::configuration::LoginThrottlingSettings;
+use zero2prod::configuration::PasswordHashingSettings;
 use zero2prod::configuration::get_configuration;
-use zero2prod::startup::{Application, get_connection_pool};
+use zero2prod::email_client::EmailProvider;
+use zero2prod::issue_delivery_worker::{ExecutionOutcome, try_execute_task};
+use zero2prod::startup::{Application, ApplicationBaseUrl, HmacSecret, get_connection_pool};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 pub struct TestApp {
@@ -27,6 +39,13 @@ pub struct TestApp {
     pub prot: u16,
     // pub database_name: String,
     pub test_user: TestUser,
+    pub email_client: Arc<dyn EmailProvider>,
+    pub base_url: ApplicationBaseUrl,
+    pub hmac_secret: HmacSecret,
+    pub password_hashing: PasswordHashingSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    // 保存 cookie、不跟随重定向,用来测试登录会话
+    pub api_client: reqwest::Client,
 }
 
 #[derive(Debug)]
@@ -47,7 +66,7 @@ impl TestUser {
     /**
      * 表操作-插入用户数据
      */
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
@@ -60,7 +79,7 @@ impl TestUser {
 
         sqlx::query!(
             r#"
-            INSERT INTO users (user_id,username,password_hash) VALUES ($1,$2,$3)
+            INSERT INTO users (user_id,username,password_hash,role) VALUES ($1,$2,$3,'owner')
         "#,
             self.user_id,
             self.username,
@@ -72,12 +91,80 @@ impl TestUser {
     }
 }
 
+/// 模拟 Postmark 的 `/email/batch`,按请求中的邮件逐个返回结果
+/// `failing` 中的收件人返回 300 错误
+#[derive(Default)]
+pub struct PostmarkBatchResponder {
+    pub failing: Vec<String>,
+}
+
+impl wiremock::Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> wiremock::ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results = messages
+            .iter()
+            .map(|message| {
+                let to = message["To"].as_str().unwrap();
+                if self.failing.iter().any(|f| f == to) {
+                    serde_json::json!({"ErrorCode": 300, "Message": "Invalid 'To' address", "To": to})
+                } else {
+                    serde_json::json!({"ErrorCode": 0, "Message": "OK", "To": to})
+                }
+            })
+            .collect::<Vec<_>>();
+        wiremock::ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
 pub struct ConfirmationLink {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
 }
 
 impl TestApp {
+    /// 把队列中的投递任务全部执行完
+    /// 应用自己的后台 worker 也可能正在处理任务,所以最后要等队列真正清空
+    /// 等待重试的任务不算在内
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+        loop {
+            let pending = sqlx::query_scalar!(
+                r#"SELECT count(*) as "count!" FROM issue_delivery_queue WHERE n_retries = 0"#
+            )
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap();
+            if pending == 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
+    /// 邮件在后台发送,轮询等待收到的第 `n` 封
+    pub async fn wait_for_email(&self, n: usize) -> wiremock::Request {
+        for _ in 0..50 {
+            let mut requests = self.email_server.received_requests().await.unwrap();
+            if requests.len() >= n {
+                return requests.swap_remove(n - 1);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("Expected {n} email(s)");
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
             .post(format!("{}/subscriptions", &self.address))
@@ -88,6 +175,15 @@ impl TestApp {
             .expect("failed to execute request.")
     }
 
+    pub async fn post_subscriber_data_request(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/data_request", &self.address))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLink {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         let get_link = |s: &str| {
@@ -102,15 +198,202 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLink { html, plain_text }
     }
 
+    pub async fn post_login<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    /// 以测试用户的身份登录
+    pub async fn login(&self) -> reqwest::Response {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password
+        }))
+        .await
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/logout", self.address))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", self.address))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn post_change_password<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/password", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn post_api_tokens<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/api_tokens", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn get_api_tokens(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/api_tokens", self.address))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn delete_api_token(&self, api_token_id: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/api_tokens/{}",
+                self.address, api_token_id
+            ))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn post_admin_json<Body: serde::Serialize>(
+        &self,
+        path: &str,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/{}", self.address, path))
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn post_password_reset_request(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password_reset", self.address))
+            .form(&[("username", username)])
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn post_password_reset<Body: serde::Serialize>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password_reset/confirm", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn get_subscriber_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                self.address, query
+            ))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn post_subscriber_import(&self, query: &str, csv: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/import?{}",
+                self.address, query
+            ))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn get_admin_consents(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/consents?{}", self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit_log?{}", self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    pub async fn delete_admin(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/{}", self.address, path))
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    /// 修改测试用户的角色,默认是 owner
+    pub async fn set_test_user_role(&self, role: &str) {
+        sqlx::query!(
+            "UPDATE users SET role = $1 WHERE user_id = $2",
+            role,
+            self.test_user.user_id
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to update the test user's role");
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        let idempotency_key = Uuid::new_v4().to_string();
+        self.post_newsletters_with_idempotency_key(body, &idempotency_key)
+            .await
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
         reqwest::Client::new()
             .post(format!("{}/newsletters", self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
             .json(&body)
             .send()
             .await
@@ -118,6 +401,43 @@ impl TestApp {
     }
 }
 
+/// 使用测试程序的公共api 创建一个未确定的订阅者
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLink {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+    let _moke_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        // mount_as_scoped 确保两个mock不会重叠
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let received = app.email_server.received_requests().await.unwrap().len();
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app.wait_for_email(received + 1).await;
+    app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
 // 使用 once_cell 确保 tracing 栈堆中只被初始化一次
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info";
@@ -135,7 +455,8 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 /// 启动一个新的应用程序,并运行在空的数据库之上
 pub async fn spawn_app() -> TestApp {
     Lazy::force(&TRACING);
-    let email_server = MockServer::start().await;
+    // 不用连接池里的 MockServer: 邮件在后台发送,上一个测试没发完的邮件可能落到下一个测试里
+    let email_server = MockServer::builder().start().await;
     let configuration = {
         let mut config = get_configuration().expect("failed to read configuration");
         config.database.database_name = Uuid::new_v4().to_string();
@@ -152,7 +473,7 @@ pub async fn spawn_app() -> TestApp {
 
     let address = format!("http://127.0.0.1:{}", application.port());
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stoppend());
+    drop(tokio::spawn(application.run_until_stoppend()));
 
     let test_app = TestApp {
         address,
@@ -160,6 +481,16 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         prot: application_port,
         test_user: TestUser::generate(),
+        email_client: configuration.email_client.client(),
+        base_url: ApplicationBaseUrl(configuration.application.base_url.clone()),
+        hmac_secret: HmacSecret(configuration.application.hmac_secret.clone()),
+        password_hashing: configuration.password_hashing,
+        login_throttling: configuration.login_throttling.clone(),
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
         // database_name: configuration.database.database_name,
     };
     test_app.test_user.store(&test_app.db_pool).await;
@@ -185,3 +516,8 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .expect("failed to run database migrations");
     connection_pool
 }
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
@@ -0,0 +1,342 @@
+use std::time::Duration;
+
+use secrecy::{ExposeSecret, SecretString};
+use zero2prod::authentication::{compute_password_hash, needs_rehash};
+
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn invalid_credentials_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "random-username",
+            "password": "random-password"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response.headers().get("Set-Cookie").is_none());
+}
+
+#[tokio::test]
+async fn a_wrong_password_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "not-the-password"
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_successful_login_redirects_to_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let response = app.login().await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let cookie = response
+        .headers()
+        .get("Set-Cookie")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(cookie.starts_with("session_id="));
+    assert!(cookie.contains("HttpOnly"));
+
+    let html = app.get_admin_dashboard().await.text().await.unwrap();
+    assert!(html.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn the_session_id_is_not_stored_in_plain_text() {
+    let app = spawn_app().await;
+
+    let response = app.login().await;
+
+    let session_id = response
+        .cookies()
+        .find(|c| c.name() == "session_id")
+        .unwrap()
+        .value()
+        .to_owned();
+    let stored = sqlx::query_scalar!("SELECT session_id_hash FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, session_id);
+}
+
+#[tokio::test]
+async fn logout_destroys_the_session() {
+    let app = spawn_app().await;
+    let session_id = app
+        .login()
+        .await
+        .cookies()
+        .find(|c| c.name() == "session_id")
+        .unwrap()
+        .value()
+        .to_owned();
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 401);
+
+    // 即使保留了旧的 cookie,会话也已经失效
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/dashboard", app.address))
+        .header("Cookie", format!("session_id={session_id}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+async fn stored_password_hash(app: &TestApp) -> SecretString {
+    sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .map(SecretString::from)
+    .unwrap()
+}
+
+#[tokio::test]
+async fn outdated_password_hashes_are_upgraded_after_a_successful_login() {
+    let app = spawn_app().await;
+    // 测试用户使用 m=1500 存储,和配置的参数不同
+    let outdated = stored_password_hash(&app).await;
+    assert!(needs_rehash(&outdated, app.password_hashing));
+
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+
+    // 升级在后台进行,轮询等待完成
+    let mut upgraded = stored_password_hash(&app).await;
+    for _ in 0..50 {
+        if upgraded.expose_secret() != outdated.expose_secret() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        upgraded = stored_password_hash(&app).await;
+    }
+    assert!(!needs_rehash(&upgraded, app.password_hashing));
+
+    // 原来的密码仍然可以登录
+    app.post_logout().await;
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_failed_login_does_not_upgrade_the_password_hash() {
+    let app = spawn_app().await;
+    let outdated = stored_password_hash(&app).await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "not-the-password"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let stored = stored_password_hash(&app).await;
+    assert_eq!(stored.expose_secret(), outdated.expose_secret());
+}
+
+#[tokio::test]
+async fn current_password_hashes_are_left_untouched() {
+    let app = spawn_app().await;
+    let current = compute_password_hash(
+        &SecretString::from(app.test_user.password.clone()),
+        app.password_hashing,
+    )
+    .unwrap();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        current.expose_secret(),
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let stored = stored_password_hash(&app).await;
+    assert_eq!(stored.expose_secret(), current.expose_secret());
+}
+
+/// 直接写入失败记录,免得测试里真的等待逐步增加的延迟
+async fn seed_login_failures(app: &TestApp, kind: &str, key: &str, failures: i32) {
+    sqlx::query!(
+        r#"
+        INSERT INTO login_failures (kind, key, failures, last_failed_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        kind,
+        key,
+        failures
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+fn retry_after(response: &reqwest::Response) -> u64 {
+    response
+        .headers()
+        .get("Retry-After")
+        .expect("Missing Retry-After header")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_username_out() {
+    let app = spawn_app().await;
+    let lockout_after = app.login_throttling.username_lockout_after_failures as i32;
+    seed_login_failures(&app, "username", &app.test_user.username, lockout_after - 1).await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "not-the-password"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // 锁定期间正确的密码也会被拒绝
+    let response = app.login().await;
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after = retry_after(&response);
+    assert!(retry_after > 0 && retry_after <= app.login_throttling.lockout_seconds);
+}
+
+#[tokio::test]
+async fn unknown_usernames_are_throttled_like_existing_ones() {
+    let app = spawn_app().await;
+    let lockout_after = app.login_throttling.username_lockout_after_failures as i32;
+    seed_login_failures(&app, "username", "nobody", lockout_after - 1).await;
+    let body = serde_json::json!({"username": "nobody", "password": "random-password"});
+
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 401);
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_locked_out_ip_cannot_log_in() {
+    let app = spawn_app().await;
+    let lockout_after = app.login_throttling.ip_lockout_after_failures as i32;
+    seed_login_failures(&app, "ip", "127.0.0.1", lockout_after - 1).await;
+    app.post_login(&serde_json::json!({
+        "username": "someone-else",
+        "password": "random-password"
+    }))
+    .await;
+
+    assert_eq!(app.login().await.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_spoofed_forwarded_header_does_not_escape_an_ip_lockout() {
+    let app = spawn_app().await;
+    let lockout_after = app.login_throttling.ip_lockout_after_failures as i32;
+    seed_login_failures(&app, "ip", "127.0.0.1", lockout_after - 1).await;
+    // 测试配置里没有可信代理,客户端自己带的头不能换一个地址
+    let post_login = |body: serde_json::Value| {
+        app.api_client
+            .post(format!("{}/login", app.address))
+            .header("X-Forwarded-For", "203.0.113.9")
+            .form(&body)
+            .send()
+    };
+    post_login(serde_json::json!({
+        "username": "someone-else",
+        "password": "random-password"
+    }))
+    .await
+    .unwrap();
+
+    let response = post_login(serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await
+    .unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn basic_auth_is_throttled_too() {
+    let app = spawn_app().await;
+    let lockout_after = app.login_throttling.username_lockout_after_failures as i32;
+    seed_login_failures(&app, "username", &app.test_user.username, lockout_after).await;
+    sqlx::query!("UPDATE login_failures SET locked_until = now() + interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "newsLetter title",
+            "content": {"text": "plain text", "html": "<p>html</p>"}
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(retry_after(&response) <= 60);
+}
+
+#[tokio::test]
+async fn a_successful_login_clears_the_username_failures() {
+    let app = spawn_app().await;
+    seed_login_failures(&app, "username", &app.test_user.username, 2).await;
+
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+
+    let remaining = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM login_failures WHERE kind = 'username'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn failures_outside_the_window_are_forgotten() {
+    let app = spawn_app().await;
+    let lockout_after = app.login_throttling.username_lockout_after_failures as i32;
+    seed_login_failures(&app, "username", &app.test_user.username, lockout_after - 1).await;
+    sqlx::query!("UPDATE login_failures SET last_failed_at = now() - interval '1 day'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "not-the-password"
+    }))
+    .await;
+
+    let failures =
+        sqlx::query_scalar!("SELECT failures FROM login_failures WHERE kind = 'username'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(failures, 1);
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+}
@@ -4,10 +4,25 @@
  * @LastEditTime: 2025-07-20 17:12:09
  * @FilePath: /zero2prod/tests/api/main.rs
  */
+mod admin_dashboard;
+mod admin_subscriber_actions;
+mod admin_subscriber_export;
+mod admin_subscriber_import;
+mod admin_subscribers;
+mod api_tokens;
+mod audit_log;
+mod change_password;
+mod consents;
 mod health_check;
 mod helpers;
+mod login;
+mod password_reset;
+mod subscriber_data;
 mod subscriptions;
 
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod two_factor;
+mod user_admin;
 
 mod newsletter;
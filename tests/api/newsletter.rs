@@ -3,7 +3,10 @@ use wiremock::{
     matchers::{any, method, path},
 };
 
-use crate::helpers::{ConfirmationLink, TestApp, spawn_app};
+use crate::helpers::{
+    PostmarkBatchResponder, TestUser, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    spawn_app,
+};
 
 /*
  * @Date: 2025-07-20 17:12:01
@@ -17,9 +20,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -33,7 +36,8 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200)
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -55,44 +59,196 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200)
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
-/// 使用测试程序的公共api 创建一个未确定的订阅者
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLink {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _moke_guard = Mock::given(path("/email"))
+#[tokio::test]
+async fn newsletters_publishing_returns_before_delivery() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
-        // mount_as_scoped 确保两个mock不会重叠
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title":"newsLetter title",
+            "content":{
+                "text":"NewsLetter body as plain text",
+                "html":"<p>NewsLetter body as html</p>"
+            }
+        }))
         .await;
+    assert_eq!(response.status().as_u16(), 202);
 
-    app.post_subscriptions(body.into())
+    // 发布请求只负责入队,每个确认的订阅者对应一条任务
+    let issues = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
         .await
-        .error_for_status()
         .unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].title, "newsLetter title");
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_workers_deliver_each_issue_only_once() {
+    let app = spawn_app().await;
+    for _ in 0..10 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1..=10)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title":"newsLetter title",
+            "content":{
+                "text":"NewsLetter body as plain text",
+                "html":"<p>NewsLetter body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
 
-    let email_request = app
+    // 多个 worker 同时消费同一个队列
+    tokio::join!(
+        app.dispatch_all_pending_emails(),
+        app.dispatch_all_pending_emails(),
+        app.dispatch_all_pending_emails(),
+        app.dispatch_all_pending_emails(),
+    );
+
+    let delivered: usize = app
         .email_server
         .received_requests()
         .await
         .unwrap()
-        .pop()
-        .unwrap();
-    app.get_confirmation_links(&email_request)
+        .iter()
+        .filter(|r| r.url.path() == "/email/batch")
+        .map(|r| {
+            serde_json::from_slice::<Vec<serde_json::Value>>(&r.body)
+                .unwrap()
+                .len()
+        })
+        .sum();
+    assert_eq!(delivered, 10);
+    let logged =
+        sqlx::query!("SELECT subscriber_email FROM issue_delivery_log WHERE status = 'delivered'")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(logged.len(), 10);
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+#[tokio::test]
+async fn delivery_failures_within_a_batch_are_recorded_per_recipient() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let failing = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
         .await
         .unwrap()
-        .error_for_status()
+        .email;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder {
+            failing: vec![failing.clone()],
+        })
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let log = sqlx::query!("SELECT subscriber_email, status, error_code FROM issue_delivery_log")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(log.len(), 2);
+    for entry in log {
+        if entry.subscriber_email == failing {
+            assert_eq!(entry.status, "failed");
+            assert_eq!(entry.error_code, Some(300));
+        } else {
+            assert_eq!(entry.status, "delivered");
+            assert_eq!(entry.error_code, None);
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_batch_that_fails_with_a_server_error_stays_queued_for_a_retry() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let failing = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 2);
+    for task in queued {
+        assert_eq!(task.n_retries, 1);
+        assert!(task.execute_after > chrono::Utc::now());
+    }
+    let n_logged = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM issue_delivery_log"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_logged, 0);
+
+    // 邮件服务恢复之后,到期的任务会被重新投递
+    drop(failing);
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
         .unwrap();
+    // 应用自己的 worker 也可能取走这些任务,等到投递记录出现为止
+    for _ in 0..50 {
+        app.dispatch_all_pending_emails().await;
+        let delivered = sqlx::query_scalar!(
+            r#"SELECT count(*) as "count!" FROM issue_delivery_log WHERE status = 'delivered'"#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        if delivered == 2 {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("The retried tasks were not delivered");
 }
+
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     let app = spawn_app().await;
@@ -200,3 +356,255 @@ async fn invalid_password_is_rejected() {
         response.headers()["WWW-Authenticate"]
     );
 }
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title":"NewsLetter title",
+        "content":{
+            "text":"Newsletter body as plain text",
+            "html":"<p>newsletter body as HTML</p>"
+        }
+    })
+}
+
+#[tokio::test]
+async fn requests_missing_idempotency_key_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // 重试,应该重放第一次的响应而不是再发一次
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_publishing_is_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response1 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 1);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_are_processed_again() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // 模拟 key 已经过期
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_scoped_per_user() {
+    let app = spawn_app().await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&other_user.username, Some(&other_user.password))
+        .header("Idempotency-Key", &idempotency_key)
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 202);
+
+    let issues = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.len(), 2);
+}
+
+#[tokio::test]
+async fn only_owners_can_publish_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_request_body = serde_json::json!({
+        "title":"newsLetter title",
+        "content":{
+            "text":"NewsLetter body as plain text",
+            "html":"<p>NewsLetter body as html</p>"
+        }
+    });
+
+    for role in ["editor", "viewer"] {
+        app.set_test_user_role(role).await;
+
+        let response = app.post_newsletters(newsletter_request_body.clone()).await;
+        assert_eq!(response.status().as_u16(), 403, "role: {role}");
+
+        // 浏览器会话走同样的检查
+        app.login().await;
+        let response = app
+            .api_client
+            .post(format!("{}/admin/newsletters", app.address))
+            .header("Idempotency-Key", uuid::Uuid::new_v4().to_string())
+            .json(&newsletter_request_body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 403, "role: {role}");
+    }
+
+    let n_issues = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn editors_can_save_drafts_but_not_publish_them() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_request_body = serde_json::json!({
+        "title":"newsLetter title",
+        "content":{
+            "text":"NewsLetter body as plain text",
+            "html":"<p>NewsLetter body as html</p>"
+        }
+    });
+    app.set_test_user_role("editor").await;
+    app.login().await;
+
+    let response = app
+        .post_admin_json("newsletters/drafts", &newsletter_request_body)
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let saved: serde_json::Value = response.json().await.unwrap();
+    let draft =
+        sqlx::query!("SELECT newsletter_draft_id, title, created_by FROM newsletter_drafts")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        saved["newsletter_draft_id"],
+        draft.newsletter_draft_id.to_string()
+    );
+    assert_eq!(draft.title, "newsLetter title");
+    assert_eq!(draft.created_by, Some(app.test_user.user_id));
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/newsletters", app.address))
+        .header("Idempotency-Key", uuid::Uuid::new_v4().to_string())
+        .json(&newsletter_request_body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    // 观察者连草稿也不能写
+    app.set_test_user_role("viewer").await;
+    let response = app
+        .post_admin_json("newsletters/drafts", &newsletter_request_body)
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let n_issues = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_issues, 0);
+}
@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+
+const NEW_PASSWORD: &str = "a-brand-new-password";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = 'owner@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// 申请重置,返回邮件里的链接中的令牌
+async fn request_reset_token(app: &TestApp, n: usize) -> String {
+    let response = app
+        .post_password_reset_request(&app.test_user.username)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let email = app.wait_for_email(n).await;
+    let link = app.get_confirmation_links(&email);
+    assert_eq!(link.html, link.plain_text);
+    assert_eq!(link.html.path(), "/password_reset/confirm");
+    link.html
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+fn reset_body(token: &str) -> serde_json::Value {
+    serde_json::json!({
+        "token": token,
+        "new_password": NEW_PASSWORD,
+        "new_password_check": NEW_PASSWORD,
+    })
+}
+
+async fn login_with(app: &TestApp, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": password
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn the_reset_link_sets_a_new_password() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mock_email_server(&app).await;
+    let token = request_reset_token(&app, 1).await;
+
+    let form = reqwest::get(format!(
+        "{}/password_reset/confirm?token={token}",
+        app.address
+    ))
+    .await
+    .unwrap();
+    assert_eq!(form.status().as_u16(), 200);
+    let response = app.post_password_reset(&reset_body(&token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(
+        login_with(&app, &app.test_user.password)
+            .await
+            .status()
+            .as_u16(),
+        401
+    );
+    assert_is_redirect_to(&login_with(&app, NEW_PASSWORD).await, "/admin/dashboard");
+    let action =
+        sqlx::query_scalar!("SELECT action FROM audit_log WHERE user_id IS NOT NULL LIMIT 1")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(action, "user.reset_password");
+}
+
+#[tokio::test]
+async fn unknown_users_and_users_without_an_email_get_the_same_response() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+
+    let known = app
+        .post_password_reset_request(&app.test_user.username)
+        .await;
+    let known_status = known.status();
+    let known_body = known.text().await.unwrap();
+    let unknown = app.post_password_reset_request("nobody").await;
+
+    assert_eq!(known_status, unknown.status());
+    assert_eq!(known_body, unknown.text().await.unwrap());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(
+        app.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mock_email_server(&app).await;
+    let token = request_reset_token(&app, 1).await;
+    assert_eq!(
+        app.post_password_reset(&reset_body(&token))
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+
+    let response = app.post_password_reset(&reset_body(&token)).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mock_email_server(&app).await;
+    let token = request_reset_token(&app, 1).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_password_reset(&reset_body(&token)).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_is_redirect_to(
+        &login_with(&app, &app.test_user.password).await,
+        "/admin/dashboard",
+    );
+}
+
+#[tokio::test]
+async fn a_new_request_invalidates_the_previous_link() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mock_email_server(&app).await;
+    let first = request_reset_token(&app, 1).await;
+    let second = request_reset_token(&app, 2).await;
+
+    assert_eq!(
+        app.post_password_reset(&reset_body(&first))
+            .await
+            .status()
+            .as_u16(),
+        400
+    );
+    assert_eq!(
+        app.post_password_reset(&reset_body(&second))
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_existing_sessions() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mock_email_server(&app).await;
+    app.login().await;
+    let token = request_reset_token(&app, 1).await;
+
+    app.post_password_reset(&reset_body(&token)).await;
+
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn mismatched_passwords_do_not_use_up_the_link() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    mock_email_server(&app).await;
+    let token = request_reset_token(&app, 1).await;
+
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "token": &token,
+            "new_password": NEW_PASSWORD,
+            "new_password_check": "something-else-entirely",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.post_password_reset(&reset_body(&token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_reset_form_rejects_an_unknown_token() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/password_reset/confirm?token=not-a-real-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
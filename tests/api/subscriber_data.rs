@@ -0,0 +1,336 @@
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+use zero2prod::domain::SubscriberDataToken;
+
+use crate::helpers::{TestApp, spawn_app};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+/// 通过公开的接口订阅,再加上一期已投递和一期排队中的 newsletter
+async fn seed_subscriber(app: &TestApp) -> Uuid {
+    app.post_subscriptions(format!(
+        "name=le%20guin&email={}",
+        EMAIL.replace('@', "%40")
+    ))
+    .await
+    .error_for_status()
+    .unwrap();
+    // 确认邮件在后台发送,等它到了再申请数据,邮件的顺序才是确定的
+    app.wait_for_email(1).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    for (issue_id, title) in [(Uuid::new_v4(), "Delivered"), (Uuid::new_v4(), "Queued")] {
+        sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issues
+                (newsletter_issue_id, title, text_content, html_content, published_at)
+            VALUES ($1, $2, 'text', '<p>html</p>', now())
+            "#,
+            issue_id,
+            title
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        if title == "Delivered" {
+            sqlx::query!(
+                r#"
+                INSERT INTO issue_delivery_log
+                    (newsletter_issue_id, subscriber_email, status, error_code, error_message, attempted_at)
+                VALUES ($1, $2, 'failed', 300, $3, now())
+                "#,
+                issue_id,
+                EMAIL,
+                format!("Invalid 'To' address: {EMAIL}")
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+        } else {
+            sqlx::query!(
+                "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) VALUES ($1, $2)",
+                issue_id,
+                EMAIL
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+        }
+    }
+    subscriber_id
+}
+
+/// 申请数据,返回邮件里的下载链接和擦除链接
+async fn request_links(app: &TestApp, n: usize) -> (reqwest::Url, reqwest::Url) {
+    let response = app.post_subscriber_data_request(EMAIL).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let email = app.wait_for_email(n).await;
+    let body: serde_json::Value = serde_json::from_slice(&email.body).unwrap();
+    assert_eq!(body["To"], EMAIL);
+    let links: Vec<reqwest::Url> = linkify::LinkFinder::new()
+        .links(body["TextBody"].as_str().unwrap())
+        .map(|link| {
+            let mut url = reqwest::Url::parse(link.as_str()).unwrap();
+            url.set_port(Some(app.prot)).unwrap();
+            url
+        })
+        .collect();
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0].path(), "/subscriptions/data");
+    assert_eq!(links[1].path(), "/subscriptions/erase");
+    (links[0].clone(), links[1].clone())
+}
+
+async fn count(app: &TestApp, query: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(query)
+        .bind(EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_download_link_returns_everything_we_hold_as_json() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let subscriber_id = seed_subscriber(&app).await;
+    let (download, _) = request_links(&app, 2).await;
+
+    let response = reqwest::get(download).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    assert!(
+        response.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .starts_with("attachment")
+    );
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["id"], subscriber_id.to_string());
+    assert_eq!(data["subscription"]["email"], EMAIL);
+    assert_eq!(data["subscription"]["name"], "le guin");
+    assert_eq!(data["subscription"]["status"], "pending_confirmation");
+    assert_eq!(data["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["deliveries"][0]["title"], "Delivered");
+    assert_eq!(data["deliveries"][0]["status"], "failed");
+    assert_eq!(data["pending_deliveries"][0]["title"], "Queued");
+    assert_eq!(data["consent_records"][0]["kind"], "subscribe");
+}
+
+#[tokio::test]
+async fn unknown_emails_get_the_same_response_and_no_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriber_data_request(EMAIL).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("If we hold data about this email address")
+    );
+}
+
+#[tokio::test]
+async fn forged_and_expired_links_are_rejected() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let subscriber_id = seed_subscriber(&app).await;
+    let expired = SubscriberDataToken::generate(
+        subscriber_id,
+        Utc::now() - chrono::Duration::minutes(1),
+        &app.hmac_secret.0,
+    );
+
+    for token in ["forged", expired.as_ref()] {
+        for path in ["data", "erase"] {
+            let response = reqwest::get(format!(
+                "{}/subscriptions/{path}?token={token}",
+                app.address
+            ))
+            .await
+            .unwrap();
+            assert_eq!(response.status().as_u16(), 401, "{path} {token}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn the_erase_link_anonymises_the_subscriber_and_leaves_a_tombstone() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let subscriber_id = seed_subscriber(&app).await;
+    let (download, erase) = request_links(&app, 2).await;
+
+    // 打开链接只显示确认页面
+    let response = reqwest::get(erase.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        count(&app, "SELECT count(*) FROM subscriptions WHERE email = $1").await,
+        1
+    );
+
+    let response = reqwest::Client::new()
+        .post(erase.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(
+        count(&app, "SELECT count(*) FROM subscriptions WHERE email = $1").await,
+        0
+    );
+    assert_eq!(
+        count(
+            &app,
+            "SELECT count(*) FROM issue_delivery_queue WHERE subscriber_email = $1"
+        )
+        .await,
+        0
+    );
+    assert_eq!(
+        count(
+            &app,
+            "SELECT count(*) FROM issue_delivery_log WHERE subscriber_email = $1 OR error_message LIKE '%' || $1 || '%'"
+        )
+        .await,
+        0
+    );
+    let tokens = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM subscription_tokens WHERE subscription_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tokens, 0);
+    let consents = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM subscription_consents WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(consents, 0);
+    let log = sqlx::query!("SELECT subscriber_email, status FROM issue_delivery_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(log.subscriber_email, format!("erased:{subscriber_id}"));
+    assert_eq!(log.status, "failed");
+    let tombstone = sqlx::query!(
+        "SELECT requested_by, consents_erased FROM subscriber_erasures WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tombstone.requested_by, "subscriber");
+    assert_eq!(tombstone.consents_erased, 1);
+    let audit =
+        sqlx::query_scalar!("SELECT target FROM audit_log WHERE action = 'subscriber.erase'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(audit, Some(subscriber_id.to_string()));
+
+    // 重复提交不报错,下载链接不再返回数据
+    let response = reqwest::Client::new().post(erase).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let response = reqwest::get(download).await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn admins_can_export_and_erase_a_subscriber() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let subscriber_id = seed_subscriber(&app).await;
+    app.login().await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers/{subscriber_id}/data",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], EMAIL);
+
+    let erase_path = format!("subscribers/{subscriber_id}/erase");
+    let response = app
+        .post_admin_json(&erase_path, &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+    let requested_by = sqlx::query_scalar!(
+        "SELECT requested_by FROM subscriber_erasures WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(requested_by, "admin");
+    assert_eq!(
+        count(&app, "SELECT count(*) FROM subscriptions WHERE email = $1").await,
+        0
+    );
+
+    let response = app
+        .post_admin_json(&erase_path, &serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn viewers_cannot_export_or_erase_subscribers() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    let subscriber_id = seed_subscriber(&app).await;
+    app.login().await;
+    app.set_test_user_role("viewer").await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/subscribers/{subscriber_id}/data",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app
+        .post_admin_json(
+            &format!("subscribers/{subscriber_id}/erase"),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -3,6 +3,10 @@ use wiremock::{
     matchers::{method, path},
 };
 
+use zero2prod::routes::{
+    subscriptions::hash_subscription_token, subscriptions_unsubscribe::unsubscribe_link,
+};
+
 use crate::helpers::spawn_app;
 
 // 替换
@@ -135,7 +139,7 @@ async fn subscribe_send_a_confirmation_email_with_a_link() {
     app.post_subscriptions(body.into()).await;
 
     // 获取第一个被截取的请求
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &app.wait_for_email(1).await;
     let confirmation = app.get_confirmation_links(email_request);
     assert_eq!(confirmation.html, confirmation.plain_text);
 }
@@ -154,3 +158,185 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let first_link = app
+        .get_confirmation_links(&app.wait_for_email(1).await)
+        .html;
+    let second_link = app
+        .get_confirmation_links(&app.wait_for_email(2).await)
+        .html;
+    assert_ne!(first_link, second_link);
+    // 第二封邮件里的链接可以完成确认
+    reqwest::get(second_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_confirming_is_indistinguishable_from_a_new_signup() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let new_signup = app.post_subscriptions(body.into()).await;
+    let new_signup = (new_signup.status(), new_signup.text().await.unwrap());
+    let email_request = &app.wait_for_email(1).await;
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let repeat_signup = app.post_subscriptions(body.into()).await;
+    let repeat_signup = (repeat_signup.status(), repeat_signup.text().await.unwrap());
+
+    assert_eq!(new_signup, repeat_signup);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn a_failing_email_provider_does_not_reveal_who_is_subscribed() {
+    let app = spawn_app().await;
+    let confirmed = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let new = "name=deng%20xin&email=994386508%40qq.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(confirmed.into()).await;
+    reqwest::get(
+        app.get_confirmation_links(&app.wait_for_email(1).await)
+            .html,
+    )
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+    // 之后邮件服务一直失败
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let new_signup = app.post_subscriptions(new.into()).await;
+    let new_signup = (new_signup.status(), new_signup.text().await.unwrap());
+    let repeat_signup = app.post_subscriptions(confirmed.into()).await;
+    let repeat_signup = (repeat_signup.status(), repeat_signup.text().await.unwrap());
+
+    assert_eq!(new_signup.0.as_u16(), 200);
+    assert_eq!(new_signup, repeat_signup);
+    // 新订阅确实尝试发送了邮件,只是失败没有体现在响应上
+    app.wait_for_email(2).await;
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_requires_a_new_confirmation() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let mut unsubscribe = reqwest::Url::parse(&unsubscribe_link(
+        &app.base_url,
+        &app.hmac_secret,
+        subscriber_id,
+    ))
+    .unwrap();
+    unsubscribe.set_port(Some(app.prot)).unwrap();
+    reqwest::Client::new()
+        .post(unsubscribe)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "pending_confirmation");
+    app.wait_for_email(2).await;
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let email_request = &app.wait_for_email(1).await;
+    let link = app.get_confirmation_links(email_request).html;
+    let (_, token) = link
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap();
+    let stored = sqlx::query_scalar!("SELECT subscription_token_hash FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, token);
+    assert_eq!(stored, hash_subscription_token(&token));
+    // 迁移脚本用 pgcrypto 处理已有的令牌,两边的结果必须一致
+    let migrated = sqlx::query_scalar!(
+        r#"SELECT encode(digest($1, 'sha3-256'), 'hex') as "hash!""#,
+        token.as_ref()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, migrated);
+}
@@ -10,7 +10,9 @@ use wiremock::{
     matchers::{method, path},
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::routes::subscriptions_confirm::delete_expired_tokens;
+
+use crate::helpers::{TestApp, create_unconfirmed_subscriber, spawn_app};
 
 #[tokio::test]
 async fn confirmation_without_token_are_rejected_with_a_400() {
@@ -35,9 +37,9 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
     app.post_subscriptions(body.into()).await;
 
-    let emial_request = &app.email_server.received_requests().await.unwrap()[0];
+    let emial_request = &app.wait_for_email(1).await;
 
-    let confirmation: crate::helpers::ConfirmationLink = app.get_confirmation_links(&emial_request);
+    let confirmation: crate::helpers::ConfirmationLink = app.get_confirmation_links(emial_request);
 
     assert_eq!(confirmation.html.host_str().unwrap(), "127.0.0.1");
 
@@ -59,7 +61,7 @@ async fn clicking_on_the_confiration_link_confirms_a_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &app.wait_for_email(1).await;
     let confirmation_link = app.get_confirmation_links(email_request);
 
     reqwest::get(confirmation_link.html)
@@ -77,3 +79,106 @@ async fn clicking_on_the_confiration_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "clooe");
     assert_eq!(saved.status, "confirmed")
 }
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+
+    let first = reqwest::get(confirmation_link.html.clone()).await.unwrap();
+    let second = reqwest::get(confirmation_link.html).await.unwrap();
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 401);
+    let tokens = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, 0);
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected_with_an_offer_to_resend() {
+    let app = spawn_app().await;
+    let confirmation_link = create_unconfirmed_subscriber(&app).await;
+    expire_all_tokens(&app).await;
+
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("/subscriptions/confirm/resend?subscription_token=")
+    );
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn resending_from_an_expired_link_sends_a_fresh_confirmation_email() {
+    let app = spawn_app().await;
+    let old_link = create_unconfirmed_subscriber(&app).await;
+    expire_all_tokens(&app).await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let mut resend_url = old_link.html.clone();
+    resend_url.set_path("/subscriptions/confirm/resend");
+    let response = reqwest::Client::new()
+        .post(resend_url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_link = app.get_confirmation_links(&email_request);
+    assert_eq!(
+        reqwest::get(old_link.html).await.unwrap().status().as_u16(),
+        401
+    );
+    assert_eq!(
+        reqwest::get(new_link.html).await.unwrap().status().as_u16(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn the_cleanup_job_only_purges_expired_tokens() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    expire_all_tokens(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+
+    let n_deleted = delete_expired_tokens(&app.db_pool).await.unwrap();
+
+    assert_eq!(n_deleted, 1);
+    let remaining = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining, 1);
+}
+
+async fn expire_all_tokens(app: &TestApp) {
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
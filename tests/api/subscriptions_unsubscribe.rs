@@ -0,0 +1,217 @@
+use wiremock::{
+    Mock,
+    matchers::{method, path},
+};
+use zero2prod::routes::subscriptions_unsubscribe::unsubscribe_link;
+
+use crate::helpers::{PostmarkBatchResponder, TestApp, create_confirmed_subscriber, spawn_app};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "newsLetter title",
+        "content": {
+            "text": "NewsLetter body as plain text",
+            "html": "<p>NewsLetter body as html</p>"
+        }
+    })
+}
+
+/// 发布一期 newsletter 并投递,返回 Postmark 收到的唯一一封邮件
+async fn publish_and_capture_message(app: &TestApp) -> serde_json::Value {
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let mut messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(messages.len(), 1);
+    messages.pop().unwrap()
+}
+
+fn header<'a>(message: &'a serde_json::Value, name: &str) -> &'a str {
+    message["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["Name"] == name)
+        .and_then(|h| h["Value"].as_str())
+        .unwrap()
+}
+
+/// 从 `List-Unsubscribe` 头里取出链接,并换成测试应用的端口
+fn unsubscribe_url(app: &TestApp, message: &serde_json::Value) -> reqwest::Url {
+    let value = header(message, "List-Unsubscribe");
+    let mut url = reqwest::Url::parse(value.trim_matches(['<', '>'])).unwrap();
+    url.set_port(Some(app.prot)).unwrap();
+    url
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn newsletters_carry_one_click_unsubscribe_headers_and_a_footer_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let message = publish_and_capture_message(&app).await;
+
+    assert_eq!(
+        header(&message, "List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+    let link = header(&message, "List-Unsubscribe").trim_matches(['<', '>']);
+    assert!(link.contains("/subscriptions/unsubscribe?token="));
+    assert!(message["TextBody"].as_str().unwrap().contains(link));
+    assert!(message["HtmlBody"].as_str().unwrap().contains(link));
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_stops_future_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let message = publish_and_capture_message(&app).await;
+
+    // RFC 8058 规定的请求格式
+    let response = reqwest::Client::new()
+        .post(unsubscribe_url(&app, &message))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+
+    Mock::given(path("/email/batch"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn opening_the_unsubscribe_link_does_not_unsubscribe() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let message = publish_and_capture_message(&app).await;
+
+    let response = reqwest::get(unsubscribe_url(&app, &message)).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(r#"method="post""#));
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_tampered_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+    let url = format!(
+        "{}/subscriptions/unsubscribe?token=not-a-valid-token",
+        app.address
+    );
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unsubscribe_without_a_token_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_who_unsubscribe_after_publishing_are_skipped() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let mut url = reqwest::Url::parse(&unsubscribe_link(
+        &app.base_url,
+        &app.hmac_secret,
+        subscriber_id,
+    ))
+    .unwrap();
+    url.set_port(Some(app.prot)).unwrap();
+    reqwest::Client::new()
+        .post(url)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let status = sqlx::query_scalar!("SELECT status FROM issue_delivery_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "skipped");
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_resubscribe() {
+    let app = spawn_app().await;
+    let confirmation_link = crate::helpers::create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_link.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let message = publish_and_capture_message(&app).await;
+    reqwest::Client::new()
+        .post(unsubscribe_url(&app, &message))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    reqwest::get(confirmation_link.html).await.unwrap();
+
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
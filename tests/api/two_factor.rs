@@ -0,0 +1,198 @@
+use secrecy::SecretString;
+use zero2prod::authentication::{totp_code, unix_now};
+
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+
+/// 当前时间步之后的验证码
+/// 绑定时已经用掉了当前时间步,登录时用下一个时间步才不会被当作重放
+fn next_code(secret: &SecretString) -> String {
+    totp_code(secret, unix_now().unwrap() + 30).unwrap()
+}
+
+/// 登录后完成 TOTP 绑定,返回密钥和恢复码
+async fn enable_totp(app: &TestApp) -> (SecretString, Vec<String>) {
+    app.login().await;
+    let response = app
+        .post_admin_json("totp", &serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+    let enrolment: serde_json::Value = response.json().await.unwrap();
+    let secret = SecretString::from(enrolment["secret"].as_str().unwrap());
+
+    let code = totp_code(&secret, unix_now().unwrap()).unwrap();
+    let response = app
+        .post_admin_json("totp/confirm", &serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let recovery_codes = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c.as_str().unwrap().to_owned())
+        .collect();
+    app.post_logout().await;
+    (secret, recovery_codes)
+}
+
+async fn login_with_code(app: &TestApp, code: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+        "totp_code": code
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn enrolment_returns_a_provisioning_uri() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app.post_admin_json("totp", &serde_json::json!({})).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let enrolment: serde_json::Value = response.json().await.unwrap();
+    let uri = enrolment["provisioning_uri"].as_str().unwrap();
+    assert!(uri.starts_with(&format!(
+        "otpauth://totp/zero2prod:{}",
+        app.test_user.username
+    )));
+    assert!(uri.contains(&format!("secret={}", enrolment["secret"].as_str().unwrap())));
+}
+
+#[tokio::test]
+async fn an_unconfirmed_enrolment_does_not_change_login() {
+    let app = spawn_app().await;
+    app.login().await;
+    app.post_admin_json("totp", &serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+    let response = app
+        .post_admin_json("totp/confirm", &serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    app.post_logout().await;
+
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn login_requires_the_second_factor_once_enabled() {
+    let app = spawn_app().await;
+    let (secret, recovery_codes) = enable_totp(&app).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    let response = app.login().await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "A one-time code is required."
+    );
+
+    let response = login_with_code(&app, "123456").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = login_with_code(&app, &next_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_one_time_code_cannot_be_replayed() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+    let code = next_code(&secret);
+    assert_is_redirect_to(&login_with_code(&app, &code).await, "/admin/dashboard");
+    app.post_logout().await;
+
+    let response = login_with_code(&app, &code).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_recovery_code_works_exactly_once() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_totp(&app).await;
+
+    let response = login_with_code(&app, &recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    let response = login_with_code(&app, &recovery_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = login_with_code(&app, &recovery_codes[1]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn basic_auth_is_rejected_once_the_second_factor_is_enabled() {
+    let app = spawn_app().await;
+    enable_totp(&app).await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "newsLetter title",
+            "content": {"text": "plain text", "html": "<p>html</p>"}
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn disabling_the_second_factor_restores_password_only_login() {
+    let app = spawn_app().await;
+    let (secret, recovery_codes) = enable_totp(&app).await;
+    login_with_code(&app, &recovery_codes[0]).await;
+
+    let response = app
+        .post_admin_json("totp/disable", &serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_admin_json(
+            "totp/disable",
+            &serde_json::json!({ "code": next_code(&secret) }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+    app.post_logout().await;
+
+    assert_is_redirect_to(&app.login().await, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn enrolling_twice_is_a_conflict() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_totp(&app).await;
+    login_with_code(&app, &recovery_codes[0]).await;
+
+    let response = app.post_admin_json("totp", &serde_json::json!({})).await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn api_tokens_cannot_manage_the_second_factor() {
+    let app = spawn_app().await;
+    app.login().await;
+    let created: serde_json::Value = app
+        .post_api_tokens(&serde_json::json!({"name": "ci", "scopes": ["newsletters:publish"]}))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/totp", app.address))
+        .bearer_auth(created["token"].as_str().unwrap())
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -0,0 +1,214 @@
+use secrecy::SecretString;
+use uuid::Uuid;
+use zero2prod::{
+    authentication::{
+        Credentials, Role, UserAdminError, create_user, delete_user, list_users, reset_password,
+        set_email, set_role, validate_credentials,
+    },
+    domain::{Password, SubscriberEmail},
+};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+fn password(value: &str) -> Password {
+    Password::parse(SecretString::from(value)).unwrap()
+}
+
+#[tokio::test]
+async fn a_created_user_can_log_in() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+
+    create_user(
+        &username,
+        Role::Viewer,
+        None,
+        password("a-long-enough-password"),
+        app.password_hashing,
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &username,
+            "password": "a-long-enough-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn creating_a_duplicate_username_fails() {
+    let app = spawn_app().await;
+
+    let outcome = create_user(
+        &app.test_user.username,
+        Role::Owner,
+        None,
+        password("a-long-enough-password"),
+        app.password_hashing,
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(matches!(outcome, Err(UserAdminError::DuplicateUsername(_))));
+}
+
+#[tokio::test]
+async fn list_returns_all_users_ordered_by_username() {
+    let app = spawn_app().await;
+    for username in ["zoe", "adam"] {
+        create_user(
+            username,
+            Role::Editor,
+            None,
+            password("a-long-enough-password"),
+            app.password_hashing,
+            &app.db_pool,
+        )
+        .await
+        .unwrap();
+    }
+
+    let users = list_users(&app.db_pool).await.unwrap();
+
+    let mut expected = vec![
+        "adam".to_string(),
+        "zoe".to_string(),
+        app.test_user.username.clone(),
+    ];
+    expected.sort();
+    let usernames: Vec<_> = users.into_iter().map(|u| u.username).collect();
+    assert_eq!(usernames, expected);
+}
+
+#[tokio::test]
+async fn a_deleted_user_loses_access() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    delete_user(&app.test_user.username, &app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(list_users(&app.db_pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn deleting_an_unknown_user_fails() {
+    let app = spawn_app().await;
+
+    let outcome = delete_user("nobody", &app.db_pool).await;
+
+    assert!(matches!(outcome, Err(UserAdminError::UnknownUsername(_))));
+}
+
+#[tokio::test]
+async fn reset_password_replaces_the_password_and_logs_the_user_out() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    reset_password(
+        &app.test_user.username,
+        password("a-brand-new-password"),
+        app.password_hashing,
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 401);
+    let old = Credentials {
+        username: app.test_user.username.clone(),
+        password: SecretString::from(app.test_user.password.clone()),
+        second_factor: None,
+    };
+    assert!(
+        validate_credentials(
+            old,
+            None,
+            app.password_hashing,
+            &app.login_throttling,
+            &app.db_pool
+        )
+        .await
+        .is_err()
+    );
+    let new = Credentials {
+        username: app.test_user.username.clone(),
+        password: SecretString::from("a-brand-new-password"),
+        second_factor: None,
+    };
+    assert!(
+        validate_credentials(
+            new,
+            None,
+            app.password_hashing,
+            &app.login_throttling,
+            &app.db_pool
+        )
+        .await
+        .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn reset_password_for_an_unknown_user_fails() {
+    let app = spawn_app().await;
+
+    let outcome = reset_password(
+        "nobody",
+        password("a-brand-new-password"),
+        app.password_hashing,
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(matches!(outcome, Err(UserAdminError::UnknownUsername(_))));
+}
+
+#[tokio::test]
+async fn set_role_changes_the_role_of_an_existing_user() {
+    let app = spawn_app().await;
+
+    set_role(&app.test_user.username, Role::Viewer, &app.db_pool)
+        .await
+        .unwrap();
+
+    let users = list_users(&app.db_pool).await.unwrap();
+    assert_eq!(users[0].role, Role::Viewer);
+    let outcome = set_role("nobody", Role::Owner, &app.db_pool).await;
+    assert!(matches!(outcome, Err(UserAdminError::UnknownUsername(_))));
+}
+
+#[tokio::test]
+async fn two_users_cannot_share_an_email_address() {
+    let app = spawn_app().await;
+    let email = SubscriberEmail::parse("owner@example.com".into()).unwrap();
+    set_email(&app.test_user.username, Some(&email), &app.db_pool)
+        .await
+        .unwrap();
+
+    let outcome = create_user(
+        "someone-else",
+        Role::Editor,
+        Some(&email),
+        password("a-long-enough-password"),
+        app.password_hashing,
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(matches!(outcome, Err(UserAdminError::DuplicateEmail(_))));
+    // 清除后可以被其他用户使用
+    set_email(&app.test_user.username, None, &app.db_pool)
+        .await
+        .unwrap();
+    let outcome = set_email("nobody", Some(&email), &app.db_pool).await;
+    assert!(matches!(outcome, Err(UserAdminError::UnknownUsername(_))));
+}